@@ -23,6 +23,8 @@ impl ApiUserAccess {
     }
 }
 
+// Access levels order by their numeric level, not by the order of the variants
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for ApiUserAccess {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.to_level().cmp(&other.to_level()))
@@ -1,5 +1,7 @@
 pub mod active_enums;
 pub mod api_user;
 pub mod client;
+pub mod password_history;
 pub mod refresh_token;
+pub mod signing_key;
 pub mod user;
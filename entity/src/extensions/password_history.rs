@@ -0,0 +1,7 @@
+use crate::{models::password_history, utils::verify_password_hash};
+
+impl password_history::Model {
+    pub fn verify_password(&self, password: &str) -> bool {
+        verify_password_hash(password, &self.password_hash)
+    }
+}
@@ -0,0 +1,16 @@
+use crate::{sea_orm_active_enums::SigningKeyStatus, signing_key};
+use sea_orm::{sqlx::types::chrono::Utc, ColumnTrait, Condition, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+
+impl signing_key::Entity {
+    /// Keys which can still verify tokens: everything except retired keys past their grace period
+    pub async fn find_verifiable<C: ConnectionTrait>(db: &C) -> Result<Vec<signing_key::Model>, DbErr> {
+        Self::find()
+            .filter(
+                Condition::any()
+                    .add(signing_key::Column::Status.ne(SigningKeyStatus::Retired))
+                    .add(signing_key::Column::ExpiresAt.gt(Utc::now())),
+            )
+            .all(db)
+            .await
+    }
+}
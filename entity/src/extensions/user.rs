@@ -1,10 +1,10 @@
-use crate::models::user;
+use crate::{models::user, utils::verify_password_hash};
 use sea_orm::{prelude::Uuid, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
 
 impl user::Model {
     pub fn verify_password(&self, password: &str) -> bool {
         match self.password_hash {
-            Some(ref hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Some(ref hash) => verify_password_hash(password, hash),
             None => false,
         }
     }
@@ -73,17 +73,6 @@ impl ActiveModelBehavior for client::ActiveModel {
             }
         }
 
-        // // Check refresh_token_reuse_limit
-        if let ActiveValue::Set(refresh_token_reuse_limit) = self.refresh_token_reuse_limit {
-            if refresh_token_reuse_limit > realm.refresh_token_reuse_limit {
-                return Err(DbErr::Custom(format!(
-                    "Client refresh_token_reuse_limit ({}) exceeds the realm's limit ({})",
-                    self.refresh_token_reuse_limit.as_ref(),
-                    &realm.refresh_token_reuse_limit
-                )));
-            }
-        }
-
         Ok(self)
     }
 }
@@ -1,5 +1,6 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.0
 
+use super::sea_orm_active_enums::ClientType;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -12,19 +13,39 @@ pub struct Model {
     pub two_factor_enabled_at: Option<DateTimeWithTimeZone>,
     pub max_concurrent_sessions: i32,
     pub session_lifetime: i32,
+    pub session_idle_timeout: i32,
+    pub access_token_lifetime: i32,
     pub use_refresh_token: bool,
     pub refresh_token_lifetime: i32,
-    pub refresh_token_reuse_limit: i32,
     pub locked_at: Option<DateTimeWithTimeZone>,
     pub realm_id: Uuid,
+    pub client_type: ClientType,
+    pub secret: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub use_stateful_access_token: bool,
+    pub audiences: Vec<String>,
+    pub token_exchange_audiences: Vec<Uuid>,
+    pub require_dpop: bool,
+    pub allow_self_registration: bool,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub registration_template: Option<Json>,
+    pub registration_email_domains: Vec<String>,
+    pub require_registration_approval: bool,
+    pub require_verified_email: bool,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::authorization_code::Entity")]
+    AuthorizationCode,
     #[sea_orm(has_many = "super::api_user::Entity")]
     ApiUser,
+    #[sea_orm(has_many = "super::claim_mapper::Entity")]
+    ClaimMapper,
+    #[sea_orm(has_many = "super::device_code::Entity")]
+    DeviceCode,
     #[sea_orm(
         belongs_to = "super::realm::Entity",
         from = "Column::RealmId",
@@ -37,8 +58,18 @@ pub enum Relation {
     RefreshToken,
     #[sea_orm(has_many = "super::resource_group::Entity")]
     ResourceGroup,
+    #[sea_orm(has_many = "super::revoked_session::Entity")]
+    RevokedSession,
     #[sea_orm(has_many = "super::session::Entity")]
     Session,
+    #[sea_orm(has_many = "super::used_webauthn_challenge::Entity")]
+    UsedWebauthnChallenge,
+}
+
+impl Related<super::authorization_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AuthorizationCode.def()
+    }
 }
 
 impl Related<super::api_user::Entity> for Entity {
@@ -47,6 +78,18 @@ impl Related<super::api_user::Entity> for Entity {
     }
 }
 
+impl Related<super::claim_mapper::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ClaimMapper.def()
+    }
+}
+
+impl Related<super::device_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeviceCode.def()
+    }
+}
+
 impl Related<super::realm::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Realm.def()
@@ -65,8 +108,20 @@ impl Related<super::resource_group::Entity> for Entity {
     }
 }
 
+impl Related<super::revoked_session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RevokedSession.def()
+    }
+}
+
 impl Related<super::session::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Session.def()
     }
 }
+
+impl Related<super::used_webauthn_challenge::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UsedWebauthnChallenge.def()
+    }
+}
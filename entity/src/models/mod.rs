@@ -3,11 +3,21 @@
 pub mod prelude;
 
 pub mod api_user;
+pub mod authorization_code;
+pub mod claim_mapper;
 pub mod client;
+pub mod device_code;
+pub mod password_history;
 pub mod realm;
+pub mod recovery_code;
 pub mod refresh_token;
 pub mod resource;
 pub mod resource_group;
+pub mod revoked_session;
 pub mod sea_orm_active_enums;
 pub mod session;
+pub mod signing_key;
+pub mod totp_secret;
+pub mod used_webauthn_challenge;
 pub mod user;
+pub mod webauthn_credential;
@@ -1,10 +1,20 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.0
 
 pub use super::api_user::Entity as ApiUser;
+pub use super::authorization_code::Entity as AuthorizationCode;
+pub use super::claim_mapper::Entity as ClaimMapper;
 pub use super::client::Entity as Client;
+pub use super::device_code::Entity as DeviceCode;
+pub use super::password_history::Entity as PasswordHistory;
 pub use super::realm::Entity as Realm;
+pub use super::recovery_code::Entity as RecoveryCode;
 pub use super::refresh_token::Entity as RefreshToken;
 pub use super::resource::Entity as Resource;
 pub use super::resource_group::Entity as ResourceGroup;
+pub use super::revoked_session::Entity as RevokedSession;
 pub use super::session::Entity as Session;
+pub use super::signing_key::Entity as SigningKey;
+pub use super::totp_secret::Entity as TotpSecret;
+pub use super::used_webauthn_challenge::Entity as UsedWebauthnChallenge;
 pub use super::user::Entity as User;
+pub use super::webauthn_credential::Entity as WebauthnCredential;
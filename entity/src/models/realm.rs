@@ -1,5 +1,6 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.0
 
+use super::sea_orm_active_enums::SigningAlgorithm;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -16,7 +17,9 @@ pub struct Model {
     pub session_lifetime: i32,
     pub use_refresh_token: bool,
     pub refresh_token_lifetime: i32,
-    pub refresh_token_reuse_limit: i32,
+    pub signing_algorithm: SigningAlgorithm,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub password_policy: Json,
     pub locked_at: Option<DateTimeWithTimeZone>,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
@@ -24,18 +27,30 @@ pub struct Model {
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::authorization_code::Entity")]
+    AuthorizationCode,
     #[sea_orm(has_many = "super::api_user::Entity")]
     ApiUser,
     #[sea_orm(has_many = "super::client::Entity")]
     Client,
+    #[sea_orm(has_many = "super::device_code::Entity")]
+    DeviceCode,
     #[sea_orm(has_many = "super::refresh_token::Entity")]
     RefreshToken,
     #[sea_orm(has_many = "super::resource_group::Entity")]
     ResourceGroup,
+    #[sea_orm(has_many = "super::signing_key::Entity")]
+    SigningKey,
     #[sea_orm(has_many = "super::user::Entity")]
     User,
 }
 
+impl Related<super::authorization_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AuthorizationCode.def()
+    }
+}
+
 impl Related<super::api_user::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::ApiUser.def()
@@ -48,6 +63,12 @@ impl Related<super::client::Entity> for Entity {
     }
 }
 
+impl Related<super::device_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeviceCode.def()
+    }
+}
+
 impl Related<super::refresh_token::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::RefreshToken.def()
@@ -60,6 +81,12 @@ impl Related<super::resource_group::Entity> for Entity {
     }
 }
 
+impl Related<super::signing_key::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SigningKey.def()
+    }
+}
+
 impl Related<super::user::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::User.def()
@@ -12,6 +12,8 @@ pub struct Model {
     pub client_id: Option<Uuid>,
     pub realm_id: Uuid,
     pub re_used_count: i32,
+    pub family_id: Uuid,
+    pub replaced_by_id: Option<Uuid>,
     pub locked_at: Option<DateTimeWithTimeZone>,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
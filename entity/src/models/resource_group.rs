@@ -14,6 +14,7 @@ pub struct Model {
     pub name: String,
     pub description: Option<String>,
     pub is_default: bool,
+    pub awaiting_approval: bool,
     pub locked_at: Option<DateTimeWithTimeZone>,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
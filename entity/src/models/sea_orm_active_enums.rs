@@ -25,3 +25,48 @@ pub enum ApiUserRole {
     #[sea_orm(string_value = "realm_admin")]
     RealmAdmin,
 }
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "claim_mapper_type")]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimMapperType {
+    #[sea_orm(string_value = "omit")]
+    Omit,
+    #[sea_orm(string_value = "resource")]
+    Resource,
+    #[sea_orm(string_value = "static")]
+    Static,
+    #[sea_orm(string_value = "user_attribute")]
+    UserAttribute,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "client_type")]
+#[serde(rename_all = "snake_case")]
+pub enum ClientType {
+    #[sea_orm(string_value = "confidential")]
+    Confidential,
+    #[sea_orm(string_value = "public")]
+    Public,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "signing_algorithm")]
+pub enum SigningAlgorithm {
+    #[sea_orm(string_value = "EdDSA")]
+    EdDSA,
+    #[sea_orm(string_value = "ES256")]
+    ES256,
+    #[sea_orm(string_value = "HS256")]
+    HS256,
+    #[sea_orm(string_value = "RS256")]
+    RS256,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "signing_key_status")]
+#[serde(rename_all = "snake_case")]
+pub enum SigningKeyStatus {
+    #[sea_orm(string_value = "active")]
+    Active,
+    #[sea_orm(string_value = "next")]
+    Next,
+    #[sea_orm(string_value = "retired")]
+    Retired,
+}
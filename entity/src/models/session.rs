@@ -19,12 +19,16 @@ pub struct Model {
     pub device_type: Option<String>,
     pub country_code: String,
     pub expires: DateTimeWithTimeZone,
+    pub scope: Option<String>,
+    pub dpop_jkt: Option<String>,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::authorization_code::Entity")]
+    AuthorizationCode,
     #[sea_orm(
         belongs_to = "super::client::Entity",
         from = "Column::ClientId",
@@ -51,6 +55,12 @@ pub enum Relation {
     User,
 }
 
+impl Related<super::authorization_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AuthorizationCode.def()
+    }
+}
+
 impl Related<super::client::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Client.def()
@@ -16,8 +16,11 @@ pub struct Model {
     pub phone: Option<String>,
     pub image: Option<String>,
     pub two_factor_enabled_at: Option<DateTimeWithTimeZone>,
+    pub two_factor_failed_attempts: i32,
+    pub two_factor_locked_until: Option<DateTimeWithTimeZone>,
     pub password_hash: Option<String>,
     pub is_temp_password: bool,
+    pub password_changed_at: DateTimeWithTimeZone,
     pub locked_at: Option<DateTimeWithTimeZone>,
     pub realm_id: Uuid,
     pub created_at: DateTimeWithTimeZone,
@@ -26,6 +29,12 @@ pub struct Model {
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::authorization_code::Entity")]
+    AuthorizationCode,
+    #[sea_orm(has_many = "super::device_code::Entity")]
+    DeviceCode,
+    #[sea_orm(has_many = "super::password_history::Entity")]
+    PasswordHistory,
     #[sea_orm(
         belongs_to = "super::realm::Entity",
         from = "Column::RealmId",
@@ -34,12 +43,36 @@ pub enum Relation {
         on_delete = "Cascade"
     )]
     Realm,
+    #[sea_orm(has_many = "super::recovery_code::Entity")]
+    RecoveryCode,
     #[sea_orm(has_many = "super::refresh_token::Entity")]
     RefreshToken,
     #[sea_orm(has_many = "super::resource_group::Entity")]
     ResourceGroup,
     #[sea_orm(has_many = "super::session::Entity")]
     Session,
+    #[sea_orm(has_one = "super::totp_secret::Entity")]
+    TotpSecret,
+    #[sea_orm(has_many = "super::webauthn_credential::Entity")]
+    WebauthnCredential,
+}
+
+impl Related<super::authorization_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AuthorizationCode.def()
+    }
+}
+
+impl Related<super::device_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeviceCode.def()
+    }
+}
+
+impl Related<super::password_history::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PasswordHistory.def()
+    }
 }
 
 impl Related<super::realm::Entity> for Entity {
@@ -48,6 +81,12 @@ impl Related<super::realm::Entity> for Entity {
     }
 }
 
+impl Related<super::recovery_code::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RecoveryCode.def()
+    }
+}
+
 impl Related<super::refresh_token::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::RefreshToken.def()
@@ -65,3 +104,15 @@ impl Related<super::session::Entity> for Entity {
         Relation::Session.def()
     }
 }
+
+impl Related<super::totp_secret::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TotpSecret.def()
+    }
+}
+
+impl Related<super::webauthn_credential::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::WebauthnCredential.def()
+    }
+}
@@ -1,3 +1,7 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordVerifier},
+    Argon2,
+};
 use sea_orm::{
     sqlx::types::chrono::{FixedOffset, Utc},
     DbErr,
@@ -12,3 +16,44 @@ pub fn check_locked_at_constraint(locked_at: &Option<sea_orm::sqlx::types::chron
     }
     Ok(())
 }
+
+/// Checks the password against an Argon2id or bcrypt hash, whichever the hash was made with. Hashes that cannot be read never match.
+pub fn verify_password_hash(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2id$") {
+        // The parameters and salt are read from the hash itself
+        return PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok());
+    }
+    bcrypt::verify(password, hash).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify_password_hash;
+
+    // The example of the Argon2 reference implementation: password "password", salt "somesalt", m=65536, t=2, p=1
+    const REFERENCE_HASH: &str = "$argon2id$v=19$m=65536,t=2,p=1$c29tZXNhbHQ$CTFhFdXPJO1aFaMaO6Mm5c8y7cJHAph8ArZWb2GRPPc";
+
+    #[test]
+    fn verifies_argon2id_reference_hash() {
+        assert!(verify_password_hash("password", REFERENCE_HASH));
+        assert!(!verify_password_hash("Password", REFERENCE_HASH));
+    }
+
+    #[test]
+    fn verifies_bcrypt_hash() {
+        let hash = bcrypt::hash("password", 4).unwrap();
+        assert!(verify_password_hash("password", &hash));
+        assert!(!verify_password_hash("password1", &hash));
+    }
+
+    #[test]
+    fn unreadable_hashes_never_match() {
+        assert!(!verify_password_hash("password", ""));
+        assert!(!verify_password_hash("password", "password"));
+        assert!(!verify_password_hash("password", "$argon2id$v=19$m=65536,t=2,p=1$c29tZXNhbHQ"));
+        assert!(!verify_password_hash(
+            "password",
+            "$argon2i$v=19$m=65536,t=2,p=1$c29tZXNhbHQ$CTFhFdXPJO1aFaMaO6Mm5c8y7cJHAph8ArZWb2GRPPc"
+        ));
+    }
+}
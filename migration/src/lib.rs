@@ -8,6 +8,29 @@ mod m20220101_000005_create_resource_table;
 mod m20220101_000006_create_refresh_token_table;
 mod m20220101_000007_create_api_user_table;
 mod m20220101_000008_create_session_table;
+mod m20220101_000009_add_signing_algorithm_to_realm;
+mod m20220101_000010_create_signing_key_table;
+mod m20220101_000011_add_realm_id_to_signing_key;
+mod m20220101_000012_add_oauth_fields_to_client;
+mod m20220101_000013_create_authorization_code_table;
+mod m20220101_000014_add_family_to_refresh_token;
+mod m20220101_000015_create_revoked_session_table;
+mod m20220101_000016_create_device_code_table;
+mod m20220101_000017_add_scope_to_session;
+mod m20220101_000018_add_audiences_to_client;
+mod m20220101_000019_add_access_token_lifetime_to_client;
+mod m20220101_000020_create_claim_mapper_table;
+mod m20220101_000021_add_dpop_to_client_and_session;
+mod m20220101_000022_add_self_registration_to_client;
+mod m20220101_000023_add_require_verified_email_to_client;
+mod m20220101_000024_create_password_history_table;
+mod m20220101_000025_create_two_factor_tables;
+mod m20220101_000026_create_webauthn_credential_table;
+mod m20220101_000027_drop_refresh_token_reuse_limit;
+mod m20220101_000028_add_two_factor_lockout_to_user;
+mod m20220101_000029_create_used_webauthn_challenge_table;
+mod m20220101_000030_reset_temp_password_of_user;
+mod m20220101_000031_add_token_exchange_audiences_to_client;
 
 pub struct Migrator;
 
@@ -23,6 +46,29 @@ impl MigratorTrait for Migrator {
             Box::new(m20220101_000006_create_refresh_token_table::Migration),
             Box::new(m20220101_000007_create_api_user_table::Migration),
             Box::new(m20220101_000008_create_session_table::Migration),
+            Box::new(m20220101_000009_add_signing_algorithm_to_realm::Migration),
+            Box::new(m20220101_000010_create_signing_key_table::Migration),
+            Box::new(m20220101_000011_add_realm_id_to_signing_key::Migration),
+            Box::new(m20220101_000012_add_oauth_fields_to_client::Migration),
+            Box::new(m20220101_000013_create_authorization_code_table::Migration),
+            Box::new(m20220101_000014_add_family_to_refresh_token::Migration),
+            Box::new(m20220101_000015_create_revoked_session_table::Migration),
+            Box::new(m20220101_000016_create_device_code_table::Migration),
+            Box::new(m20220101_000017_add_scope_to_session::Migration),
+            Box::new(m20220101_000018_add_audiences_to_client::Migration),
+            Box::new(m20220101_000019_add_access_token_lifetime_to_client::Migration),
+            Box::new(m20220101_000020_create_claim_mapper_table::Migration),
+            Box::new(m20220101_000021_add_dpop_to_client_and_session::Migration),
+            Box::new(m20220101_000022_add_self_registration_to_client::Migration),
+            Box::new(m20220101_000023_add_require_verified_email_to_client::Migration),
+            Box::new(m20220101_000024_create_password_history_table::Migration),
+            Box::new(m20220101_000025_create_two_factor_tables::Migration),
+            Box::new(m20220101_000026_create_webauthn_credential_table::Migration),
+            Box::new(m20220101_000027_drop_refresh_token_reuse_limit::Migration),
+            Box::new(m20220101_000028_add_two_factor_lockout_to_user::Migration),
+            Box::new(m20220101_000029_create_used_webauthn_challenge_table::Migration),
+            Box::new(m20220101_000030_reset_temp_password_of_user::Migration),
+            Box::new(m20220101_000031_add_token_exchange_audiences_to_client::Migration),
         ]
     }
 }
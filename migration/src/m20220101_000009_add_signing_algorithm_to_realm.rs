@@ -0,0 +1,52 @@
+use sea_orm::{ActiveEnum, DbBackend, DeriveActiveEnum, EnumIter, Schema};
+use sea_orm_migration::prelude::{extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(DbBackend::Postgres);
+        manager.create_type(schema.create_enum_from_active_enum::<SigningAlgorithm>()).await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realm::Table)
+                    .add_column(
+                        ColumnDef::new(Realm::SigningAlgorithm)
+                            .custom(SigningAlgorithm::name())
+                            .not_null()
+                            .default("HS256"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Realm::Table).drop_column(Realm::SigningAlgorithm).to_owned())
+            .await?;
+        manager.drop_type(Type::drop().name(SigningAlgorithm::name()).to_owned()).await
+    }
+}
+
+#[derive(EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "signing_algorithm")]
+pub enum SigningAlgorithm {
+    #[sea_orm(string_value = "HS256")]
+    HS256,
+    #[sea_orm(string_value = "RS256")]
+    RS256,
+    #[sea_orm(string_value = "ES256")]
+    ES256,
+    #[sea_orm(string_value = "EdDSA")]
+    EdDSA,
+}
+
+#[derive(DeriveIden)]
+pub enum Realm {
+    Table,
+    SigningAlgorithm,
+}
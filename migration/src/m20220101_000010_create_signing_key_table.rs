@@ -0,0 +1,80 @@
+use super::m20220101_000009_add_signing_algorithm_to_realm::SigningAlgorithm;
+use sea_orm::sqlx::types::chrono;
+use sea_orm::{ActiveEnum, DbBackend, DeriveActiveEnum, EnumIter, Schema};
+use sea_orm_migration::prelude::{extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(DbBackend::Postgres);
+        manager.create_type(schema.create_enum_from_active_enum::<SigningKeyStatus>()).await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(SigningKey::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(SigningKey::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(SigningKey::Algorithm).custom(SigningAlgorithm::name()).not_null())
+                    .col(ColumnDef::new(SigningKey::PrivateKey).text().not_null())
+                    .col(ColumnDef::new(SigningKey::Status).custom(SigningKeyStatus::name()).not_null())
+                    .col(ColumnDef::new(SigningKey::ExpiresAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(SigningKey::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .col(
+                        ColumnDef::new(SigningKey::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("signing_key_algorithm_status_idx")
+                    .table(SigningKey::Table)
+                    .col(SigningKey::Algorithm)
+                    .col(SigningKey::Status)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(SigningKey::Table).to_owned()).await?;
+        manager.drop_type(Type::drop().name(SigningKeyStatus::name()).to_owned()).await
+    }
+}
+
+#[derive(EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "signing_key_status")]
+pub enum SigningKeyStatus {
+    #[sea_orm(string_value = "active")]
+    Active,
+    #[sea_orm(string_value = "next")]
+    Next,
+    #[sea_orm(string_value = "retired")]
+    Retired,
+}
+
+#[derive(DeriveIden)]
+pub enum SigningKey {
+    Table,
+    Id,
+    RealmId,
+    Algorithm,
+    PrivateKey,
+    Status,
+    ExpiresAt,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,80 @@
+use super::m20220101_000001_create_realm_table::Realm;
+use super::m20220101_000010_create_signing_key_table::SigningKey;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Keys shared by every realm cannot be attributed to a single one, they get regenerated per realm on startup
+        manager.exec_stmt(Query::delete().from_table(SigningKey::Table).to_owned()).await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SigningKey::Table)
+                    .add_column(ColumnDef::new(SigningKey::RealmId).uuid().not_null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_signing_key_realm_id")
+                            .from_tbl(SigningKey::Table)
+                            .from_col(SigningKey::RealmId)
+                            .to_tbl(Realm::Table)
+                            .to_col(Realm::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(Index::drop().name("signing_key_algorithm_status_idx").table(SigningKey::Table).to_owned())
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("signing_key_realm_id_algorithm_status_idx")
+                    .table(SigningKey::Table)
+                    .col(SigningKey::RealmId)
+                    .col(SigningKey::Algorithm)
+                    .col(SigningKey::Status)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("signing_key_realm_id_algorithm_status_idx")
+                    .table(SigningKey::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SigningKey::Table)
+                    .drop_foreign_key(Alias::new("fk_signing_key_realm_id"))
+                    .drop_column(SigningKey::RealmId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("signing_key_algorithm_status_idx")
+                    .table(SigningKey::Table)
+                    .col(SigningKey::Algorithm)
+                    .col(SigningKey::Status)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,60 @@
+use sea_orm::{ActiveEnum, DbBackend, DeriveActiveEnum, EnumIter, Schema};
+use sea_orm_migration::prelude::{extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(DbBackend::Postgres);
+        manager.create_type(schema.create_enum_from_active_enum::<ClientType>()).await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(ColumnDef::new(Client::Type).custom(ClientType::name()).not_null().default("public"))
+                    .add_column(ColumnDef::new(Client::Secret).string())
+                    .add_column(
+                        ColumnDef::new(Client::RedirectUris)
+                            .array(ColumnType::Text)
+                            .not_null()
+                            .default(Expr::cust("'{}'")),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .drop_column(Client::Type)
+                    .drop_column(Client::Secret)
+                    .drop_column(Client::RedirectUris)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_type(Type::drop().name(ClientType::name()).to_owned()).await
+    }
+}
+
+#[derive(EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "client_type")]
+pub enum ClientType {
+    #[sea_orm(string_value = "public")]
+    Public,
+    #[sea_orm(string_value = "confidential")]
+    Confidential,
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    #[sea_orm(iden = "client_type")]
+    Type,
+    Secret,
+    RedirectUris,
+}
@@ -0,0 +1,98 @@
+use super::m20220101_000001_create_realm_table::Realm;
+use super::m20220101_000002_create_client_table::Client;
+use super::m20220101_000003_create_user_table::User;
+use super::m20220101_000008_create_session_table::Session;
+use sea_orm::sqlx::types::chrono;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuthorizationCode::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(AuthorizationCode::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(AuthorizationCode::Code).string().not_null().unique_key())
+                    .col(ColumnDef::new(AuthorizationCode::RealmId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_authorization_code_realm_id")
+                            .from(AuthorizationCode::Table, AuthorizationCode::RealmId)
+                            .to(Realm::Table, Realm::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(AuthorizationCode::ClientId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_authorization_code_client_id")
+                            .from(AuthorizationCode::Table, AuthorizationCode::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(AuthorizationCode::UserId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_authorization_code_user_id")
+                            .from(AuthorizationCode::Table, AuthorizationCode::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(AuthorizationCode::SessionId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_authorization_code_session_id")
+                            .from(AuthorizationCode::Table, AuthorizationCode::SessionId)
+                            .to(Session::Table, Session::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(AuthorizationCode::RedirectUri).text().not_null())
+                    .col(ColumnDef::new(AuthorizationCode::Scope).string())
+                    .col(ColumnDef::new(AuthorizationCode::Nonce).string())
+                    .col(ColumnDef::new(AuthorizationCode::CodeChallenge).string())
+                    .col(ColumnDef::new(AuthorizationCode::ExpiresAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(AuthorizationCode::UsedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(AuthorizationCode::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .col(
+                        ColumnDef::new(AuthorizationCode::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(AuthorizationCode::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum AuthorizationCode {
+    Table,
+    Id,
+    Code,
+    RealmId,
+    ClientId,
+    UserId,
+    SessionId,
+    RedirectUri,
+    Scope,
+    Nonce,
+    CodeChallenge,
+    ExpiresAt,
+    UsedAt,
+    CreatedAt,
+    UpdatedAt,
+}
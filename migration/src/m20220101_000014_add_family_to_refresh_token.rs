@@ -0,0 +1,71 @@
+use super::m20220101_000006_create_refresh_token_table::RefreshToken;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RefreshToken::Table)
+                    .add_column(ColumnDef::new(RefreshTokenFamily::FamilyId).uuid())
+                    .add_column(ColumnDef::new(RefreshTokenFamily::ReplacedById).uuid())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Every existing token starts its own family
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(RefreshToken::Table)
+                    .value(RefreshTokenFamily::FamilyId, Expr::col(RefreshToken::Id))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RefreshToken::Table)
+                    .modify_column(ColumnDef::new(RefreshTokenFamily::FamilyId).uuid().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("refresh_token_family_id_idx")
+                    .table(RefreshToken::Table)
+                    .col(RefreshTokenFamily::FamilyId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("refresh_token_family_id_idx").table(RefreshToken::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RefreshToken::Table)
+                    .drop_column(RefreshTokenFamily::FamilyId)
+                    .drop_column(RefreshTokenFamily::ReplacedById)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RefreshTokenFamily {
+    FamilyId,
+    ReplacedById,
+}
@@ -0,0 +1,72 @@
+use super::m20220101_000002_create_client_table::Client;
+use sea_orm::sqlx::types::chrono;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(ColumnDef::new(StatefulClient::UseStatefulAccessToken).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(RevokedSession::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(RevokedSession::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(RevokedSession::ClientId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_revoked_session_client_id")
+                            .from(RevokedSession::Table, RevokedSession::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(RevokedSession::ExpiresAt).timestamp_with_time_zone().not_null())
+                    .col(
+                        ColumnDef::new(RevokedSession::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(RevokedSession::Table).to_owned()).await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .drop_column(StatefulClient::UseStatefulAccessToken)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum StatefulClient {
+    UseStatefulAccessToken,
+}
+
+#[derive(DeriveIden)]
+enum RevokedSession {
+    Table,
+    Id,
+    ClientId,
+    ExpiresAt,
+    CreatedAt,
+}
@@ -0,0 +1,89 @@
+use super::m20220101_000001_create_realm_table::Realm;
+use super::m20220101_000002_create_client_table::Client;
+use super::m20220101_000003_create_user_table::User;
+use sea_orm::sqlx::types::chrono;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DeviceCode::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(DeviceCode::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(DeviceCode::Code).string().not_null().unique_key())
+                    .col(ColumnDef::new(DeviceCode::UserCode).string().not_null().unique_key())
+                    .col(ColumnDef::new(DeviceCode::RealmId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_device_code_realm_id")
+                            .from(DeviceCode::Table, DeviceCode::RealmId)
+                            .to(Realm::Table, Realm::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(DeviceCode::ClientId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_device_code_client_id")
+                            .from(DeviceCode::Table, DeviceCode::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(DeviceCode::UserId).uuid())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_device_code_user_id")
+                            .from(DeviceCode::Table, DeviceCode::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(DeviceCode::Scope).string())
+                    .col(ColumnDef::new(DeviceCode::ApprovedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(DeviceCode::DeniedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(DeviceCode::LastPolledAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(DeviceCode::ExpiresAt).timestamp_with_time_zone().not_null())
+                    .col(
+                        ColumnDef::new(DeviceCode::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .col(
+                        ColumnDef::new(DeviceCode::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(DeviceCode::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum DeviceCode {
+    Table,
+    Id,
+    #[sea_orm(iden = "device_code")]
+    Code,
+    UserCode,
+    RealmId,
+    ClientId,
+    UserId,
+    Scope,
+    ApprovedAt,
+    DeniedAt,
+    LastPolledAt,
+    ExpiresAt,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,30 @@
+use super::m20220101_000008_create_session_table::Session;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Session::Table)
+                    .add_column(ColumnDef::new(SessionScope::Scope).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Session::Table).drop_column(SessionScope::Scope).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SessionScope {
+    Scope,
+}
@@ -0,0 +1,35 @@
+use super::m20220101_000002_create_client_table::Client;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(
+                        ColumnDef::new(ClientAudience::Audiences)
+                            .array(ColumnType::Text)
+                            .not_null()
+                            .default(Expr::cust("'{}'")),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Client::Table).drop_column(ClientAudience::Audiences).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ClientAudience {
+    Audiences,
+}
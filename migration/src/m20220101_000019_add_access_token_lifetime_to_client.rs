@@ -0,0 +1,91 @@
+use super::{m20220101_000001_create_realm_table::Realm, m20220101_000002_create_client_table::Client};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(ColumnDef::new(ClientLifetime::AccessTokenLifetime).integer().not_null().default(300))
+                    .add_column(ColumnDef::new(ClientLifetime::SessionIdleTimeout).integer().not_null().default(1800))
+                    .modify_column(ColumnDef::new(Client::SessionLifetime).integer().not_null().default(3600))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realm::Table)
+                    .modify_column(ColumnDef::new(Realm::SessionLifetime).integer().not_null().default(86400))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Access tokens used to live as long as the session, so existing clients keep that lifetime for them
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Client::Table)
+                    .value(ClientLifetime::AccessTokenLifetime, Expr::col(Client::SessionLifetime))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Refreshing used to start a new session, so sessions now have to last at least as long as a refresh token to not cut users off.
+        // Realms cap the lifetime of their clients and never allow shorter refresh tokens than their clients, so they can follow the same rule.
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Realm::Table)
+                    .value(
+                        Realm::SessionLifetime,
+                        Expr::cust("GREATEST(\"session_lifetime\", \"refresh_token_lifetime\")"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Client::Table)
+                    .value(
+                        Client::SessionLifetime,
+                        Expr::cust("GREATEST(\"session_lifetime\", \"refresh_token_lifetime\")"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realm::Table)
+                    .modify_column(ColumnDef::new(Realm::SessionLifetime).integer().not_null().default(300))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .drop_column(ClientLifetime::AccessTokenLifetime)
+                    .drop_column(ClientLifetime::SessionIdleTimeout)
+                    .modify_column(ColumnDef::new(Client::SessionLifetime).integer().not_null().default(300))
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ClientLifetime {
+    AccessTokenLifetime,
+    SessionIdleTimeout,
+}
@@ -0,0 +1,87 @@
+use super::m20220101_000002_create_client_table::Client;
+use sea_orm::{sqlx::types::chrono, ActiveEnum, DbBackend, DeriveActiveEnum, EnumIter, Schema};
+use sea_orm_migration::prelude::{extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(DbBackend::Postgres);
+        manager.create_type(schema.create_enum_from_active_enum::<ClaimMapperType>()).await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(ClaimMapper::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(ClaimMapper::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(ClaimMapper::ClientId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_claim_mapper_client_id")
+                            .from(ClaimMapper::Table, ClaimMapper::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(ClaimMapper::MapperType).custom(ClaimMapperType::name()).not_null())
+                    .col(ColumnDef::new(ClaimMapper::ClaimName).string().not_null())
+                    .col(ColumnDef::new(ClaimMapper::Source).string())
+                    .col(ColumnDef::new(ClaimMapper::Value).json_binary())
+                    .col(ColumnDef::new(ClaimMapper::Multivalued).boolean().not_null().default(false))
+                    .col(
+                        ColumnDef::new(ClaimMapper::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .col(
+                        ColumnDef::new(ClaimMapper::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("claim_mapper_client_id_and_claim_name_idx")
+                            .col(ClaimMapper::ClientId)
+                            .col(ClaimMapper::ClaimName),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(ClaimMapper::Table).to_owned()).await?;
+        manager.drop_type(Type::drop().name(ClaimMapperType::name()).to_owned()).await
+    }
+}
+
+#[derive(EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "claim_mapper_type")]
+pub enum ClaimMapperType {
+    #[sea_orm(string_value = "resource")]
+    Resource,
+    #[sea_orm(string_value = "user_attribute")]
+    UserAttribute,
+    #[sea_orm(string_value = "static")]
+    Static,
+    #[sea_orm(string_value = "omit")]
+    Omit,
+}
+
+#[derive(DeriveIden)]
+pub enum ClaimMapper {
+    Table,
+    Id,
+    ClientId,
+    MapperType,
+    ClaimName,
+    Source,
+    Value,
+    Multivalued,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,46 @@
+use super::{m20220101_000002_create_client_table::Client, m20220101_000008_create_session_table::Session};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(ColumnDef::new(ClientDpop::RequireDpop).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Session::Table)
+                    .add_column(ColumnDef::new(SessionDpop::DpopJkt).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Session::Table).drop_column(SessionDpop::DpopJkt).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Client::Table).drop_column(ClientDpop::RequireDpop).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ClientDpop {
+    RequireDpop,
+}
+
+#[derive(DeriveIden)]
+enum SessionDpop {
+    DpopJkt,
+}
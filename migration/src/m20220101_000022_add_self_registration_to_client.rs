@@ -0,0 +1,85 @@
+use super::{m20220101_000002_create_client_table::Client, m20220101_000004_create_resource_group_table::ResourceGroup};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(
+                        ColumnDef::new(ClientRegistration::AllowSelfRegistration)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(ColumnDef::new(ClientRegistration::RegistrationTemplate).json_binary())
+                    .add_column(
+                        ColumnDef::new(ClientRegistration::RegistrationEmailDomains)
+                            .array(ColumnType::Text)
+                            .not_null()
+                            .default(Expr::cust("'{}'")),
+                    )
+                    .add_column(
+                        ColumnDef::new(ClientRegistration::RequireRegistrationApproval)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ResourceGroup::Table)
+                    .add_column(
+                        ColumnDef::new(ResourceGroupRegistration::AwaitingApproval)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ResourceGroup::Table)
+                    .drop_column(ResourceGroupRegistration::AwaitingApproval)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .drop_column(ClientRegistration::AllowSelfRegistration)
+                    .drop_column(ClientRegistration::RegistrationTemplate)
+                    .drop_column(ClientRegistration::RegistrationEmailDomains)
+                    .drop_column(ClientRegistration::RequireRegistrationApproval)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ClientRegistration {
+    AllowSelfRegistration,
+    RegistrationTemplate,
+    RegistrationEmailDomains,
+    RequireRegistrationApproval,
+}
+
+#[derive(DeriveIden)]
+enum ResourceGroupRegistration {
+    AwaitingApproval,
+}
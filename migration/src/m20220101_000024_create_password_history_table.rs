@@ -0,0 +1,97 @@
+use super::{m20220101_000001_create_realm_table::Realm, m20220101_000003_create_user_table::User};
+use sea_orm::sqlx::types::chrono;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realm::Table)
+                    .add_column(
+                        ColumnDef::new(RealmPasswordPolicy::PasswordPolicy)
+                            .json_binary()
+                            .not_null()
+                            .default(Expr::cust("'{}'")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(
+                        ColumnDef::new(UserPassword::PasswordChangedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(PasswordHistory::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(PasswordHistory::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(PasswordHistory::UserId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_password_history_user_id")
+                            .from(PasswordHistory::Table, PasswordHistory::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(PasswordHistory::PasswordHash).string().not_null())
+                    .col(
+                        ColumnDef::new(PasswordHistory::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(PasswordHistory::Table).to_owned()).await?;
+        manager
+            .alter_table(Table::alter().table(User::Table).drop_column(UserPassword::PasswordChangedAt).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realm::Table)
+                    .drop_column(RealmPasswordPolicy::PasswordPolicy)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RealmPasswordPolicy {
+    PasswordPolicy,
+}
+
+#[derive(DeriveIden)]
+enum UserPassword {
+    PasswordChangedAt,
+}
+
+#[derive(DeriveIden)]
+enum PasswordHistory {
+    Table,
+    Id,
+    UserId,
+    PasswordHash,
+    CreatedAt,
+}
@@ -0,0 +1,88 @@
+use super::m20220101_000003_create_user_table::User;
+use sea_orm::sqlx::types::chrono;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TotpSecret::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(TotpSecret::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(TotpSecret::UserId).uuid().not_null().unique_key())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_totp_secret_user_id")
+                            .from(TotpSecret::Table, TotpSecret::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(TotpSecret::Secret).string().not_null())
+                    .col(ColumnDef::new(TotpSecret::LastUsedStep).big_integer())
+                    .col(
+                        ColumnDef::new(TotpSecret::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(RecoveryCode::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(RecoveryCode::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(RecoveryCode::UserId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_recovery_code_user_id")
+                            .from(RecoveryCode::Table, RecoveryCode::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(RecoveryCode::CodeHash).string().not_null())
+                    .col(ColumnDef::new(RecoveryCode::UsedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(RecoveryCode::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(RecoveryCode::Table).to_owned()).await?;
+        manager.drop_table(Table::drop().table(TotpSecret::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TotpSecret {
+    Table,
+    Id,
+    UserId,
+    Secret,
+    LastUsedStep,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum RecoveryCode {
+    Table,
+    Id,
+    UserId,
+    CodeHash,
+    UsedAt,
+    CreatedAt,
+}
@@ -0,0 +1,59 @@
+use super::m20220101_000003_create_user_table::User;
+use sea_orm::sqlx::types::chrono;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WebauthnCredential::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(WebauthnCredential::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(WebauthnCredential::UserId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_webauthn_credential_user_id")
+                            .from(WebauthnCredential::Table, WebauthnCredential::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(WebauthnCredential::CredentialId).string().not_null().unique_key())
+                    .col(ColumnDef::new(WebauthnCredential::PublicKey).string().not_null())
+                    .col(ColumnDef::new(WebauthnCredential::Algorithm).integer().not_null())
+                    .col(ColumnDef::new(WebauthnCredential::SignCount).big_integer().not_null().default(0))
+                    .col(ColumnDef::new(WebauthnCredential::Name).string())
+                    .col(ColumnDef::new(WebauthnCredential::LastUsedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(WebauthnCredential::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(WebauthnCredential::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WebauthnCredential {
+    Table,
+    Id,
+    UserId,
+    CredentialId,
+    PublicKey,
+    Algorithm,
+    SignCount,
+    Name,
+    LastUsedAt,
+    CreatedAt,
+}
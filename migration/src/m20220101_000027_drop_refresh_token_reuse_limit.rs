@@ -0,0 +1,39 @@
+use super::{m20220101_000001_create_realm_table::Realm, m20220101_000002_create_client_table::Client};
+use sea_orm_migration::prelude::*;
+
+// Refresh tokens rotate on every use since the families were added, so a reuse limit no longer applies
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Client::Table).drop_column(Client::RefreshTokenReuseLimit).to_owned())
+            .await?;
+
+        manager
+            .alter_table(Table::alter().table(Realm::Table).drop_column(Realm::RefreshTokenReuseLimit).to_owned())
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realm::Table)
+                    .add_column(ColumnDef::new(Realm::RefreshTokenReuseLimit).integer().not_null().default(0))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(ColumnDef::new(Client::RefreshTokenReuseLimit).integer().not_null().default(0))
+                    .to_owned(),
+            )
+            .await
+    }
+}
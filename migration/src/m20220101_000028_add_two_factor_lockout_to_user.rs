@@ -0,0 +1,43 @@
+use super::m20220101_000003_create_user_table::User;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(
+                        ColumnDef::new(UserTwoFactorLockout::TwoFactorFailedAttempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column(ColumnDef::new(UserTwoFactorLockout::TwoFactorLockedUntil).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(UserTwoFactorLockout::TwoFactorFailedAttempts)
+                    .drop_column(UserTwoFactorLockout::TwoFactorLockedUntil)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserTwoFactorLockout {
+    TwoFactorFailedAttempts,
+    TwoFactorLockedUntil,
+}
@@ -0,0 +1,49 @@
+use super::m20220101_000002_create_client_table::Client;
+use sea_orm::sqlx::types::chrono;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UsedWebauthnChallenge::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(UsedWebauthnChallenge::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(UsedWebauthnChallenge::ClientId).uuid().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_used_webauthn_challenge_client_id")
+                            .from(UsedWebauthnChallenge::Table, UsedWebauthnChallenge::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(UsedWebauthnChallenge::ExpiresAt).timestamp_with_time_zone().not_null())
+                    .col(
+                        ColumnDef::new(UsedWebauthnChallenge::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(chrono::Utc::now()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(UsedWebauthnChallenge::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UsedWebauthnChallenge {
+    Table,
+    Id,
+    ClientId,
+    ExpiresAt,
+    CreatedAt,
+}
@@ -0,0 +1,35 @@
+use super::m20220101_000003_create_user_table::User;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The flag defaulted to true while nothing read it, so existing users were never given a temporary password
+        manager
+            .exec_stmt(Query::update().table(User::Table).value(User::IsTempPassword, false).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .modify_column(ColumnDef::new(User::IsTempPassword).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .modify_column(ColumnDef::new(User::IsTempPassword).boolean().not_null().default(true))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -8,7 +8,7 @@ use tracing::{error, info};
 
 use crate::{
     middleware::logger::logger,
-    packages::{admin, db::get_db_connection_pool, logger},
+    packages::{admin, db::get_db_connection_pool, logger, session_cache, signing_key},
     routes,
 };
 
@@ -25,6 +25,8 @@ pub async fn create_app() -> Router {
     if is_settings_reloaded {
         info!("New admin credentials initialized and settings reloaded!");
     }
+    signing_key::setup(&state.db).await.expect("Failed to setup signing keys");
+    session_cache::setup(&state.db).await.expect("Failed to setup session revocations");
 
     Router::new()
         .merge(routes::create_routes())
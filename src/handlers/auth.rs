@@ -1,31 +1,55 @@
-use chrono::Utc;
 use entity::{
-    client, refresh_token, resource, resource_group,
+    claim_mapper, client, realm, refresh_token, resource, resource_group,
     sea_orm_active_enums::{ApiUserAccess, ApiUserRole},
-    session, user,
+    session, user, webauthn_credential,
 };
 
-use sea_orm::{
-    prelude::Uuid, ActiveModelTrait, ColumnTrait, DatabaseTransaction, DbErr, EntityTrait, PaginatorTrait, QueryFilter, Set, TransactionTrait,
-};
+use sea_orm::{prelude::Uuid, ColumnTrait, Condition, DatabaseTransaction, DbErr, EntityTrait, QueryFilter, TransactionTrait};
 use std::sync::Arc;
 
 use crate::{
     mappers::auth::{
-        CreateUserRequest, IntrospectRequest, IntrospectResponse, LogoutRequest, LogoutResponse, RefreshTokenRequest, RefreshTokenResponse,
+        ChangePasswordRequest, ConfirmTwoFactorRequest, CreateUserRequest, EnrollTwoFactorRequest, ForgotPasswordRequest, IntrospectRequest,
+        IntrospectResponse, LogoutRequest, LogoutResponse, PasswordResponse, RecoveryCodesResponse, RefreshTokenRequest, RefreshTokenResponse,
+        RegisterPasskeyRequest, ResendVerificationRequest, ResetPasswordRequest, SetPasswordRequest, SignupRequest, SignupResponse,
+        TwoFactorEnrollmentResponse, TwoFactorSetupResponse, VerifyEmailQuery, VerifyEmailResponse, VerifyTwoFactorRequest,
     },
     middleware::session_info_extractor::SessionInfo,
     packages::{
         api_token::{decode_refresh_token, ApiUser, RefreshTokenClaims},
+        claim_mapper::{mapped_claims, omits},
         db::AppState,
+        dpop::{proves_possession, DpopKey},
         errors::{AuthenticateError, Error},
-        jwt_token::{create, decode, JwtUser},
-        settings::SETTINGS,
+        jwt_token::{create, decode, expiry, issuer, IdTokenClaims, JwtUser},
+        webauthn::{AssertionCredential, CreationOptions, RequestOptions},
+    },
+    services::{
+        auth::{
+            authenticate_user, authenticate_user_by_id, extend_session, handle_refresh_token, handle_refresh_token_reuse, insert_refresh_token,
+            insert_session, revoke_sessions,
+        },
+        claim_mapper::get_all_claim_mappers,
+        password::{
+            change_password as change_user_password, expire_outdated_password, issue_password_change_token, replace_temporary_password,
+            request_password_reset, reset_password as reset_user_password,
+        },
+        registration::register_user,
+        two_factor::{
+            complete_two_factor_challenge, enable_two_factor, enroll_two_factor, issue_two_factor_challenge, requires_two_factor, setup_two_factor,
+        },
+        user::insert_user,
+        verification::{resend_verification_email, send_verification_email, verify_email as verify_email_token},
+        webauthn::{finish_authentication, finish_registration, start_authentication, start_registration},
     },
-    services::{auth::handle_refresh_token, user::insert_user},
     utils::role_checker::{has_access_to_api_cred, is_current_realm_admin, is_master_realm_admin},
 };
-use axum::{extract::Path, Extension, Json};
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
@@ -38,111 +62,250 @@ pub struct Credentials {
 #[derive(Serialize)]
 pub struct LoginResponse {
     access_token: String,
+    id_token: String,
     user: user::Model,
     session_id: Uuid,
     realm_id: Uuid,
     client_id: Uuid,
     #[serde(skip_serializing_if = "Option::is_none")]
     refresh_token: Option<String>,
+    // Handed out once, when two-factor authentication is set up while signing in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery_codes: Option<Vec<String>>,
 }
 
 pub async fn login(
     Extension(state): Extension<Arc<AppState>>,
     Extension(session_info): Extension<Arc<SessionInfo>>,
     Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    dpop: DpopKey,
     Json(payload): Json<Credentials>,
-) -> Result<Json<LoginResponse>, Error> {
+) -> Result<Response, Error> {
     debug!("🚀 Login request received! {:#?}", session_info);
 
-    let user_with_resource_groups = user::Entity::find()
-        .filter(user::Column::Email.eq(payload.email))
-        .find_also_related(resource_group::Entity)
-        .filter(resource_group::Column::RealmId.eq(realm_id))
-        .filter(resource_group::Column::ClientId.eq(client_id))
+    let client = client::Entity::find_by_id(client_id)
         .one(&state.db)
-        .await?;
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(|| {
+            debug!("No client found");
+            Error::not_found()
+        })?;
 
-    if user_with_resource_groups.is_none() {
-        debug!("No matching data found");
-        return Err(Error::not_found());
-    }
+    let (user, resource_groups) = authenticate_user(&state.db, &client, &payload.email, &payload.password).await?;
 
-    let (user, resource_groups) = user_with_resource_groups.unwrap();
-    if !user.verify_password(&payload.password) {
-        debug!("Wrong password");
-        return Err(Error::Authenticate(AuthenticateError::WrongCredentials));
-    }
-    if user.locked_at.is_some() {
-        debug!("User is locked");
+    if client.locked_at.is_some() {
+        debug!("Client is locked");
         return Err(Error::Authenticate(AuthenticateError::Locked));
     }
+    let dpop_jkt = dpop.binding(&client)?;
 
-    if resource_groups.is_none() {
-        debug!("No matching resource group found");
-        return Err(Error::not_found());
-    }
+    let realm = realm::Entity::find_by_id(realm_id).one(&state.db).await?.ok_or_else(|| {
+        debug!("No realm found");
+        Error::not_found()
+    })?;
 
-    let resource_groups = resource_groups.unwrap();
-    if resource_groups.locked_at.is_some() {
-        debug!("Resource group is locked");
-        return Err(Error::Authenticate(AuthenticateError::Locked));
+    let user = expire_outdated_password(&state.db, &realm, user).await?;
+    if user.is_temp_password {
+        debug!("Temporary password must be changed");
+        return Ok(Json(issue_password_change_token(&realm, &client, &user)?).into_response());
+    }
+    if requires_two_factor(&client, &user) {
+        debug!("Second factor required");
+        return Ok(Json(issue_two_factor_challenge(&state.db, state.mailer.as_ref(), &realm, &client, &user).await?).into_response());
     }
 
-    // Fetch client separately
-    let client = client::Entity::find_by_id(client_id).one(&state.db).await?.ok_or_else(|| {
-        debug!("No client found");
-        Error::not_found()
-    })?;
+    let login_response = start_session(&state, session_info, realm, client, user, resource_groups, dpop_jkt).await?;
+    Ok(Json(login_response).into_response())
+}
 
-    if client.locked_at.is_some() {
-        debug!("Client is locked");
-        return Err(Error::Authenticate(AuthenticateError::Locked));
+/// Replaces the temporary password of a user with one they chose, and signs them in like a login would.
+pub async fn set_password(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    dpop: DpopKey,
+    Json(payload): Json<SetPasswordRequest>,
+) -> Result<Response, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+    let dpop_jkt = dpop.binding(&client)?;
+
+    let user = replace_temporary_password(&state.db, &client, payload).await?;
+    let (user, resource_groups) = authenticate_user_by_id(&state.db, &client, user.id).await?;
+
+    let realm = realm::Entity::find_by_id(realm_id).one(&state.db).await?.ok_or_else(Error::not_found)?;
+    if requires_two_factor(&client, &user) {
+        return Ok(Json(issue_two_factor_challenge(&state.db, state.mailer.as_ref(), &realm, &client, &user).await?).into_response());
     }
+    let login_response = start_session(&state, session_info, realm, client, user, resource_groups, dpop_jkt).await?;
+    Ok(Json(login_response).into_response())
+}
+
+/// Second step of the login for users who have to prove a second factor, which sets it up for users who had none.
+pub async fn verify_two_factor(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    dpop: DpopKey,
+    Json(payload): Json<VerifyTwoFactorRequest>,
+) -> Result<Json<LoginResponse>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+    let dpop_jkt = dpop.binding(&client)?;
+
+    let (user, recovery_codes) = complete_two_factor_challenge(&state.db, &client, &payload).await?;
+    let (user, resource_groups) = authenticate_user_by_id(&state.db, &client, user.id).await?;
+
+    let realm = realm::Entity::find_by_id(realm_id).one(&state.db).await?.ok_or_else(Error::not_found)?;
+    let login_response = start_session(&state, session_info, realm, client, user, resource_groups, dpop_jkt).await?;
+    Ok(Json(LoginResponse {
+        recovery_codes,
+        ..login_response
+    }))
+}
+
+/// Signs in with a passkey instead of a password. Passkeys verify the user on their own, so no second factor is asked for.
+pub async fn passkey_login(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    dpop: DpopKey,
+    Json(payload): Json<AssertionCredential>,
+) -> Result<Json<LoginResponse>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+    let dpop_jkt = dpop.binding(&client)?;
+
+    let user = finish_authentication(&state.db, &client, &payload).await?;
+    let (user, resource_groups) = authenticate_user_by_id(&state.db, &client, user.id).await?;
+
+    let realm = realm::Entity::find_by_id(realm_id).one(&state.db).await?.ok_or_else(Error::not_found)?;
+    let login_response = start_session(&state, session_info, realm, client, user, resource_groups, dpop_jkt).await?;
+    Ok(Json(login_response))
+}
+
+pub async fn passkey_login_options(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<RequestOptions>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+    Ok(Json(start_authentication(&state.db, &client).await?))
+}
+
+pub async fn passkey_registration_options(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<CreationOptions>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+    Ok(Json(start_registration(&state.db, &client, &user).await?))
+}
+
+pub async fn register_passkey(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<RegisterPasskeyRequest>,
+) -> Result<Json<webauthn_credential::Model>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+    Ok(Json(finish_registration(&state.db, &client, &user, payload).await?))
+}
+
+/// Hands users who were mailed an enrollment token at login the secret to set up two-factor authentication with.
+pub async fn enroll_two_factor_authentication(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<EnrollTwoFactorRequest>,
+) -> Result<Json<TwoFactorEnrollmentResponse>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+    Ok(Json(enroll_two_factor(&state.db, &client, &payload.token).await?))
+}
+
+pub async fn setup_two_factor_authentication(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<TwoFactorSetupResponse>, Error> {
+    Ok(Json(setup_two_factor(&state.db, &user).await?))
+}
+
+pub async fn confirm_two_factor_authentication(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<ConfirmTwoFactorRequest>,
+) -> Result<Json<RecoveryCodesResponse>, Error> {
+    let recovery_codes = enable_two_factor(&state.db, &user, &payload.code).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
 
+async fn start_session(
+    state: &AppState,
+    session_info: Arc<SessionInfo>,
+    realm: realm::Model,
+    client: client::Model,
+    user: user::Model,
+    resource_groups: resource_group::Model,
+    dpop_jkt: Option<String>,
+) -> Result<LoginResponse, Error> {
     let login_response = state
         .db
         .transaction(|txn| {
             Box::pin(async move {
                 let result: Result<LoginResponse, Error> = async {
                     let refresh_token_model = if client.use_refresh_token {
-                        let model = refresh_token::ActiveModel {
-                            id: Set(Uuid::now_v7()),
-                            user_id: Set(user.id),
-                            client_id: Set(Some(client_id)),
-                            realm_id: Set(realm_id),
-                            re_used_count: Set(0),
-                            locked_at: Set(None),
-                            ..Default::default()
-                        };
-                        Some(model.insert(txn).await?)
+                        Some(insert_refresh_token(txn, user.id, &client).await?)
                     } else {
                         None
                     };
 
-                    let session = create_session(
+                    let resources = find_resources(txn, &resource_groups).await?;
+                    let mappers = get_all_claim_mappers(txn, client.id).await?;
+                    let session = insert_session(
+                        txn,
                         &client,
                         &user,
-                        resource_groups,
                         session_info,
                         refresh_token_model.as_ref().map(|x| x.id),
-                        txn,
+                        None,
+                        dpop_jkt,
                     )
                     .await?;
+                    let session = sign_session(&realm, &client, &user, resource_groups, resources, &session, &mappers)?;
 
                     let refresh_token = if let Some(refresh_token) = refresh_token_model {
                         let claims = RefreshTokenClaims::from(&refresh_token, &client);
-                        Some(claims.create_token(&SETTINGS.read().secrets.signing_key).unwrap())
+                        Some(claims.create_token(&realm).map_err(|e| Error::SigningKey(e.to_string()))?)
                     } else {
                         None
                     };
 
                     Ok(LoginResponse {
                         access_token: session.access_token,
+                        id_token: session.id_token,
                         realm_id: user.realm_id,
                         user,
                         session_id: session.session_id,
                         client_id: client.id,
                         refresh_token,
+                        recovery_codes: None,
                     })
                 }
                 .await;
@@ -152,30 +315,10 @@ pub async fn login(
         })
         .await?;
 
-    Ok(Json(login_response))
+    Ok(login_response)
 }
 
-async fn create_session(
-    client: &client::Model,
-    user: &user::Model,
-    resource_groups: resource_group::Model,
-    session_info: Arc<SessionInfo>,
-    refresh_token_id: Option<Uuid>,
-    db: &DatabaseTransaction,
-) -> Result<LoginResponse, Error> {
-    let sessions = session::Entity::find()
-        .filter(session::Column::ClientId.eq(client.id))
-        .filter(session::Column::UserId.eq(user.id))
-        .filter(session::Column::Expires.gt(chrono::Utc::now()))
-        .count(db)
-        .await?;
-
-    if sessions >= client.max_concurrent_sessions as u64 {
-        debug!("Client has reached max concurrent sessions");
-        return Err(Error::Authenticate(AuthenticateError::MaxConcurrentSessions));
-    }
-
-    // Fetch resources
+async fn find_resources(db: &DatabaseTransaction, resource_groups: &resource_group::Model) -> Result<Vec<resource::Model>, Error> {
     let resources = resource::Entity::find()
         .filter(resource::Column::GroupId.eq(resource_groups.id))
         .filter(resource::Column::LockedAt.is_null())
@@ -186,41 +329,33 @@ async fn create_session(
         debug!("No resources found");
         return Err(Error::Authenticate(AuthenticateError::Locked));
     }
+    Ok(resources)
+}
 
-    let session_model = session::ActiveModel {
-        id: Set(Uuid::now_v7()),
-        user_id: Set(user.id),
-        client_id: Set(client.id),
-        ip_address: Set(session_info.ip_address.to_string()),
-        user_agent: Set(Some(session_info.user_agent.to_string())),
-        browser: Set(Some(session_info.browser.to_string())),
-        browser_version: Set(Some(session_info.browser_version.to_string())),
-        operating_system: Set(Some(session_info.operating_system.to_string())),
-        device_type: Set(Some(session_info.device_type.to_string())),
-        country_code: Set(session_info.country_code.to_string()),
-        refresh_token_id: Set(refresh_token_id),
-        expires: Set((Utc::now() + chrono::Duration::seconds(client.session_lifetime as i64)).into()),
-        ..Default::default()
-    };
-    let session = session_model.insert(db).await?;
-
-    let access_token = create(
-        user.clone(),
-        client,
-        resource_groups,
-        resources,
-        &session,
-        &SETTINGS.read().secrets.signing_key,
-    )
-    .unwrap();
+fn sign_session(
+    realm: &realm::Model,
+    client: &client::Model,
+    user: &user::Model,
+    resource_groups: resource_group::Model,
+    resources: Vec<resource::Model>,
+    session: &session::Model,
+    mappers: &[claim_mapper::Model],
+) -> Result<LoginResponse, Error> {
+    let access_token =
+        create(user.clone(), client, resource_groups, resources, session, mappers, realm).map_err(|e| Error::SigningKey(e.to_string()))?;
+    let id_token = IdTokenClaims::new(user, client, session, None)
+        .create_token(realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))?;
 
     Ok(LoginResponse {
         access_token,
+        id_token,
         realm_id: user.realm_id,
         user: user.clone(),
         session_id: session.id,
         client_id: client.id,
         refresh_token: None,
+        recovery_codes: None,
     })
 }
 
@@ -232,16 +367,106 @@ pub async fn register(
 ) -> Result<Json<user::Model>, Error> {
     if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
         let user = insert_user(&state.db, realm_id, client_id, payload).await?;
+        let client = client::Entity::find_by_id(client_id).one(&state.db).await?.ok_or_else(Error::not_found)?;
+        // The user exists either way, they can ask for another verification email when this one fails
+        if let Err(err) = send_verification_email(&state.db, state.mailer.as_ref(), &client, &user).await {
+            err.log();
+        }
         Ok(Json(user))
     } else {
         Err(Error::Authenticate(AuthenticateError::ActionForbidden))
     }
 }
 
+pub async fn signup(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<SignupRequest>,
+) -> Result<Json<SignupResponse>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+
+    let user = register_user(&state.db, &client, payload).await?;
+    if let Err(err) = send_verification_email(&state.db, state.mailer.as_ref(), &client, &user).await {
+        err.log();
+    }
+    Ok(Json(SignupResponse {
+        user,
+        awaiting_approval: client.require_registration_approval,
+    }))
+}
+
+pub async fn verify_email(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Result<Json<VerifyEmailResponse>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+
+    let user = verify_email_token(&state.db, &client, &query.token).await?;
+    Ok(Json(VerifyEmailResponse { ok: true, user_id: user.id }))
+}
+
+pub async fn resend_verification(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ResendVerificationRequest>,
+) -> Result<StatusCode, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+
+    resend_verification_email(&state.db, state.mailer.as_ref(), &client, &payload.email).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn forgot_password(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<StatusCode, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+
+    request_password_reset(&state.db, state.mailer.as_ref(), &client, payload).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn reset_password(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<PasswordResponse>, Error> {
+    let client = client::Entity::find_active_by_id(&state.db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(Error::not_found)?;
+
+    let user = reset_user_password(&state.db, &client, payload).await?;
+    Ok(Json(PasswordResponse { ok: true, user_id: user.id }))
+}
+
+pub async fn change_password(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<PasswordResponse>, Error> {
+    let user = change_user_password(&state.db, &user, payload).await?;
+    Ok(Json(PasswordResponse { ok: true, user_id: user.id }))
+}
+
 pub async fn logout_current_session(user: JwtUser, Extension(state): Extension<Arc<AppState>>) -> Result<Json<LogoutResponse>, Error> {
-    let result = session::Entity::delete_by_id(user.sid).exec(&state.db).await?;
+    let rows_affected = revoke_sessions(&state.db, Condition::all().add(session::Column::Id.eq(user.sid))).await?;
     Ok(Json(LogoutResponse {
-        ok: result.rows_affected == 1,
+        ok: rows_affected == 1,
         user_id: user.sub,
         session_id: user.sid,
     }))
@@ -250,32 +475,34 @@ pub async fn logout_current_session(user: JwtUser, Extension(state): Extension<A
 pub async fn logout(
     user: JwtUser,
     Extension(state): Extension<Arc<AppState>>,
-    Path((realm_id, _)): Path<(Uuid, Uuid)>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
     Json(payload): Json<LogoutRequest>,
 ) -> Result<Json<LogoutResponse>, Error> {
     if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
         match payload.access_token {
             Some(access_token) => {
-                let sid = decode(&access_token, &SETTINGS.read().secrets.signing_key)
+                let sid = decode(&state.db, &access_token, realm_id, Some(client_id))
+                    .await
                     .map_err(|_| AuthenticateError::InvalidToken)?
                     .claims
                     .sid;
-                let result = session::Entity::delete_by_id(sid).exec(&state.db).await?;
+                let rows_affected = revoke_sessions(&state.db, Condition::all().add(session::Column::Id.eq(sid))).await?;
                 Ok(Json(LogoutResponse {
-                    ok: result.rows_affected == 1,
+                    ok: rows_affected == 1,
                     user_id: user.sub,
                     session_id: user.sid,
                 }))
             }
             None => match payload.refresh_token {
                 Some(refresh_token) => {
-                    let sid = decode(&refresh_token, &SETTINGS.read().secrets.signing_key)
+                    let sid = decode(&state.db, &refresh_token, realm_id, Some(client_id))
+                        .await
                         .map_err(|_| AuthenticateError::InvalidToken)?
                         .claims
                         .sid;
-                    let result = session::Entity::delete_by_id(sid).exec(&state.db).await?;
+                    let rows_affected = revoke_sessions(&state.db, Condition::all().add(session::Column::Id.eq(sid))).await?;
                     Ok(Json(LogoutResponse {
-                        ok: result.rows_affected == 1,
+                        ok: rows_affected == 1,
                         user_id: user.sub,
                         session_id: user.sid,
                     }))
@@ -293,13 +520,15 @@ pub async fn logout_my_all_sessions(
     Extension(state): Extension<Arc<AppState>>,
     Path((_, client_id)): Path<(Uuid, Uuid)>,
 ) -> Result<Json<LogoutResponse>, Error> {
-    let result = session::Entity::delete_many()
-        .filter(session::Column::ClientId.eq(client_id))
-        .filter(session::Column::UserId.eq(user.sub))
-        .exec(&state.db)
-        .await?;
+    let rows_affected = revoke_sessions(
+        &state.db,
+        Condition::all()
+            .add(session::Column::ClientId.eq(client_id))
+            .add(session::Column::UserId.eq(user.sub)),
+    )
+    .await?;
     Ok(Json(LogoutResponse {
-        ok: result.rows_affected > 0,
+        ok: rows_affected > 0,
         user_id: user.sub,
         session_id: user.sid,
     }))
@@ -314,34 +543,40 @@ pub async fn logout_all(
     if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
         match payload.access_token {
             Some(access_token) => {
-                let sub = decode(&access_token, &SETTINGS.read().secrets.signing_key)
+                let sub = decode(&state.db, &access_token, realm_id, Some(client_id))
+                    .await
                     .map_err(|_| AuthenticateError::InvalidToken)?
                     .claims
                     .sub;
-                let result = session::Entity::delete_many()
-                    .filter(session::Column::ClientId.eq(client_id))
-                    .filter(session::Column::UserId.eq(sub))
-                    .exec(&state.db)
-                    .await?;
+                let rows_affected = revoke_sessions(
+                    &state.db,
+                    Condition::all()
+                        .add(session::Column::ClientId.eq(client_id))
+                        .add(session::Column::UserId.eq(sub)),
+                )
+                .await?;
                 Ok(Json(LogoutResponse {
-                    ok: result.rows_affected > 0,
+                    ok: rows_affected > 0,
                     user_id: user.sub,
                     session_id: user.sid,
                 }))
             }
             None => match payload.refresh_token {
                 Some(refresh_token) => {
-                    let sub = decode(&refresh_token, &SETTINGS.read().secrets.signing_key)
+                    let sub = decode(&state.db, &refresh_token, realm_id, Some(client_id))
+                        .await
                         .map_err(|_| AuthenticateError::InvalidToken)?
                         .claims
                         .sub;
-                    let result = session::Entity::delete_many()
-                        .filter(session::Column::ClientId.eq(client_id))
-                        .filter(session::Column::UserId.eq(sub))
-                        .exec(&state.db)
-                        .await?;
+                    let rows_affected = revoke_sessions(
+                        &state.db,
+                        Condition::all()
+                            .add(session::Column::ClientId.eq(client_id))
+                            .add(session::Column::UserId.eq(sub)),
+                    )
+                    .await?;
                     Ok(Json(LogoutResponse {
-                        ok: result.rows_affected > 0,
+                        ok: rows_affected > 0,
                         user_id: user.sub,
                         session_id: user.sid,
                     }))
@@ -361,12 +596,27 @@ pub async fn introspect(
     Json(payload): Json<IntrospectRequest>,
 ) -> Result<Json<IntrospectResponse>, Error> {
     if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
-        let token_data = decode(&payload.access_token, &SETTINGS.read().secrets.signing_key).expect("Failed to decode token");
+        let token_data = decode(&state.db, &payload.access_token, realm_id, Some(client_id))
+            .await
+            .map_err(|_| AuthenticateError::InvalidToken)?;
 
         if token_data.claims.resource.is_none() || token_data.claims.resource.is_some() && token_data.claims.resource.unwrap().client_id != client_id
         {
             return Err(Error::Authenticate(AuthenticateError::NoResource));
         }
+        // Bound tokens are only valid along with the proof of possession the resource server received them with
+        if let Some(cnf) = &token_data.claims.cnf {
+            let proves_possession = proves_possession(
+                cnf,
+                &payload.access_token,
+                payload.dpop_proof.as_deref(),
+                payload.htm.as_deref(),
+                payload.htu.as_deref(),
+            );
+            if !proves_possession {
+                return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+            }
+        }
 
         let session = session::Entity::find_by_id(token_data.claims.sid).one(&state.db).await?;
         match session {
@@ -400,19 +650,26 @@ pub async fn introspect(
                                             .filter(resource::Column::LockedAt.is_null())
                                             .all(&state.db)
                                             .await?;
+                                        let mappers = get_all_claim_mappers(&state.db, client.id).await?;
+                                        let claims = mapped_claims(&mappers, &user, &resources);
                                         Ok(Json(IntrospectResponse {
                                             active: true,
                                             client_id: client.id,
                                             first_name: user.first_name.to_string(),
-                                            last_name: Some(user.last_name.unwrap_or("".to_string())),
+                                            last_name: Some(user.last_name.unwrap_or("".to_string())).filter(|_| !omits(&mappers, "last_name")),
                                             sub: user.id,
-                                            token_type: "bearer".to_string(),
+                                            token_type: match token_data.claims.cnf {
+                                                Some(_) => "DPoP".to_string(),
+                                                None => "bearer".to_string(),
+                                            },
                                             exp: token_data.claims.exp,
                                             iat: token_data.claims.iat,
-                                            iss: SETTINGS.read().server.host.clone(),
+                                            iss: issuer(realm_id),
                                             client_name: client.name,
                                             resource_group: resource_group.name,
                                             resources: resources.iter().map(|r| r.name.clone()).collect::<Vec<String>>(),
+                                            cnf: token_data.claims.cnf,
+                                            claims,
                                         }))
                                     }
                                     None => Err(Error::Authenticate(AuthenticateError::NoResource))?,
@@ -434,8 +691,8 @@ pub async fn introspect(
 pub async fn refresh_token(
     user: ApiUser,
     Extension(state): Extension<Arc<AppState>>,
-    Extension(session_info): Extension<Arc<SessionInfo>>,
     Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    dpop: DpopKey,
     Json(payload): Json<RefreshTokenRequest>,
 ) -> Result<Json<RefreshTokenResponse>, Error> {
     if !has_access_to_api_cred(&user, ApiUserRole::ClientAdmin, ApiUserAccess::Admin).await {
@@ -443,22 +700,50 @@ pub async fn refresh_token(
         return Err(Error::Authenticate(AuthenticateError::ActionForbidden));
     }
 
-    let token_data = decode_refresh_token(&payload.refresh_token, &SETTINGS.read().secrets.signing_key).expect("Failed to decode token");
+    let token_data = decode_refresh_token(&state.db, &payload.refresh_token, realm_id, client_id)
+        .await
+        .map_err(|_| AuthenticateError::InvalidToken)?;
     if token_data.claims.rli != realm_id || token_data.claims.cli != client_id {
         return Err(Error::Authenticate(AuthenticateError::InvalidToken));
     }
 
-    let refresh_token = refresh_token::Entity::find_active_by_id(&state.db, token_data.claims.sub).await?;
+    let refresh_token = refresh_token::Entity::find_by_id(token_data.claims.sub).one(&state.db).await?;
     if refresh_token.is_none() {
         return Err(Error::not_found());
     }
+    let refresh_token = refresh_token.unwrap();
+    if refresh_token.replaced_by_id.is_some() {
+        handle_refresh_token_reuse(&state.db, &refresh_token).await?;
+        return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+    }
+    if refresh_token.locked_at.is_some() {
+        return Err(Error::not_found());
+    }
+    // The refresh token can only renew the session it belongs to, once that has ended the user has to log in again
+    let session = session::Entity::find()
+        .filter(session::Column::RefreshTokenId.eq(refresh_token.id))
+        .filter(session::Column::Expires.gt(chrono::Utc::now()))
+        .one(&state.db)
+        .await?
+        .ok_or(AuthenticateError::InvalidToken)?;
     let client = client::Entity::find_active_by_id(&state.db, token_data.claims.cli).await?;
     if client.is_none() {
         return Err(Error::Authenticate(AuthenticateError::InvalidToken));
     }
 
-    let refresh_token = refresh_token.unwrap();
     let client = client.unwrap();
+    // A session bound to a DPoP key is only renewed by the holder of that key, clients requiring DPoP do not renew unbound ones
+    let is_holder = match &session.dpop_jkt {
+        Some(dpop_jkt) => dpop.0.as_ref() == Some(dpop_jkt),
+        None => !client.require_dpop,
+    };
+    if !is_holder {
+        return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+    }
+    let realm = realm::Entity::find_by_id(client.realm_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(Error::not_found)?;
 
     // Fetch user and resource groups
     let user_with_resource_groups = user::Entity::find()
@@ -492,21 +777,35 @@ pub async fn refresh_token(
     }
 
     debug!("Before transaction calls");
-    Ok(state
+    let presented_refresh_token = refresh_token.clone();
+    let response = state
         .db
         .transaction(|txn| {
             Box::pin(async move {
-                let refresh_token_claims = handle_refresh_token(txn, &refresh_token, &client).await.unwrap();
-                let session = create_session(&client, &user, resource_groups, session_info, Some(refresh_token_claims.sub), txn)
-                    .await
-                    .unwrap();
-                let refresh_token = refresh_token_claims.create_token(&SETTINGS.read().secrets.signing_key).unwrap();
-                Ok(Json(RefreshTokenResponse {
-                    access_token: session.access_token.clone(),
+                let Some(refresh_token_claims) = handle_refresh_token(txn, &refresh_token, &client).await? else {
+                    return Ok(None);
+                };
+                let resources = find_resources(txn, &resource_groups).await?;
+                let mappers = get_all_claim_mappers(txn, client.id).await?;
+                let session = extend_session(txn, &session, &client, refresh_token_claims.sub).await?;
+                let tokens = sign_session(&realm, &client, &user, resource_groups, resources, &session, &mappers)?;
+                let refresh_token = refresh_token_claims.create_token(&realm).map_err(|e| Error::SigningKey(e.to_string()))?;
+                Ok::<_, Error>(Some(RefreshTokenResponse {
+                    access_token: tokens.access_token,
+                    id_token: tokens.id_token,
                     refresh_token,
-                    expires_in: token_data.claims.exp - chrono::Local::now().timestamp() as usize,
+                    expires_in: expiry(&client, &session).saturating_sub(chrono::Local::now().timestamp() as usize),
                 }))
             })
         })
-        .await?)
+        .await?;
+
+    match response {
+        Some(response) => Ok(Json(response)),
+        // Another request rotated the token first
+        None => {
+            handle_refresh_token_reuse(&state.db, &presented_refresh_token).await?;
+            Err(Error::Authenticate(AuthenticateError::InvalidToken))
+        }
+    }
 }
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, Extension, Json};
+use entity::claim_mapper;
+use uuid::Uuid;
+
+use crate::{
+    mappers::{
+        client::claim_mapper::{CreateClaimMapperRequest, UpdateClaimMapperRequest},
+        DeleteResponse,
+    },
+    packages::{
+        db::AppState,
+        errors::{AuthenticateError, Error},
+        jwt_token::JwtUser,
+    },
+    services::claim_mapper::{delete_claim_mapper_by_id, get_all_claim_mappers, insert_claim_mapper, update_claim_mapper_by_id},
+    utils::role_checker::{is_current_realm_admin, is_master_realm_admin},
+};
+
+pub async fn get_claim_mappers(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<Vec<claim_mapper::Model>>, Error> {
+    if !is_master_realm_admin(&user) && !is_current_realm_admin(&user, &realm_id.to_string()) {
+        return Err(Error::Authenticate(AuthenticateError::NoResource));
+    }
+
+    Ok(Json(get_all_claim_mappers(&state.db, client_id).await?))
+}
+
+pub async fn create_claim_mapper(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<CreateClaimMapperRequest>,
+) -> Result<Json<claim_mapper::Model>, Error> {
+    if !is_master_realm_admin(&user) && !is_current_realm_admin(&user, &realm_id.to_string()) {
+        return Err(Error::Authenticate(AuthenticateError::NoResource));
+    }
+
+    Ok(Json(insert_claim_mapper(&state.db, client_id, payload).await?))
+}
+
+pub async fn update_claim_mapper(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id, claim_mapper_id)): Path<(Uuid, Uuid, Uuid)>,
+    Json(payload): Json<UpdateClaimMapperRequest>,
+) -> Result<Json<claim_mapper::Model>, Error> {
+    if !is_master_realm_admin(&user) && !is_current_realm_admin(&user, &realm_id.to_string()) {
+        return Err(Error::Authenticate(AuthenticateError::NoResource));
+    }
+
+    Ok(Json(update_claim_mapper_by_id(&state.db, client_id, claim_mapper_id, payload).await?))
+}
+
+pub async fn delete_claim_mapper(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id, claim_mapper_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> Result<Json<DeleteResponse>, Error> {
+    if !is_master_realm_admin(&user) && !is_current_realm_admin(&user, &realm_id.to_string()) {
+        return Err(Error::Authenticate(AuthenticateError::NoResource));
+    }
+
+    let delete_result = delete_claim_mapper_by_id(&state.db, client_id, claim_mapper_id).await?;
+    Ok(Json(DeleteResponse {
+        ok: delete_result.rows_affected == 1,
+    }))
+}
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 pub mod api_user;
+pub mod claim_mapper;
+pub mod registration;
 
 use crate::{
     mappers::{
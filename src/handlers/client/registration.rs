@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, Extension, Json};
+use entity::{resource_group, user};
+use uuid::Uuid;
+
+use crate::{
+    mappers::DeleteResponse,
+    packages::{
+        db::AppState,
+        errors::{AuthenticateError, Error},
+        jwt_token::JwtUser,
+    },
+    services::registration::{approve_registration, get_pending_registrations, reject_registration},
+    utils::role_checker::{is_current_realm_admin, is_master_realm_admin},
+};
+
+pub async fn get_registrations(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<Vec<user::Model>>, Error> {
+    if !is_master_realm_admin(&user) && !is_current_realm_admin(&user, &realm_id.to_string()) {
+        return Err(Error::Authenticate(AuthenticateError::NoResource));
+    }
+
+    Ok(Json(get_pending_registrations(&state.db, realm_id, client_id).await?))
+}
+
+pub async fn approve(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id, user_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> Result<Json<resource_group::Model>, Error> {
+    if !is_master_realm_admin(&user) && !is_current_realm_admin(&user, &realm_id.to_string()) {
+        return Err(Error::Authenticate(AuthenticateError::NoResource));
+    }
+
+    Ok(Json(approve_registration(&state.db, realm_id, client_id, user_id).await?))
+}
+
+pub async fn reject(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, client_id, user_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> Result<Json<DeleteResponse>, Error> {
+    if !is_master_realm_admin(&user) && !is_current_realm_admin(&user, &realm_id.to_string()) {
+        return Err(Error::Authenticate(AuthenticateError::NoResource));
+    }
+
+    let ok = reject_registration(&state.db, realm_id, client_id, user_id).await?;
+    Ok(Json(DeleteResponse { ok }))
+}
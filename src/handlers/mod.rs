@@ -1,4 +1,7 @@
 pub mod auth;
 pub mod client;
+pub mod oauth;
 pub mod realm;
+pub mod signing_key;
 pub mod user;
+pub mod well_known;
@@ -0,0 +1,476 @@
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{Path, Query},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, PRAGMA, SET_COOKIE, X_FRAME_OPTIONS},
+        StatusCode,
+    },
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form, Json,
+};
+use axum_extra::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    TypedHeader,
+};
+use chrono::Utc;
+use entity::{client, device_code, realm, session, user};
+use sea_orm::{prelude::Uuid, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use url::Url;
+
+use crate::{
+    mappers::{
+        auth::VerifyTwoFactorRequest,
+        oauth::{
+            AuthorizeLoginRequest, AuthorizeRequest, AuthorizeTwoFactorRequest, DeviceAuthorizationRequest, DeviceAuthorizationResponse,
+            DeviceVerificationQuery, DeviceVerificationRequest, DeviceVerificationResponse, GrantType, IntrospectionRequest, IntrospectionResponse,
+            RevocationRequest, TokenRequest,
+        },
+    },
+    middleware::session_info_extractor::SessionInfo,
+    packages::{
+        action_token::{self, Action},
+        db::AppState,
+        dpop::DpopKey,
+        errors::{AuthenticateError, Error, OAuthError},
+        jwt_token::{issuer, JwtUser, StandardClaims},
+        login_page::{LoginPage, Step},
+        sso::{self, SsoClaims, CSRF_COOKIE, SESSION_COOKIE},
+        webauthn::{AssertionCredential, RequestOptions},
+    },
+    routes::{
+        oauth::{AUTHORIZE_PATH, DEVICE_PATH},
+        realm::OAUTH_PATH,
+    },
+    services::{
+        auth::{authenticate_user, authenticate_user_by_id},
+        client::get_client_by_id,
+        oauth::{
+            authenticate_client, authenticate_service, create_authorization_code, create_device_code, exchange_authorization_code,
+            exchange_device_code, exchange_token, find_signed_in_user, get_device_code_to_verify, get_userinfo, introspect_token,
+            issue_client_credentials_token, revoke_token, validate_authorize_request, verify_device_code, ServiceCaller, DEVICE_CODE_INTERVAL,
+        },
+        password::expire_outdated_password,
+        two_factor::{complete_two_factor_challenge, issue_two_factor_challenge, requires_two_factor},
+        webauthn::start_second_factor_authentication,
+    },
+};
+
+// client_secret_basic takes precedence over client_secret_post
+fn client_credentials<'a>(
+    basic: &'a Option<TypedHeader<Authorization<Basic>>>,
+    client_id: Option<Uuid>,
+    client_secret: Option<&'a str>,
+) -> (Option<Uuid>, Option<&'a str>) {
+    match basic {
+        Some(TypedHeader(Authorization(basic))) => (Uuid::parse_str(basic.username()).ok(), Some(basic.password())),
+        None => (client_id, client_secret),
+    }
+}
+
+const SIGN_IN_EXPIRED: &str = "Your sign in expired, please try again";
+
+fn redirect_with(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> Result<Redirect, Error> {
+    let mut url = Url::parse(redirect_uri).map_err(|_| OAuthError::InvalidRequest("Invalid redirect_uri".to_string()))?;
+    url.query_pairs_mut().extend_pairs(params);
+    if let Some(state) = state {
+        url.query_pairs_mut().append_pair("state", state);
+    }
+    Ok(Redirect::to(url.as_str()))
+}
+
+pub async fn authorize(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path(realm_id): Path<Uuid>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Query(payload): Query<AuthorizeRequest>,
+) -> Result<Response, Error> {
+    start_authorization(&state, session_info, realm_id, cookies, payload).await
+}
+
+// OpenID Connect lets the authorization request be posted as a form as well
+pub async fn authorize_form(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path(realm_id): Path<Uuid>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(payload): Form<AuthorizeRequest>,
+) -> Result<Response, Error> {
+    start_authorization(&state, session_info, realm_id, cookies, payload).await
+}
+
+/// Users who signed in at Shield before are sent straight back to the client with a code, others get the sign in page.
+async fn start_authorization(
+    state: &AppState,
+    session_info: Arc<SessionInfo>,
+    realm_id: Uuid,
+    cookies: Option<TypedHeader<Cookie>>,
+    payload: AuthorizeRequest,
+) -> Result<Response, Error> {
+    let client = find_authorizing_client(&state.db, realm_id, &payload).await?;
+    if let Err(err) = validate_authorize_request(&client, &payload) {
+        return authorization_error(&payload, err);
+    }
+
+    let realm = realm::Entity::find_by_id(realm_id).one(&state.db).await?.ok_or_else(Error::not_found)?;
+    let sso_claims = match cookie(&cookies, SESSION_COOKIE) {
+        Some(token) => sso::decode(&state.db, token, realm_id).await,
+        None => None,
+    };
+    if let Some(claims) = sso_claims {
+        if let Some(user) = find_signed_in_user(&state.db, &realm, &client, &claims).await? {
+            let code = create_authorization_code(&state.db, &client, &user, session_info, &payload).await?;
+            return Ok(redirect_with(&payload.redirect_uri, &[("code", &code.code)], payload.state.as_deref())?.into_response());
+        }
+    }
+
+    Ok(login_page(
+        realm_id,
+        &client,
+        &payload,
+        StatusCode::OK,
+        None,
+        Step::Password { email: None },
+    ))
+}
+
+pub async fn authorize_login(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path(realm_id): Path<Uuid>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(payload): Form<AuthorizeLoginRequest>,
+) -> Result<Response, Error> {
+    let request = &payload.request;
+    let client = find_authorizing_client(&state.db, realm_id, request).await?;
+    if let Err(err) = validate_authorize_request(&client, request) {
+        return authorization_error(request, err);
+    }
+    let retry = |status: StatusCode, error: &str| {
+        login_page(
+            realm_id,
+            &client,
+            request,
+            status,
+            Some(error),
+            Step::Password { email: Some(&payload.email) },
+        )
+    };
+    if cookie(&cookies, CSRF_COOKIE) != Some(payload.csrf_token.as_str()) {
+        return Ok(retry(StatusCode::BAD_REQUEST, SIGN_IN_EXPIRED));
+    }
+
+    let user = match authenticate_user(&state.db, &client, &payload.email, &payload.password).await {
+        Ok((user, _)) => user,
+        // Unknown emails look like wrong passwords, so the page does not tell which emails are registered
+        Err(Error::NotFound(_) | Error::Authenticate(AuthenticateError::WrongCredentials)) => {
+            return Ok(retry(StatusCode::UNAUTHORIZED, "Wrong email or password"));
+        }
+        Err(Error::Authenticate(err)) => return Ok(retry(StatusCode::FORBIDDEN, &err.to_string())),
+        Err(err) => return Err(err),
+    };
+    let realm = realm::Entity::find_by_id(realm_id).one(&state.db).await?.ok_or_else(Error::not_found)?;
+    let user = expire_outdated_password(&state.db, &realm, user).await?;
+    // Temporary passwords are only ever exchanged for one of the user's own, through the first party login
+    if user.is_temp_password {
+        return Ok(retry(StatusCode::FORBIDDEN, &AuthenticateError::PasswordChangeRequired.to_string()));
+    }
+    if requires_two_factor(&client, &user) {
+        let challenge = match issue_two_factor_challenge(&state.db, state.mailer.as_ref(), &realm, &client, &user).await {
+            Ok(challenge) => challenge,
+            Err(Error::Authenticate(err)) => return Ok(retry(StatusCode::FORBIDDEN, &err.to_string())),
+            Err(err) => return Err(err),
+        };
+        // Setting up two-factor authentication only happens through the first party login, with the mailed token
+        if challenge.enrollment_email_sent {
+            let error = format!("{}, we sent you an email to set it up", AuthenticateError::TwoFactorRequired);
+            return Ok(retry(StatusCode::FORBIDDEN, &error));
+        }
+        let step = Step::TwoFactor {
+            two_factor_token: &challenge.two_factor_token,
+            authenticator_app: user.two_factor_enabled_at.is_some(),
+            webauthn: challenge.webauthn.as_ref(),
+        };
+        return Ok(login_page(realm_id, &client, request, StatusCode::OK, None, step));
+    }
+
+    finish_authorization(state.as_ref(), session_info, &realm, &client, &user, request, false).await
+}
+
+pub async fn authorize_two_factor(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path(realm_id): Path<Uuid>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(payload): Form<AuthorizeTwoFactorRequest>,
+) -> Result<Response, Error> {
+    let request = &payload.request;
+    let client = find_authorizing_client(&state.db, realm_id, request).await?;
+    if let Err(err) = validate_authorize_request(&client, request) {
+        return authorization_error(request, err);
+    }
+    let start_over = |error: &str| {
+        login_page(
+            realm_id,
+            &client,
+            request,
+            StatusCode::UNAUTHORIZED,
+            Some(error),
+            Step::Password { email: None },
+        )
+    };
+    if cookie(&cookies, CSRF_COOKIE) != Some(payload.csrf_token.as_str()) {
+        return Ok(start_over(SIGN_IN_EXPIRED));
+    }
+
+    let claims = match action_token::decode(&state.db, &payload.two_factor_token, &client, Action::TwoFactor).await {
+        Ok(claims) => claims,
+        Err(_) => return Ok(start_over(SIGN_IN_EXPIRED)),
+    };
+    let Some(user) = user::Entity::find_by_id(claims.sub)
+        .filter(user::Column::RealmId.eq(realm_id))
+        .one(&state.db)
+        .await?
+        .filter(|user| claims.holds_for(user))
+    else {
+        return Ok(start_over(SIGN_IN_EXPIRED));
+    };
+    let try_again = |error: &str, webauthn: Option<RequestOptions>| {
+        let step = Step::TwoFactor {
+            two_factor_token: &payload.two_factor_token,
+            authenticator_app: user.two_factor_enabled_at.is_some(),
+            webauthn: webauthn.as_ref(),
+        };
+        login_page(realm_id, &client, request, StatusCode::UNAUTHORIZED, Some(error), step)
+    };
+    let Ok(webauthn) = payload
+        .webauthn
+        .as_deref()
+        .filter(|webauthn| !webauthn.is_empty())
+        .map(serde_json::from_str::<AssertionCredential>)
+        .transpose()
+    else {
+        let webauthn = start_second_factor_authentication(&state.db, &client, &user).await?;
+        return Ok(try_again(&AuthenticateError::InvalidPasskey.to_string(), webauthn));
+    };
+    // The page only finishes signing in, setting up two-factor authentication is left to the first party login
+    let verify_request = VerifyTwoFactorRequest {
+        two_factor_token: payload.two_factor_token.clone(),
+        code: payload
+            .code
+            .clone()
+            .filter(|code| !code.is_empty() && user.two_factor_enabled_at.is_some()),
+        recovery_code: payload
+            .recovery_code
+            .clone()
+            .filter(|recovery_code| !recovery_code.is_empty() && user.two_factor_enabled_at.is_some()),
+        webauthn,
+    };
+    match complete_two_factor_challenge(&state.db, &client, &verify_request).await {
+        Ok(_) => {}
+        Err(Error::Authenticate(err @ (AuthenticateError::InvalidTwoFactorCode | AuthenticateError::InvalidPasskey))) => {
+            let webauthn = start_second_factor_authentication(&state.db, &client, &user).await?;
+            return Ok(try_again(&err.to_string(), webauthn));
+        }
+        Err(Error::Authenticate(AuthenticateError::InvalidToken)) => return Ok(start_over(SIGN_IN_EXPIRED)),
+        Err(Error::Authenticate(err)) => return Ok(start_over(&err.to_string())),
+        Err(err) => return Err(err),
+    }
+
+    let (user, _) = authenticate_user_by_id(&state.db, &client, user.id).await?;
+    let realm = realm::Entity::find_by_id(realm_id).one(&state.db).await?.ok_or_else(Error::not_found)?;
+    finish_authorization(state.as_ref(), session_info, &realm, &client, &user, request, true).await
+}
+
+// Until the redirect URI is known to belong to the client, errors must not be sent to it
+async fn find_authorizing_client(db: &DatabaseConnection, realm_id: Uuid, payload: &AuthorizeRequest) -> Result<client::Model, Error> {
+    let client = client::Entity::find_active_by_id(db, payload.client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(|| OAuthError::InvalidRequest("Unknown client".to_string()))?;
+    if !client.redirect_uris.contains(&payload.redirect_uri) {
+        return Err(OAuthError::InvalidRequest("redirect_uri is not registered for this client".to_string()).into());
+    }
+    Ok(client)
+}
+
+fn authorization_error(payload: &AuthorizeRequest, err: OAuthError) -> Result<Response, Error> {
+    let params = [("error", err.error_code()), ("error_description", &err.to_string())];
+    Ok(redirect_with(&payload.redirect_uri, &params, payload.state.as_deref())?.into_response())
+}
+
+// Signs the browser in at Shield along with sending it back to the client with a code
+async fn finish_authorization(
+    state: &AppState,
+    session_info: Arc<SessionInfo>,
+    realm: &realm::Model,
+    client: &client::Model,
+    user: &user::Model,
+    request: &AuthorizeRequest,
+    two_factor: bool,
+) -> Result<Response, Error> {
+    let code = create_authorization_code(&state.db, client, user, session_info, request).await?;
+    let session = session::Entity::find_by_id(code.session_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(Error::not_found)?;
+    let claims = SsoClaims::new(&session, realm.id, two_factor);
+    let token = claims.create_token(realm).map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    let redirect = redirect_with(&request.redirect_uri, &[("code", &code.code)], request.state.as_deref())?;
+    Ok(([(SET_COOKIE, sso::session_cookie(realm.id, &claims, &token))], redirect).into_response())
+}
+
+fn login_page(realm_id: Uuid, client: &client::Model, request: &AuthorizeRequest, status: StatusCode, error: Option<&str>, step: Step) -> Response {
+    let csrf_token = sso::new_csrf_token();
+    let script_nonce = sso::new_csrf_token();
+    let action = format!("{}{}{}", issuer(realm_id), OAUTH_PATH, AUTHORIZE_PATH);
+    let page = LoginPage {
+        action: &action,
+        client_name: &client.name,
+        request,
+        csrf_token: &csrf_token,
+        script_nonce: &script_nonce,
+        error,
+        step,
+    };
+    let headers = [
+        (SET_COOKIE, sso::csrf_cookie(realm_id, &csrf_token)),
+        (CACHE_CONTROL, "no-store".to_string()),
+        // The page must not be framed by other sites, which could trick users into signing in on it
+        (X_FRAME_OPTIONS, "DENY".to_string()),
+        (
+            CONTENT_SECURITY_POLICY,
+            format!(
+                "default-src 'none'; script-src 'nonce-{}'; style-src 'unsafe-inline'; frame-ancestors 'none'",
+                script_nonce
+            ),
+        ),
+    ];
+    (status, headers, Html(page.render())).into_response()
+}
+
+fn cookie<'a>(cookies: &'a Option<TypedHeader<Cookie>>, name: &str) -> Option<&'a str> {
+    cookies.as_ref().and_then(|TypedHeader(cookies)| cookies.get(name))
+}
+
+pub async fn token(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(session_info): Extension<Arc<SessionInfo>>,
+    Path(realm_id): Path<Uuid>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    dpop: DpopKey,
+    Form(payload): Form<TokenRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let grant_type = GrantType::from_str(&payload.grant_type)?;
+    let (client_id, client_secret) = client_credentials(&basic, payload.client_id, payload.client_secret.as_deref());
+    let response = match grant_type {
+        GrantType::AuthorizationCode => {
+            let client = authenticate_client(&state.db, realm_id, client_id, client_secret).await?;
+            exchange_authorization_code(&state.db, &client, &dpop, &payload).await?
+        }
+        GrantType::ClientCredentials => issue_client_credentials_token(&state.db, realm_id, client_id, client_secret, &dpop).await?,
+        GrantType::DeviceCode => {
+            let client = authenticate_client(&state.db, realm_id, client_id, client_secret).await?;
+            exchange_device_code(&state.db, &client, session_info, &dpop, &payload).await?
+        }
+        GrantType::TokenExchange => exchange_token(&state.db, realm_id, client_id, client_secret, session_info, &dpop, &payload).await?,
+    };
+
+    Ok(([(CACHE_CONTROL, "no-store"), (PRAGMA, "no-cache")], Json(response)))
+}
+
+pub async fn introspect(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(payload): Form<IntrospectionRequest>,
+) -> Result<Json<IntrospectionResponse>, Error> {
+    let (client_id, client_secret) = client_credentials(&basic, payload.client_id, payload.client_secret.as_deref());
+    let client = match authenticate_service(&state.db, realm_id, client_id, client_secret).await? {
+        ServiceCaller::ApiUser(_, client) => client,
+        ServiceCaller::Client(client) => client,
+    };
+
+    let response = introspect_token(&state.db, &client, &payload).await?;
+    Ok(Json(response))
+}
+
+pub async fn revoke(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(payload): Form<RevocationRequest>,
+) -> Result<StatusCode, Error> {
+    let (client_id, client_secret) = client_credentials(&basic, payload.client_id, payload.client_secret.as_deref());
+    let client = authenticate_client(&state.db, realm_id, client_id, client_secret).await?;
+
+    revoke_token(&state.db, &client, &payload.token, payload.token_type_hint.as_deref()).await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn device_authorization(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(payload): Form<DeviceAuthorizationRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let (client_id, client_secret) = client_credentials(&basic, payload.client_id, payload.client_secret.as_deref());
+    let client = authenticate_client(&state.db, realm_id, client_id, client_secret).await?;
+    let device_code = create_device_code(&state.db, &client, payload.scope).await?;
+
+    let verification_uri = format!("{}{}{}", issuer(realm_id), OAUTH_PATH, DEVICE_PATH);
+    let mut verification_uri_complete = Url::parse(&verification_uri).map_err(|e| Error::cannot_perform_operation(&e.to_string()))?;
+    verification_uri_complete
+        .query_pairs_mut()
+        .append_pair("user_code", &device_code.user_code);
+
+    let response = DeviceAuthorizationResponse {
+        device_code: device_code.device_code,
+        user_code: device_code.user_code,
+        verification_uri,
+        verification_uri_complete: verification_uri_complete.to_string(),
+        expires_in: (device_code.expires_at.to_utc() - Utc::now()).num_seconds(),
+        interval: DEVICE_CODE_INTERVAL,
+    };
+    Ok(([(CACHE_CONTROL, "no-store"), (PRAGMA, "no-cache")], Json(response)))
+}
+
+fn device_verification_response(device_code: device_code::Model, client: client::Model) -> DeviceVerificationResponse {
+    DeviceVerificationResponse {
+        user_code: device_code.user_code,
+        client_id: client.id,
+        client_name: client.name,
+        scope: device_code.scope,
+        expires_at: device_code.expires_at,
+    }
+}
+
+pub async fn get_device(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+    Query(query): Query<DeviceVerificationQuery>,
+) -> Result<Json<DeviceVerificationResponse>, Error> {
+    let device_code = get_device_code_to_verify(&state.db, realm_id, &user, &query.user_code).await?;
+    let client = get_client_by_id(&state.db, device_code.client_id).await?.ok_or_else(Error::not_found)?;
+
+    Ok(Json(device_verification_response(device_code, client)))
+}
+
+pub async fn verify_device(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+    Json(payload): Json<DeviceVerificationRequest>,
+) -> Result<Json<DeviceVerificationResponse>, Error> {
+    let device_code = verify_device_code(&state.db, realm_id, &user, &payload.user_code, payload.approve).await?;
+    let client = get_client_by_id(&state.db, device_code.client_id).await?.ok_or_else(Error::not_found)?;
+
+    Ok(Json(device_verification_response(device_code, client)))
+}
+
+pub async fn userinfo(user: JwtUser, Extension(state): Extension<Arc<AppState>>, Path(realm_id): Path<Uuid>) -> Result<Json<StandardClaims>, Error> {
+    Ok(Json(get_userinfo(&state.db, realm_id, user.sid).await?))
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, Extension, Json};
+use sea_orm::prelude::Uuid;
+
+use crate::{
+    mappers::{
+        signing_key::{CreateSigningKeyRequest, RotateSigningKeyRequest, SigningKeyResponse},
+        DeleteResponse,
+    },
+    packages::{
+        db::AppState,
+        errors::{AuthenticateError, Error},
+        jwt_token::JwtUser,
+    },
+    services::signing_key::{delete_signing_key_by_id, get_all_signing_keys, insert_next_signing_key, rotate_signing_keys},
+    utils::role_checker::{is_current_realm_admin, is_master_realm_admin},
+};
+
+pub async fn get_signing_keys(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+) -> Result<Json<Vec<SigningKeyResponse>>, Error> {
+    if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
+        let keys = get_all_signing_keys(&state.db, realm_id).await?;
+        Ok(Json(keys.into_iter().map(SigningKeyResponse::from).collect()))
+    } else {
+        Err(Error::Authenticate(AuthenticateError::NoResource))
+    }
+}
+
+pub async fn create_signing_key(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+    Json(payload): Json<CreateSigningKeyRequest>,
+) -> Result<Json<SigningKeyResponse>, Error> {
+    if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
+        let key = insert_next_signing_key(&state.db, realm_id, payload.algorithm).await?;
+        Ok(Json(SigningKeyResponse::from(key)))
+    } else {
+        Err(Error::Authenticate(AuthenticateError::NoResource))
+    }
+}
+
+pub async fn rotate_signing_key(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+    Json(payload): Json<RotateSigningKeyRequest>,
+) -> Result<Json<Vec<SigningKeyResponse>>, Error> {
+    if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
+        let keys = rotate_signing_keys(&state.db, realm_id, payload.algorithm).await?;
+        Ok(Json(keys.into_iter().map(SigningKeyResponse::from).collect()))
+    } else {
+        Err(Error::Authenticate(AuthenticateError::NoResource))
+    }
+}
+
+pub async fn delete_signing_key(
+    user: JwtUser,
+    Extension(state): Extension<Arc<AppState>>,
+    Path((realm_id, key_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DeleteResponse>, Error> {
+    if is_master_realm_admin(&user) || is_current_realm_admin(&user, &realm_id.to_string()) {
+        let result = delete_signing_key_by_id(&state.db, realm_id, key_id).await?;
+        Ok(Json(DeleteResponse {
+            ok: result.rows_affected == 1,
+        }))
+    } else {
+        Err(Error::Authenticate(AuthenticateError::NoResource))
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, Extension, Json};
+use jsonwebtoken::jwk::JwkSet;
+use sea_orm::prelude::Uuid;
+
+use crate::{
+    mappers::{oauth::GrantType, well_known::OpenIdConfiguration},
+    packages::{db::AppState, dpop, errors::Error, jwt_token::issuer, signing_key::KEYS},
+    routes::{
+        oauth::{AUTHORIZE_PATH, DEVICE_AUTHORIZATION_PATH, INTROSPECT_PATH, REVOKE_PATH, TOKEN_PATH, USERINFO_PATH},
+        realm::{OAUTH_PATH, WELL_KNOWN_PATH},
+        well_known::JWKS_PATH,
+    },
+    services::realm::get_realm_by_id,
+};
+
+pub async fn get_jwks(Extension(state): Extension<Arc<AppState>>, Path(realm_id): Path<Uuid>) -> Result<Json<JwkSet>, Error> {
+    let realm = get_realm_by_id(&state.db, realm_id).await?.ok_or_else(Error::not_found)?;
+
+    Ok(Json(KEYS.read().jwks(realm.id)))
+}
+
+pub async fn get_openid_configuration(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(realm_id): Path<Uuid>,
+) -> Result<Json<OpenIdConfiguration>, Error> {
+    let realm = get_realm_by_id(&state.db, realm_id).await?.ok_or_else(Error::not_found)?;
+    let issuer = issuer(realm.id);
+
+    Ok(Json(OpenIdConfiguration {
+        jwks_uri: format!("{}{}{}", issuer, WELL_KNOWN_PATH, JWKS_PATH),
+        authorization_endpoint: Some(format!("{}{}{}", issuer, OAUTH_PATH, AUTHORIZE_PATH)),
+        token_endpoint: Some(format!("{}{}{}", issuer, OAUTH_PATH, TOKEN_PATH)),
+        introspection_endpoint: Some(format!("{}{}{}", issuer, OAUTH_PATH, INTROSPECT_PATH)),
+        revocation_endpoint: Some(format!("{}{}{}", issuer, OAUTH_PATH, REVOKE_PATH)),
+        userinfo_endpoint: Some(format!("{}{}{}", issuer, OAUTH_PATH, USERINFO_PATH)),
+        device_authorization_endpoint: Some(format!("{}{}{}", issuer, OAUTH_PATH, DEVICE_AUTHORIZATION_PATH)),
+        issuer,
+        grant_types_supported: GrantType::SUPPORTED.to_vec(),
+        response_types_supported: vec!["code".to_string()],
+        scopes_supported: ["openid", "profile", "email", "phone"].iter().map(|scope| scope.to_string()).collect(),
+        token_endpoint_auth_methods_supported: vec!["client_secret_basic".to_string(), "client_secret_post".to_string(), "none".to_string()],
+        code_challenge_methods_supported: vec!["S256".to_string()],
+        dpop_signing_alg_values_supported: dpop::SIGNING_ALGORITHMS.to_vec(),
+        subject_types_supported: vec!["public".to_string()],
+        id_token_signing_alg_values_supported: vec![realm.signing_algorithm],
+        claims_supported: [
+            "sub",
+            "sid",
+            "rli",
+            "iss",
+            "exp",
+            "iat",
+            "aud",
+            "auth_time",
+            "nonce",
+            "act",
+            "first_name",
+            "last_name",
+            "given_name",
+            "family_name",
+            "picture",
+            "email",
+            "email_verified",
+            "phone",
+            "phone_number",
+            "resource",
+        ]
+        .iter()
+        .map(|claim| claim.to_string())
+        .collect(),
+    }))
+}
@@ -1,8 +1,15 @@
+use entity::user;
 use sea_orm::prelude::Uuid;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 
-#[derive(Deserialize)]
+use crate::packages::{
+    dpop::Confirmation,
+    webauthn::{AssertionCredential, RegistrationCredential, RequestOptions},
+};
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ResourceSubset {
     pub group_name: String,
     pub identifiers: HashMap<String, String>,
@@ -17,6 +24,137 @@ pub struct CreateUserRequest {
     pub phone: Option<String>,
     pub image: Option<String>,
     pub resource: ResourceSubset,
+    // Users have to replace a temporary password before they get a session, only passwords marked as one are temporary
+    pub is_temp_password: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct SignupRequest {
+    pub email: String,
+    pub password: String,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub phone: Option<String>,
+    pub image: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SignupResponse {
+    pub user: user::Model,
+    pub awaiting_approval: bool,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Serialize)]
+pub struct VerifyEmailResponse {
+    pub ok: bool,
+    pub user_id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+    // Page of the client the reset link opens, must be one of its redirect URIs
+    pub redirect_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize)]
+pub struct PasswordChangeRequiredResponse {
+    pub password_change_required: bool,
+    pub password_change_token: String,
+    pub expires_in: i64, // in seconds
+}
+
+#[derive(Deserialize)]
+pub struct SetPasswordRequest {
+    pub password_change_token: String,
+    pub password: String,
+}
+
+/// What users who have to prove a second factor get from logging in instead of a session.
+/// Users with passkeys get the options to prove it with one of them, instead of a code.
+/// Users of clients requiring two-factor authentication who have set up neither are mailed a token to set it up with.
+#[derive(Serialize)]
+pub struct TwoFactorRequiredResponse {
+    pub two_factor_required: bool,
+    pub two_factor_token: String,
+    pub expires_in: i64, // in seconds
+    pub enrollment_email_sent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webauthn: Option<RequestOptions>,
+}
+
+#[derive(Deserialize)]
+pub struct EnrollTwoFactorRequest {
+    pub token: String,
+}
+
+/// The secret to set up two-factor authentication with, its codes complete the login through `two-factor/verify`.
+#[derive(Serialize)]
+pub struct TwoFactorEnrollmentResponse {
+    #[serde(flatten)]
+    pub setup: TwoFactorSetupResponse,
+    pub two_factor_token: String,
+    pub expires_in: i64, // in seconds
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTwoFactorRequest {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Either a code of the authenticator app, one of the recovery codes or an assertion of one of the user's passkeys.
+#[derive(Deserialize)]
+pub struct VerifyTwoFactorRequest {
+    pub two_factor_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+    pub webauthn: Option<AssertionCredential>,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterPasskeyRequest {
+    #[serde(flatten)]
+    pub credential: RegistrationCredential,
+    pub name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PasswordResponse {
+    pub ok: bool,
+    pub user_id: Uuid,
 }
 
 #[derive(Deserialize)]
@@ -35,6 +173,10 @@ pub struct LogoutResponse {
 #[derive(Deserialize)]
 pub struct IntrospectRequest {
     pub access_token: String,
+    // The DPoP proof a bound token came with and the method and URL of the request it was sent to
+    pub dpop_proof: Option<String>,
+    pub htm: Option<String>,
+    pub htu: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -43,6 +185,7 @@ pub struct IntrospectResponse {
     pub client_id: Uuid,
     pub sub: Uuid,
     pub first_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub last_name: Option<String>,
     pub token_type: String,
     pub exp: usize,
@@ -51,6 +194,11 @@ pub struct IntrospectResponse {
     pub client_name: String,
     pub resource_group: String,
     pub resources: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+    // Claims the client's claim mappers add to its tokens
+    #[serde(flatten)]
+    pub claims: Map<String, Value>,
 }
 
 #[derive(Deserialize)]
@@ -61,6 +209,7 @@ pub struct RefreshTokenRequest {
 #[derive(Serialize)]
 pub struct RefreshTokenResponse {
     pub access_token: String,
+    pub id_token: String,
     pub refresh_token: String,
     pub expires_in: usize,
 }
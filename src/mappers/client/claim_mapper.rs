@@ -0,0 +1,21 @@
+use entity::sea_orm_active_enums::ClaimMapperType;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Deserialize)]
+pub struct CreateClaimMapperRequest {
+    pub mapper_type: ClaimMapperType,
+    pub claim_name: String,
+    pub source: Option<String>,
+    pub value: Option<Value>,
+    pub multivalued: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateClaimMapperRequest {
+    pub mapper_type: Option<ClaimMapperType>,
+    pub claim_name: Option<String>,
+    pub source: Option<String>,
+    pub value: Option<Value>,
+    pub multivalued: Option<bool>,
+}
@@ -1,11 +1,27 @@
 pub mod api_user;
+pub mod claim_mapper;
+use entity::sea_orm_active_enums::ClientType;
 use sea_orm::prelude::Uuid;
 use serde::Deserialize;
 
+use super::auth::ResourceSubset;
+
 #[derive(Deserialize)]
 pub struct CreateClientRequest {
     pub name: String,
     pub realm_id: Uuid,
+    pub client_type: Option<ClientType>,
+    pub redirect_uris: Option<Vec<String>>,
+    pub use_stateful_access_token: Option<bool>,
+    pub audiences: Option<Vec<String>>,
+    pub token_exchange_audiences: Option<Vec<Uuid>>,
+    pub require_dpop: Option<bool>,
+    pub allow_self_registration: Option<bool>,
+    pub registration_template: Option<ResourceSubset>,
+    pub registration_email_domains: Option<Vec<String>>,
+    pub require_registration_approval: Option<bool>,
+    pub require_verified_email: Option<bool>,
+    pub require_two_factor: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -14,6 +30,19 @@ pub struct UpdateClientRequest {
     pub lock: Option<bool>,
     pub max_concurrent_sessions: Option<i32>,
     pub session_lifetime: Option<i32>,       // in seconds
+    pub session_idle_timeout: Option<i32>,   // in seconds
+    pub access_token_lifetime: Option<i32>,  // in seconds
     pub refresh_token_lifetime: Option<i32>, // in seconds
-    pub refresh_token_reuse_limit: Option<i32>,
+    pub client_type: Option<ClientType>,
+    pub redirect_uris: Option<Vec<String>>,
+    pub use_stateful_access_token: Option<bool>,
+    pub audiences: Option<Vec<String>>,
+    pub token_exchange_audiences: Option<Vec<Uuid>>,
+    pub require_dpop: Option<bool>,
+    pub allow_self_registration: Option<bool>,
+    pub registration_template: Option<ResourceSubset>,
+    pub registration_email_domains: Option<Vec<String>>,
+    pub require_registration_approval: Option<bool>,
+    pub require_verified_email: Option<bool>,
+    pub require_two_factor: Option<bool>,
 }
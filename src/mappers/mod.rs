@@ -2,8 +2,11 @@ use serde::Serialize;
 
 pub mod auth;
 pub mod client;
+pub mod oauth;
 pub mod realm;
+pub mod signing_key;
 pub mod user;
+pub mod well_known;
 
 #[derive(Serialize)]
 pub struct DeleteResponse {
@@ -0,0 +1,224 @@
+use std::str::FromStr;
+
+use entity::sea_orm_active_enums::{ApiUserAccess, ApiUserRole};
+use sea_orm::prelude::{DateTimeWithTimeZone, Uuid};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::packages::{dpop::Confirmation, errors::OAuthError, jwt_token::Actor};
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantType {
+    AuthorizationCode,
+    ClientCredentials,
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
+    DeviceCode,
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:token-exchange")]
+    TokenExchange,
+}
+
+impl GrantType {
+    pub const SUPPORTED: [GrantType; 4] = [
+        GrantType::AuthorizationCode,
+        GrantType::ClientCredentials,
+        GrantType::DeviceCode,
+        GrantType::TokenExchange,
+    ];
+}
+
+// RFC 8693: only access tokens can be exchanged and issued
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+// Parsed by hand so that unknown grants surface as `unsupported_grant_type` rather than a form rejection
+impl FromStr for GrantType {
+    type Err = OAuthError;
+
+    fn from_str(grant_type: &str) -> Result<Self, Self::Err> {
+        match grant_type {
+            "authorization_code" => Ok(GrantType::AuthorizationCode),
+            "client_credentials" => Ok(GrantType::ClientCredentials),
+            "urn:ietf:params:oauth:grant-type:device_code" => Ok(GrantType::DeviceCode),
+            "urn:ietf:params:oauth:grant-type:token-exchange" => Ok(GrantType::TokenExchange),
+            _ => Err(OAuthError::UnsupportedGrantType),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: Uuid,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+}
+
+impl AuthorizeRequest {
+    /// The parameters of the request, which the pages of the authorization carry along.
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let params = [
+            ("response_type", Some(self.response_type.clone())),
+            ("client_id", Some(self.client_id.to_string())),
+            ("redirect_uri", Some(self.redirect_uri.clone())),
+            ("scope", self.scope.clone()),
+            ("state", self.state.clone()),
+            ("nonce", self.nonce.clone()),
+            ("code_challenge", self.code_challenge.clone()),
+            ("code_challenge_method", self.code_challenge_method.clone()),
+        ];
+        params.into_iter().filter_map(|(name, value)| value.map(|value| (name, value))).collect()
+    }
+}
+
+/// Sign in form of Shield's authorization page, which is how users authenticate to authorize a client.
+#[derive(Deserialize)]
+pub struct AuthorizeLoginRequest {
+    #[serde(flatten)]
+    pub request: AuthorizeRequest,
+    pub csrf_token: String,
+    pub email: String,
+    pub password: String,
+}
+
+/// Second step of the sign in, for users who have to prove a second factor.
+/// The passkey assertion comes as the JSON the page's script put in the form.
+#[derive(Deserialize)]
+pub struct AuthorizeTwoFactorRequest {
+    #[serde(flatten)]
+    pub request: AuthorizeRequest,
+    pub csrf_token: String,
+    pub two_factor_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+    pub webauthn: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<Uuid>,
+    pub client_secret: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub device_code: Option<String>,
+    pub subject_token: Option<String>,
+    pub subject_token_type: Option<String>,
+    pub requested_token_type: Option<String>,
+    // Not part of RFC 8693, names the user an admin impersonates
+    pub requested_subject: Option<Uuid>,
+    pub audience: Option<Uuid>,
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceAuthorizationRequest {
+    pub client_id: Option<Uuid>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceVerificationQuery {
+    pub user_code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceVerificationRequest {
+    pub user_code: String,
+    pub approve: bool,
+}
+
+#[derive(Serialize)]
+pub struct DeviceVerificationResponse {
+    pub user_code: String,
+    pub client_id: Uuid,
+    pub client_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    pub expires_at: DateTimeWithTimeZone,
+}
+
+#[derive(Deserialize)]
+pub struct IntrospectionRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<Uuid>,
+    pub client_secret: Option<String>,
+    // Not part of RFC 7662, the DPoP proof a bound token came with and the method and URL of the request it was sent to
+    pub dpop_proof: Option<String>,
+    pub htm: Option<String>,
+    pub htu: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RevocationRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<Uuid>,
+    pub client_secret: Option<String>,
+}
+
+// RFC 7662: an inactive token is reported as `{"active": false}` and nothing else
+#[derive(Serialize, Default)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<ApiUserRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<ApiUserAccess>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+    // Claims the client's claim mappers added to the token
+    #[serde(flatten)]
+    pub claims: Map<String, Value>,
+}
@@ -1,5 +1,8 @@
+use entity::sea_orm_active_enums::SigningAlgorithm;
 use serde::Deserialize;
 
+use crate::packages::password_policy::PasswordPolicy;
+
 #[derive(Deserialize)]
 pub struct CreateRealmRequest {
     pub name: String,
@@ -12,5 +15,6 @@ pub struct UpdateRealmRequest {
     pub max_concurrent_sessions: Option<i32>,
     pub session_lifetime: Option<i32>,       // in seconds
     pub refresh_token_lifetime: Option<i32>, // in seconds
-    pub refresh_token_reuse_limit: Option<i32>,
+    pub signing_algorithm: Option<SigningAlgorithm>,
+    pub password_policy: Option<PasswordPolicy>,
 }
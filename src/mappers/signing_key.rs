@@ -0,0 +1,40 @@
+use entity::{
+    sea_orm_active_enums::{SigningAlgorithm, SigningKeyStatus},
+    signing_key,
+};
+use sea_orm::prelude::{DateTimeWithTimeZone, Uuid};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct CreateSigningKeyRequest {
+    pub algorithm: SigningAlgorithm,
+}
+
+#[derive(Deserialize)]
+pub struct RotateSigningKeyRequest {
+    pub algorithm: SigningAlgorithm,
+}
+
+// Never expose the private key material
+#[derive(Serialize)]
+pub struct SigningKeyResponse {
+    pub id: Uuid,
+    pub algorithm: SigningAlgorithm,
+    pub status: SigningKeyStatus,
+    pub expires_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+impl From<signing_key::Model> for SigningKeyResponse {
+    fn from(key: signing_key::Model) -> Self {
+        Self {
+            id: key.id,
+            algorithm: key.algorithm,
+            status: key.status,
+            expires_at: key.expires_at,
+            created_at: key.created_at,
+            updated_at: key.updated_at,
+        }
+    }
+}
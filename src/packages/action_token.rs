@@ -0,0 +1,102 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use entity::{client, realm, user};
+use jsonwebtoken::errors::Error as JwtError;
+use sea_orm::{prelude::Uuid, ConnectionTrait};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    errors::{AuthenticateError, Error},
+    jwt_token, signing_key,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    VerifyEmail,
+    ResetPassword,
+    ChangeTemporaryPassword,
+    TwoFactor,
+    EnrollTwoFactor,
+}
+
+/// Token handed to a user to act on their account through a client, by email or in place of a session.
+/// It only holds while the account is still in the state it was issued for, so it can be used once.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActionTokenClaims {
+    pub exp: usize,  // Expiration time (as UTC timestamp)
+    pub iat: usize,  // Issued at (as UTC timestamp)
+    pub iss: String, // Issuer
+    pub aud: Uuid,   // Audience --> Client ID
+    pub sub: Uuid,   // Subject --> User ID
+    pub rli: Uuid,   // Realm ID
+    pub email: String,
+    pub action: Action,
+    // Hash of the password a password token was issued against, setting a new password uses it up.
+    // Two-factor tokens also hash the lockout, so locking the user out voids the tokens issued before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+impl ActionTokenClaims {
+    pub fn new(user: &user::Model, client: &client::Model, action: Action, lifetime: i64) -> Self {
+        let now = chrono::Local::now().timestamp();
+        Self {
+            exp: (now + lifetime) as usize,
+            iat: now as usize,
+            iss: jwt_token::issuer(user.realm_id),
+            aud: client.id,
+            sub: user.id,
+            rli: user.realm_id,
+            email: user.email.clone(),
+            action,
+            fingerprint: fingerprint(user, action),
+        }
+    }
+
+    /// Whether the user is still in the state the token was issued for.
+    pub fn holds_for(&self, user: &user::Model) -> bool {
+        if user.id != self.sub || user.email != self.email {
+            return false;
+        }
+        match self.action {
+            Action::VerifyEmail => user.email_verified_at.is_none(),
+            Action::ResetPassword | Action::TwoFactor => self.fingerprint == fingerprint(user, self.action),
+            Action::ChangeTemporaryPassword => user.is_temp_password && self.fingerprint == fingerprint(user, self.action),
+            Action::EnrollTwoFactor => user.two_factor_enabled_at.is_none() && self.fingerprint == fingerprint(user, self.action),
+        }
+    }
+
+    pub fn create_token(&self, realm: &realm::Model) -> Result<String, JwtError> {
+        signing_key::sign(&self, realm)
+    }
+}
+
+/// Verifies a token the client issued for the action, tokens of other actions or clients are rejected.
+pub async fn decode<C: ConnectionTrait>(db: &C, token: &str, client: &client::Model, action: Action) -> Result<ActionTokenClaims, Error> {
+    signing_key::verify::<ActionTokenClaims, C>(db, token, client.realm_id, Some(client.id))
+        .await
+        .ok()
+        .map(|token_data| token_data.claims)
+        .filter(|claims| claims.action == action)
+        .ok_or(Error::Authenticate(AuthenticateError::InvalidToken))
+}
+
+fn fingerprint(user: &user::Model, action: Action) -> Option<String> {
+    let mut hasher = Sha256::new();
+    match action {
+        Action::VerifyEmail => return None,
+        Action::ResetPassword | Action::ChangeTemporaryPassword | Action::EnrollTwoFactor => {
+            hasher.update(user.password_hash.as_deref().unwrap_or_default())
+        }
+        Action::TwoFactor => {
+            hasher.update(user.password_hash.as_deref().unwrap_or_default());
+            hasher.update(
+                user.two_factor_locked_until
+                    .map(|locked_until| locked_until.to_rfc3339())
+                    .unwrap_or_default(),
+            );
+        }
+    }
+    Some(URL_SAFE_NO_PAD.encode(hasher.finalize()))
+}
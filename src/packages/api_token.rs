@@ -1,5 +1,4 @@
-use jsonwebtoken::{errors::Error as JwtError, DecodingKey, EncodingKey, Header, TokenData, Validation};
-use once_cell::sync::Lazy;
+use jsonwebtoken::{errors::Error as JwtError, TokenData};
 use sea_orm::{
     prelude::{DateTimeWithTimeZone, Uuid},
     DatabaseConnection,
@@ -7,18 +6,16 @@ use sea_orm::{
 use serde::{Deserialize, Serialize};
 
 use entity::{
-    api_user, client, refresh_token,
+    api_user, client, realm, refresh_token,
     sea_orm_active_enums::{ApiUserAccess, ApiUserRole},
 };
 
 use super::{
+    dpop::Confirmation,
     errors::{AuthenticateError, Error},
-    settings::SETTINGS,
+    jwt_token, signing_key,
 };
 
-static VALIDATION: Lazy<Validation> = Lazy::new(Validation::default);
-static HEADER: Lazy<Header> = Lazy::new(Header::default);
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiUser {
     pub id: Uuid,
@@ -53,8 +50,11 @@ impl ApiUser {
         let id = parts[0]
             .parse::<Uuid>()
             .map_err(|_| Error::Authenticate(AuthenticateError::InvalidApiCredentials))?;
-        let secret = parts[1];
 
+        Self::validate(db, id, parts[1]).await
+    }
+
+    pub async fn validate(db: &DatabaseConnection, id: Uuid, secret: &str) -> Result<ApiUser, Error> {
         let api_user = api_user::Entity::find_active_by_id(db, id).await?;
         if api_user.is_none() {
             return Err(Error::Authenticate(AuthenticateError::InvalidApiCredentials));
@@ -79,11 +79,71 @@ impl ApiUser {
     }
 }
 
+/// Claims of the access token issued by the `client_credentials` grant.
+/// Role and access are only present when an api user, rather than the client itself, requested the token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceClaims {
+    pub exp: usize,  // Expiration time (as UTC timestamp). validate_exp defaults to true in validation
+    pub iat: usize,  // Issued at (as UTC timestamp)
+    pub iss: String, // Issuer
+    #[serde(default)]
+    pub aud: Vec<String>, // Audience --> Client ID followed by the client's audiences
+    pub sub: Uuid,   // Subject --> Api User ID or Client ID
+    pub rli: Uuid,   // Realm ID
+    pub cli: Uuid,   // Client ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<ApiUserRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<ApiUserAccess>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+impl ServiceClaims {
+    pub fn from_api_user(api_user: &ApiUser, client: &client::Model) -> Self {
+        let now = chrono::Local::now().timestamp();
+        Self {
+            exp: (now + client.access_token_lifetime as i64).min(api_user.expires.timestamp()) as usize,
+            iat: now as usize,
+            iss: jwt_token::issuer(api_user.realm_id),
+            aud: jwt_token::audience(client),
+            sub: api_user.id,
+            rli: api_user.realm_id,
+            cli: api_user.client_id,
+            role: Some(api_user.role.clone()),
+            access: Some(api_user.access.clone()),
+            cnf: None,
+        }
+    }
+
+    pub fn from_client(client: &client::Model) -> Self {
+        let now = chrono::Local::now().timestamp();
+        Self {
+            exp: (now + client.access_token_lifetime as i64) as usize,
+            iat: now as usize,
+            iss: jwt_token::issuer(client.realm_id),
+            aud: jwt_token::audience(client),
+            sub: client.id,
+            rli: client.realm_id,
+            cli: client.id,
+            role: None,
+            access: None,
+            cnf: None,
+        }
+    }
+
+    pub fn create_token(&self, realm: &realm::Model) -> Result<String, JwtError> {
+        signing_key::sign(&self, realm)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefreshTokenClaims {
     pub exp: usize,  // Expiration time (as UTC timestamp). validate_exp defaults to true in validation
     pub iat: usize,  // Issued at (as UTC timestamp)
     pub iss: String, // Issuer
+    #[serde(default)]
+    pub aud: Vec<String>, // Audience --> Client ID, refresh tokens are only ever redeemed by the client they were issued to
     pub sid: Uuid,   // Session ID
     pub sub: Uuid,   // Subject --> Refresh Token ID
     pub rli: Uuid,   // Realm ID
@@ -97,20 +157,23 @@ impl RefreshTokenClaims {
             iat: chrono::Local::now().timestamp() as usize,
             sub: refresh_token.id,
             sid: refresh_token.user_id,
-            iss: SETTINGS.read().server.host.clone(),
+            iss: jwt_token::issuer(refresh_token.realm_id),
+            aud: vec![client.id.to_string()],
             cli: client.id,
             rli: refresh_token.realm_id,
         }
     }
 
-    pub fn create_token(&self, secret: &str) -> Result<String, jsonwebtoken::errors::Error> {
-        let encoding_key = EncodingKey::from_secret(secret.as_ref());
-        jsonwebtoken::encode(&HEADER, &self, &encoding_key)
+    pub fn create_token(&self, realm: &realm::Model) -> Result<String, JwtError> {
+        signing_key::sign(&self, realm)
     }
 }
 
-pub fn decode_refresh_token(token: &str, secret: &str) -> Result<TokenData<RefreshTokenClaims>, JwtError> {
-    let decoding_key = DecodingKey::from_secret(secret.as_ref());
-
-    jsonwebtoken::decode::<RefreshTokenClaims>(token, &decoding_key, &VALIDATION)
+pub async fn decode_refresh_token(
+    db: &DatabaseConnection,
+    token: &str,
+    realm_id: Uuid,
+    client_id: Uuid,
+) -> Result<TokenData<RefreshTokenClaims>, JwtError> {
+    signing_key::verify::<RefreshTokenClaims, DatabaseConnection>(db, token, realm_id, Some(client_id)).await
 }
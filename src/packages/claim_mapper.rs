@@ -0,0 +1,136 @@
+use entity::{claim_mapper, resource, sea_orm_active_enums::ClaimMapperType, user};
+use serde_json::{Map, Value};
+
+use super::errors::Error;
+
+// Profile claims a client may leave out of its tokens. `first_name` and `resource` always stay, the first tells
+// user tokens apart from other tokens of the realm and Shield authorizes its own API with the second
+pub const OMITTABLE_CLAIMS: [&str; 3] = ["last_name", "email", "phone"];
+// User fields a mapper can copy into a claim
+pub const USER_ATTRIBUTES: [&str; 6] = ["first_name", "last_name", "email", "email_verified", "phone", "image"];
+// Claims set by Shield itself, as well as the fields of the introspection responses mapped claims end up in
+const RESERVED_CLAIMS: [&str; 25] = [
+    "exp",
+    "iat",
+    "nbf",
+    "jti",
+    "sub",
+    "sid",
+    "rli",
+    "cli",
+    "iss",
+    "aud",
+    "act",
+    "cnf",
+    "nonce",
+    "auth_time",
+    "first_name",
+    "last_name",
+    "email",
+    "phone",
+    "resource",
+    "active",
+    "client_id",
+    "username",
+    "token_type",
+    "scope",
+    "role",
+];
+
+/// Checks that the mapper can be applied to tokens, so that a broken rule is rejected up front rather than silently skipped.
+pub fn validate(mapper: &claim_mapper::Model) -> Result<(), Error> {
+    let claim_name = mapper.claim_name.as_str();
+    if claim_name.is_empty() {
+        return Err(Error::cannot_perform_operation("Claim name cannot be empty"));
+    }
+
+    match mapper.mapper_type {
+        ClaimMapperType::Omit => {
+            if !OMITTABLE_CLAIMS.contains(&claim_name) {
+                return Err(Error::cannot_perform_operation(&format!(
+                    "Only {} can be omitted",
+                    OMITTABLE_CLAIMS.join(", ")
+                )));
+            }
+            return Ok(());
+        }
+        ClaimMapperType::Resource => {
+            if mapper.source.as_deref().unwrap_or_default().is_empty() {
+                return Err(Error::cannot_perform_operation("Resource mappers need the resource name as source"));
+            }
+        }
+        ClaimMapperType::UserAttribute => {
+            if !mapper.source.as_deref().is_some_and(|source| USER_ATTRIBUTES.contains(&source)) {
+                return Err(Error::cannot_perform_operation(&format!(
+                    "User attribute mappers need one of {} as source",
+                    USER_ATTRIBUTES.join(", ")
+                )));
+            }
+        }
+        ClaimMapperType::Static => {
+            if mapper.value.is_none() {
+                return Err(Error::cannot_perform_operation("Static mappers need a value"));
+            }
+        }
+    }
+
+    if RESERVED_CLAIMS.contains(&claim_name) {
+        return Err(Error::cannot_perform_operation(&format!("Claim {} is reserved", claim_name)));
+    }
+    Ok(())
+}
+
+/// Whether the client's mappers leave the claim out of its tokens.
+pub fn omits(mappers: &[claim_mapper::Model], claim_name: &str) -> bool {
+    mappers
+        .iter()
+        .any(|mapper| mapper.mapper_type == ClaimMapperType::Omit && mapper.claim_name == claim_name)
+}
+
+/// Claims the client's mappers add on top of the default ones. Resources the user does not have are skipped, as are
+/// mappers saved before their claim was reserved, so they can never clash with a claim Shield sets.
+pub fn mapped_claims(mappers: &[claim_mapper::Model], user: &user::Model, resources: &[resource::Model]) -> Map<String, Value> {
+    let mut claims = Map::new();
+    for mapper in mappers.iter().filter(|mapper| !RESERVED_CLAIMS.contains(&mapper.claim_name.as_str())) {
+        let value = match mapper.mapper_type {
+            ClaimMapperType::Resource => resource_value(mapper, resources),
+            ClaimMapperType::UserAttribute => user_attribute(user, mapper.source.as_deref().unwrap_or_default()),
+            ClaimMapperType::Static => mapper.value.clone(),
+            ClaimMapperType::Omit => None,
+        };
+
+        if let Some(value) = value {
+            claims.insert(mapper.claim_name.clone(), value);
+        }
+    }
+    claims
+}
+
+fn resource_value(mapper: &claim_mapper::Model, resources: &[resource::Model]) -> Option<Value> {
+    let resource = resources.iter().find(|resource| mapper.source.as_ref() == Some(&resource.name))?;
+    if !mapper.multivalued {
+        return Some(Value::String(resource.value.clone()));
+    }
+
+    // Resources are unique by name, so several values share one resource separated by commas
+    let values = resource
+        .value
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| Value::String(value.to_string()))
+        .collect();
+    Some(Value::Array(values))
+}
+
+fn user_attribute(user: &user::Model, attribute: &str) -> Option<Value> {
+    match attribute {
+        "first_name" => Some(Value::String(user.first_name.clone())),
+        "last_name" => user.last_name.clone().map(Value::String),
+        "email" => Some(Value::String(user.email.clone())),
+        "email_verified" => Some(Value::Bool(user.email_verified_at.is_some())),
+        "phone" => user.phone.clone().map(Value::String),
+        "image" => user.image.clone().map(Value::String),
+        _ => None,
+    }
+}
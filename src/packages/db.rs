@@ -1,13 +1,17 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use migration::{Migrator, MigratorTrait};
 use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbBackend, DbErr, Statement};
 
-use super::settings::SETTINGS;
+use super::{
+    mailer::{self, Mailer},
+    settings::SETTINGS,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DatabaseConnection,
+    pub mailer: Arc<dyn Mailer>,
 }
 
 pub async fn get_db_connection_pool() -> Result<AppState, DbErr> {
@@ -59,5 +63,8 @@ pub async fn get_db_connection_pool() -> Result<AppState, DbErr> {
 
     Migrator::up(&db, None).await?;
 
-    Ok(AppState { db })
+    Ok(AppState {
+        db,
+        mailer: mailer::from_settings(),
+    })
 }
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::OriginalUri,
+    http::{request::Parts, HeaderMap},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use entity::client;
+use jsonwebtoken::{jwk::Jwk, Algorithm, DecodingKey, Validation};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::{errors::OAuthError, settings::SETTINGS};
+
+pub const DPOP_HEADER: &str = "DPoP";
+// Only asymmetric algorithms prove possession of a key, see RFC 9449 section 4.2
+pub const SIGNING_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::EdDSA,
+];
+// Proofs are accepted this long after they were created, or this far ahead to allow for clock skew
+const PROOF_LIFETIME: i64 = 60; // in seconds
+
+// JWT ID of the proofs already used -> when they were created, kept until they could not be accepted anyway
+static USED_PROOFS: Lazy<RwLock<HashMap<String, i64>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Confirmation claim of RFC 7800, binds a token to the thumbprint of the DPoP key of its holder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Confirmation {
+    pub jkt: String,
+}
+
+impl Confirmation {
+    pub fn from(jkt: Option<String>) -> Option<Self> {
+        jkt.map(|jkt| Self { jkt })
+    }
+}
+
+/// Thumbprint of the key the request proved possession of with its `DPoP` header, `None` when it carries no proof.
+pub struct DpopKey(pub Option<String>);
+
+impl DpopKey {
+    /// The key the tokens issued to the client are bound to, clients requiring DPoP get no unbound tokens.
+    pub fn binding(&self, client: &client::Model) -> Result<Option<String>, OAuthError> {
+        if client.require_dpop && self.0.is_none() {
+            return Err(OAuthError::InvalidDpopProof("The client requires a DPoP proof".to_string()));
+        }
+        Ok(self.0.clone())
+    }
+}
+
+#[derive(Deserialize)]
+struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: i64,
+    ath: Option<String>,
+}
+
+/// Verifies a DPoP proof as described in RFC 9449 section 4.3 and returns the thumbprint of the key it was signed with.
+/// A proof sent along with an access token has to be bound to it.
+pub fn verify(proof: &str, htm: &str, htu: &str, access_token: Option<&str>) -> Result<String, OAuthError> {
+    let invalid = |reason: &str| OAuthError::InvalidDpopProof(reason.to_string());
+
+    let header = jsonwebtoken::decode_header(proof).map_err(|_| invalid("Malformed DPoP proof"))?;
+    if header.typ.as_deref() != Some("dpop+jwt") {
+        return Err(invalid("DPoP proof must be of type dpop+jwt"));
+    }
+    if !SIGNING_ALGORITHMS.contains(&header.alg) {
+        return Err(invalid("DPoP proof algorithm is not supported"));
+    }
+    let jwk = public_jwk(proof).ok_or_else(|| invalid("DPoP proof must carry a public JWK"))?;
+    let jkt = thumbprint(&jwk).ok_or_else(|| invalid("DPoP proof key is not supported"))?;
+    let key = serde_json::from_value::<Jwk>(jwk)
+        .ok()
+        .and_then(|jwk| DecodingKey::from_jwk(&jwk).ok())
+        .ok_or_else(|| invalid("DPoP proof key is not supported"))?;
+
+    // Proofs have no expiry of their own, their age is checked against `iat` instead
+    let mut validation = Validation::new(header.alg);
+    validation.required_spec_claims.clear();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    let claims = jsonwebtoken::decode::<ProofClaims>(proof, &key, &validation)
+        .map_err(|_| invalid("Invalid DPoP proof"))?
+        .claims;
+
+    if claims.htm != htm || !is_same_uri(&claims.htu, htu) {
+        return Err(invalid("DPoP proof was created for another request"));
+    }
+    let now = chrono::Utc::now().timestamp();
+    if (now - claims.iat).abs() > PROOF_LIFETIME {
+        return Err(invalid("DPoP proof has expired"));
+    }
+    if let Some(access_token) = access_token {
+        if claims.ath.as_deref() != Some(access_token_hash(access_token).as_str()) {
+            return Err(invalid("DPoP proof is not bound to the access token"));
+        }
+    }
+    if !mark_used(claims.jti, claims.iat, now) {
+        return Err(invalid("DPoP proof has already been used"));
+    }
+
+    Ok(jkt)
+}
+
+/// Whether the request a bound token was sent with proves possession of its key.
+/// Resource servers relying on introspection forward the proof along with the method and URL of the request it came with.
+pub fn proves_possession(cnf: &Confirmation, access_token: &str, proof: Option<&str>, htm: Option<&str>, htu: Option<&str>) -> bool {
+    let (Some(proof), Some(htm), Some(htu)) = (proof, htm, htu) else {
+        return false;
+    };
+    verify(proof, htm, htu, Some(access_token)).is_ok_and(|jkt| jkt == cnf.jkt)
+}
+
+/// The proof of the request, which may carry at most one.
+pub fn proof(headers: &HeaderMap) -> Result<Option<&str>, OAuthError> {
+    let mut proofs = headers.get_all(DPOP_HEADER).iter();
+    match (proofs.next(), proofs.next()) {
+        (None, _) => Ok(None),
+        (Some(proof), None) => Ok(Some(
+            proof
+                .to_str()
+                .map_err(|_| OAuthError::InvalidDpopProof("Malformed DPoP proof".to_string()))?,
+        )),
+        (Some(_), Some(_)) => Err(OAuthError::InvalidDpopProof("Only one DPoP proof can be sent".to_string())),
+    }
+}
+
+/// The URL proofs of the request have to be created for. Routers nested under a realm only see the rest of the path.
+pub fn request_uri(parts: &Parts) -> String {
+    let path = match parts.extensions.get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => parts.uri.path(),
+    };
+    format!("{}{}", SETTINGS.read().server.host, path)
+}
+
+pub fn token_type(bound: bool) -> String {
+    if bound { "DPoP" } else { "Bearer" }.to_string()
+}
+
+fn access_token_hash(access_token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes()))
+}
+
+// The parsed header drops unknown members, so the key is read from the raw header to make sure it has no private part
+fn public_jwk(proof: &str) -> Option<Value> {
+    let header = URL_SAFE_NO_PAD.decode(proof.split('.').next()?).ok()?;
+    let jwk = serde_json::from_slice::<Value>(&header).ok()?.get("jwk")?.clone();
+    match jwk.get("d") {
+        Some(_) => None,
+        None => Some(jwk),
+    }
+}
+
+// RFC 7638: the hash of the required members of the key, in lexicographic order and without whitespace
+fn thumbprint(jwk: &Value) -> Option<String> {
+    let members: &[&str] = match jwk.get("kty")?.as_str()? {
+        "EC" => &["crv", "kty", "x", "y"],
+        "RSA" => &["e", "kty", "n"],
+        "OKP" => &["crv", "kty", "x"],
+        _ => return None,
+    };
+    let members = members
+        .iter()
+        .map(|member| Some(format!("\"{}\":{}", member, serde_json::to_string(jwk.get(*member)?.as_str()?).ok()?)))
+        .collect::<Option<Vec<String>>>()?;
+
+    Some(URL_SAFE_NO_PAD.encode(Sha256::digest(format!("{{{}}}", members.join(",")))))
+}
+
+// The query and fragment are not part of `htu`, see RFC 9449 section 4.3
+fn is_same_uri(htu: &str, expected: &str) -> bool {
+    let normalize = |uri: &str| {
+        Url::parse(uri).ok().map(|mut url| {
+            url.set_query(None);
+            url.set_fragment(None);
+            url
+        })
+    };
+    normalize(htu).is_some_and(|htu| Some(htu) == normalize(expected))
+}
+
+fn mark_used(jti: String, iat: i64, now: i64) -> bool {
+    let mut used = USED_PROOFS.write();
+    used.retain(|_, iat| now - *iat <= PROOF_LIFETIME);
+    used.insert(jti, iat).is_none()
+}
@@ -1,8 +1,8 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
-use bcrypt::BcryptError;
 use sea_orm::{DbErr, TransactionError};
+use serde::Serialize;
 use serde_json::json;
 use std::io;
 use tokio::task::JoinError;
@@ -17,6 +17,9 @@ pub enum Error {
     #[error("{0}")]
     BadRequest(#[from] BadRequestError),
 
+    #[error("{0}")]
+    OAuth(#[from] OAuthError),
+
     #[error("Database error: {0}")]
     Database(#[from] DatabaseError),
 
@@ -30,13 +33,22 @@ pub enum Error {
     RunSyncTask(#[from] JoinError),
 
     #[error("{0}")]
-    HashPassword(#[from] BcryptError),
+    HashPassword(String),
 
     #[error("{0}")]
     File(#[from] FileError),
 
     #[error("{0}")]
     SerdeJson(#[from] serde_json::Error),
+
+    #[error("Signing key error: {0}")]
+    SigningKey(String),
+
+    #[error("Mail delivery error: {0}")]
+    Mail(String),
+
+    #[error("Validation failed")]
+    Validation(Vec<FieldError>),
 }
 
 impl Error {
@@ -46,6 +58,8 @@ impl Error {
             Error::BadRequest(err) => err.get_codes(),
             Error::NotFound(_) => (StatusCode::NOT_FOUND, 40003),
             Error::Authenticate(err) => err.get_codes(),
+            Error::OAuth(err) => err.get_codes(),
+            Error::Validation(_) => (StatusCode::BAD_REQUEST, 40029),
 
             // 5XX Errors
             Error::RunSyncTask(_) => (StatusCode::INTERNAL_SERVER_ERROR, 5005),
@@ -54,6 +68,8 @@ impl Error {
             Error::File(_) => (StatusCode::INTERNAL_SERVER_ERROR, 5008),
             Error::SerdeJson(_) => (StatusCode::INTERNAL_SERVER_ERROR, 5009),
             Error::DbTransaction(_, status_code) => (*status_code, 5010),
+            Error::SigningKey(_) => (StatusCode::INTERNAL_SERVER_ERROR, 5011),
+            Error::Mail(_) => (StatusCode::INTERNAL_SERVER_ERROR, 5012),
         }
     }
 
@@ -76,7 +92,12 @@ impl IntoResponse for Error {
         self.log();
         let (status_code, code) = self.get_codes();
         let message = self.to_string();
-        let body = Json(json!({ "code": code, "message": message }));
+        // OAuth clients expect the RFC 6749 error shape instead of our own
+        let body = match self {
+            Error::OAuth(err) => Json(json!({ "error": err.error_code(), "error_description": message })),
+            Error::Validation(errors) => Json(json!({ "code": code, "message": message, "errors": errors })),
+            _ => Json(json!({ "code": code, "message": message })),
+        };
 
         (status_code, body).into_response()
     }
@@ -99,6 +120,20 @@ pub enum AuthenticateError {
     ActionForbidden,
     #[error("User is locked")]
     Locked,
+    #[error("Registration is awaiting approval")]
+    AwaitingApproval,
+    #[error("Email is not verified")]
+    EmailNotVerified,
+    #[error("Temporary password must be changed")]
+    PasswordChangeRequired,
+    #[error("Two-factor authentication is required")]
+    TwoFactorRequired,
+    #[error("Invalid two-factor code")]
+    InvalidTwoFactorCode,
+    #[error("Invalid passkey")]
+    InvalidPasskey,
+    #[error("Too many invalid two-factor codes, try again later")]
+    TwoFactorLocked,
 }
 
 impl AuthenticateError {
@@ -111,6 +146,86 @@ impl AuthenticateError {
             AuthenticateError::ActionForbidden => (StatusCode::FORBIDDEN, 40009),
             AuthenticateError::MaxConcurrentSessions => (StatusCode::LOCKED, 40010),
             AuthenticateError::InvalidApiCredentials => (StatusCode::FORBIDDEN, 40011),
+            AuthenticateError::AwaitingApproval => (StatusCode::FORBIDDEN, 40026),
+            AuthenticateError::EmailNotVerified => (StatusCode::FORBIDDEN, 40027),
+            AuthenticateError::PasswordChangeRequired => (StatusCode::FORBIDDEN, 40028),
+            AuthenticateError::TwoFactorRequired => (StatusCode::FORBIDDEN, 40030),
+            AuthenticateError::InvalidTwoFactorCode => (StatusCode::UNAUTHORIZED, 40031),
+            AuthenticateError::InvalidPasskey => (StatusCode::UNAUTHORIZED, 40032),
+            AuthenticateError::TwoFactorLocked => (StatusCode::TOO_MANY_REQUESTS, 40033),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("...")]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("Client authentication failed")]
+    InvalidClient,
+    #[error("{0}")]
+    InvalidGrant(String),
+    #[error("Client is not authorized for this request")]
+    UnauthorizedClient,
+    #[error("Grant type is not supported")]
+    UnsupportedGrantType,
+    #[error("Response type is not supported")]
+    UnsupportedResponseType,
+    #[error("Token type cannot be revoked")]
+    UnsupportedTokenType,
+    #[error("The user has not yet approved the device")]
+    AuthorizationPending,
+    #[error("Polling too often, the interval has been increased")]
+    SlowDown,
+    #[error("The user denied the device")]
+    AccessDenied,
+    #[error("The device code has expired")]
+    ExpiredToken,
+    #[error("{0}")]
+    InvalidScope(String),
+    #[error("{0}")]
+    InvalidTarget(String),
+    #[error("{0}")]
+    InvalidDpopProof(String),
+}
+
+impl OAuthError {
+    fn get_codes(&self) -> (StatusCode, u16) {
+        match self {
+            OAuthError::InvalidRequest(_) => (StatusCode::BAD_REQUEST, 40012),
+            OAuthError::InvalidClient => (StatusCode::UNAUTHORIZED, 40013),
+            OAuthError::InvalidGrant(_) => (StatusCode::BAD_REQUEST, 40014),
+            OAuthError::UnauthorizedClient => (StatusCode::BAD_REQUEST, 40015),
+            OAuthError::UnsupportedGrantType => (StatusCode::BAD_REQUEST, 40016),
+            OAuthError::UnsupportedResponseType => (StatusCode::BAD_REQUEST, 40017),
+            OAuthError::UnsupportedTokenType => (StatusCode::BAD_REQUEST, 40018),
+            OAuthError::AuthorizationPending => (StatusCode::BAD_REQUEST, 40019),
+            OAuthError::SlowDown => (StatusCode::BAD_REQUEST, 40020),
+            OAuthError::AccessDenied => (StatusCode::BAD_REQUEST, 40021),
+            OAuthError::ExpiredToken => (StatusCode::BAD_REQUEST, 40022),
+            OAuthError::InvalidScope(_) => (StatusCode::BAD_REQUEST, 40023),
+            OAuthError::InvalidTarget(_) => (StatusCode::BAD_REQUEST, 40024),
+            OAuthError::InvalidDpopProof(_) => (StatusCode::BAD_REQUEST, 40025),
+        }
+    }
+
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant(_) => "invalid_grant",
+            OAuthError::UnauthorizedClient => "unauthorized_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::UnsupportedResponseType => "unsupported_response_type",
+            OAuthError::UnsupportedTokenType => "unsupported_token_type",
+            OAuthError::AuthorizationPending => "authorization_pending",
+            OAuthError::SlowDown => "slow_down",
+            OAuthError::AccessDenied => "access_denied",
+            OAuthError::ExpiredToken => "expired_token",
+            OAuthError::InvalidScope(_) => "invalid_scope",
+            OAuthError::InvalidTarget(_) => "invalid_target",
+            OAuthError::InvalidDpopProof(_) => "invalid_dpop_proof",
         }
     }
 }
@@ -133,6 +248,33 @@ impl From<TransactionError<DbErr>> for Error {
     }
 }
 
+impl From<TransactionError<Error>> for Error {
+    fn from(err: TransactionError<Error>) -> Self {
+        match err {
+            TransactionError::Connection(db_err) => Error::DbTransaction(Box::new(db_err), StatusCode::INTERNAL_SERVER_ERROR),
+            TransactionError::Transaction(err) => err,
+        }
+    }
+}
+
+/// A field of the request that was rejected, `code` tells clients which rule it broke.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &'static str, message: String) -> Self {
+        Self {
+            field: field.to_string(),
+            code,
+            message,
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum BadRequestError {
     #[error("Cannot perform operation: {0}")]
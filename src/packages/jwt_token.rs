@@ -1,18 +1,20 @@
-use jsonwebtoken::{errors::Error, DecodingKey, EncodingKey, Header, TokenData, Validation};
-use once_cell::sync::Lazy;
-use sea_orm::prelude::Uuid;
+use jsonwebtoken::{errors::Error, TokenData};
+use sea_orm::{prelude::Uuid, ConnectionTrait};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 
-use entity::{client, resource, resource_group, session, user};
+use entity::{claim_mapper, client, realm, resource, resource_group, session, user};
 
-use super::settings::SETTINGS;
+use super::{
+    claim_mapper::{mapped_claims, omits},
+    dpop::Confirmation,
+    settings::SETTINGS,
+    signing_key,
+};
 
 type TokenResult = Result<TokenData<Claims>, Error>;
 
-static VALIDATION: Lazy<Validation> = Lazy::new(Validation::default);
-static HEADER: Lazy<Header> = Lazy::new(Header::default);
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Resource {
     pub client_id: Uuid,
@@ -37,6 +39,13 @@ impl Resource {
     }
 }
 
+/// The admin acting on behalf of the subject of an impersonation token, see RFC 8693 section 4.1.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: Uuid,
+    pub iss: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JwtUser {
     pub sub: Uuid,
@@ -49,6 +58,8 @@ pub struct JwtUser {
     pub phone: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub resource: Option<Resource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
 }
 
 impl JwtUser {
@@ -67,6 +78,7 @@ impl JwtUser {
             email: user.email.clone(),
             phone: user.phone.unwrap_or_else(|| "".into()),
             resource: Some(Resource::from(client, resource_group, resources)),
+            act: None,
         }
     }
 
@@ -75,10 +87,11 @@ impl JwtUser {
             sub: claims.sub,
             sid: claims.sid,
             first_name: claims.first_name,
-            last_name: claims.last_name,
-            email: claims.email,
-            phone: claims.phone,
+            last_name: claims.last_name.unwrap_or_default(),
+            email: claims.email.unwrap_or_default(),
+            phone: claims.phone.unwrap_or_default(),
             resource: claims.resource,
+            act: claims.act,
         }
     }
 }
@@ -89,12 +102,27 @@ pub struct Claims {
     pub iat: usize,  // Issued at (as UTC timestamp)
     pub sub: Uuid,   // Subject
     pub sid: Uuid,   // Session ID
+    pub rli: Uuid,   // Realm ID
     pub iss: String, // Issuer
+    #[serde(default)]
+    pub aud: Vec<String>, // Audience --> Client ID followed by the client's audiences
     pub first_name: String,
-    pub last_name: String,
-    pub email: String,
-    pub phone: String,
+    // Left out when the client's claim mappers omit them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
     pub resource: Option<Resource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    // Present when the token is bound to the DPoP key of its holder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+    // Added by the client's claim mappers
+    #[serde(flatten)]
+    pub mapped: Map<String, Value>,
 }
 
 impl Claims {
@@ -104,22 +132,120 @@ impl Claims {
         resource_group: resource_group::Model,
         resources: Vec<resource::Model>,
         session: &session::Model,
+        mappers: &[claim_mapper::Model],
     ) -> Self {
+        let realm_id = user.realm_id;
+        let mapped = mapped_claims(mappers, &user, &resources);
         let user = JwtUser::from(user, client, resource_group, resources, session);
+        let unless_omitted = |claim: &str, value: String| Some(value).filter(|_| !omits(mappers, claim));
 
         Self {
-            exp: session.expires.timestamp() as usize,
+            exp: expiry(client, session),
             iat: chrono::Local::now().timestamp() as usize,
             sub: user.sub,
             sid: user.sid,
-            iss: SETTINGS.read().server.host.clone(),
+            rli: realm_id,
+            iss: issuer(realm_id),
+            aud: audience(client),
             first_name: user.first_name,
-            last_name: user.last_name,
-            email: user.email,
-            phone: user.phone,
+            last_name: unless_omitted("last_name", user.last_name),
+            email: unless_omitted("email", user.email),
+            phone: unless_omitted("phone", user.phone),
             resource: user.resource,
+            act: None,
+            cnf: Confirmation::from(session.dpop_jkt.clone()),
+            mapped,
+        }
+    }
+}
+
+/// OpenID Connect standard claims of the user, released according to the scopes granted to the session.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StandardClaims {
+    pub sub: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+}
+
+impl StandardClaims {
+    // Sessions without a scope come from first party logins, which get every claim
+    pub fn new(user: &user::Model, scope: Option<&str>) -> Self {
+        let granted = |name: &str| scope.is_none_or(|scope| scope.split_whitespace().any(|granted| granted == name));
+        let profile = granted("profile");
+        let email = granted("email");
+        let phone = granted("phone");
+
+        Self {
+            sub: user.id,
+            given_name: Some(user.first_name.clone()).filter(|_| profile),
+            family_name: user.last_name.clone().filter(|_| profile),
+            picture: user.image.clone().filter(|_| profile),
+            email: Some(user.email.clone()).filter(|_| email),
+            email_verified: Some(user.email_verified_at.is_some()).filter(|_| email),
+            phone_number: user.phone.clone().filter(|_| phone),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub exp: usize,  // Expiration time (as UTC timestamp)
+    pub iat: usize,  // Issued at (as UTC timestamp)
+    pub iss: String, // Issuer
+    pub aud: Uuid,   // Audience --> Client ID
+    pub sid: Uuid,   // Session ID
+    pub rli: Uuid,   // Realm ID
+    pub auth_time: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub claims: StandardClaims,
+}
+
+impl IdTokenClaims {
+    pub fn new(user: &user::Model, client: &client::Model, session: &session::Model, nonce: Option<String>) -> Self {
+        Self {
+            exp: expiry(client, session),
+            iat: chrono::Local::now().timestamp() as usize,
+            iss: issuer(user.realm_id),
+            aud: client.id,
+            sid: session.id,
+            rli: user.realm_id,
+            auth_time: session.created_at.timestamp() as usize,
+            nonce,
+            claims: StandardClaims::new(user, session.scope.as_deref()),
         }
     }
+
+    pub fn create_token(&self, realm: &realm::Model) -> Result<String, Error> {
+        signing_key::sign(&self, realm)
+    }
+}
+
+/// Every realm is its own issuer, matching the base URL of the realm's discovery document.
+pub fn issuer(realm_id: Uuid) -> String {
+    format!("{}/realms/{}", SETTINGS.read().server.host, realm_id)
+}
+
+/// Access and ID tokens live for the client's access token lifetime, but never past the session they belong to.
+pub fn expiry(client: &client::Model, session: &session::Model) -> usize {
+    let exp = chrono::Local::now().timestamp() + client.access_token_lifetime as i64;
+    exp.min(session.expires.timestamp()) as usize
+}
+
+/// Resource servers accept a token when they find themselves in its audience, a client is always part of its own.
+pub fn audience(client: &client::Model) -> Vec<String> {
+    std::iter::once(client.id.to_string()).chain(client.audiences.iter().cloned()).collect()
 }
 
 pub fn create(
@@ -128,16 +254,15 @@ pub fn create(
     resource_group: resource_group::Model,
     resources: Vec<resource::Model>,
     session: &session::Model,
-    secret: &str,
+    mappers: &[claim_mapper::Model],
+    realm: &realm::Model,
 ) -> Result<String, Error> {
-    let encoding_key = EncodingKey::from_secret(secret.as_ref());
-    let claims = Claims::new(user, client, resource_group, resources, session);
+    let claims = Claims::new(user, client, resource_group, resources, session, mappers);
 
-    jsonwebtoken::encode(&HEADER, &claims, &encoding_key)
+    signing_key::sign(&claims, realm)
 }
 
-pub fn decode(token: &str, secret: &str) -> TokenResult {
-    let decoding_key = DecodingKey::from_secret(secret.as_ref());
-
-    jsonwebtoken::decode::<Claims>(token, &decoding_key, &VALIDATION)
+/// With an `audience` the token is only accepted when that client is part of its audience.
+pub async fn decode<C: ConnectionTrait>(db: &C, token: &str, realm_id: Uuid, audience: Option<Uuid>) -> TokenResult {
+    signing_key::verify::<Claims, C>(db, token, realm_id, audience).await
 }
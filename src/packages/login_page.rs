@@ -0,0 +1,249 @@
+use crate::mappers::oauth::AuthorizeRequest;
+
+use super::webauthn::RequestOptions;
+
+/// The step of the sign in a page asks for.
+pub enum Step<'a> {
+    Password {
+        email: Option<&'a str>,
+    },
+    // Users prove the second factor with their authenticator app or a recovery code, or with one of their passkeys
+    TwoFactor {
+        two_factor_token: &'a str,
+        authenticator_app: bool,
+        webauthn: Option<&'a RequestOptions>,
+    },
+}
+
+// Fills the assertion of a passkey into the form, the options come in the JSON form the browser parses them from
+const PASSKEY_SCRIPT: &str = r#"const form = document.getElementById("sign-in");
+document.getElementById("passkey").addEventListener("click", async () => {
+  try {
+    const publicKey = PublicKeyCredential.parseRequestOptionsFromJSON(JSON.parse(form.dataset.webauthn));
+    const credential = await navigator.credentials.get({ publicKey });
+    form.elements.webauthn.value = JSON.stringify(credential.toJSON());
+    form.submit();
+  } catch (error) {
+    console.error(error);
+  }
+});"#;
+
+/// Shield's own sign in page, where users authenticate to authorize a client. It posts back to the authorization endpoint,
+/// carrying the authorization request along in hidden fields.
+pub struct LoginPage<'a> {
+    pub action: &'a str,
+    pub client_name: &'a str,
+    pub request: &'a AuthorizeRequest,
+    pub csrf_token: &'a str,
+    pub script_nonce: &'a str,
+    pub error: Option<&'a str>,
+    pub step: Step<'a>,
+}
+
+impl LoginPage<'_> {
+    pub fn render(&self) -> String {
+        let mut hidden_fields = self.request.params();
+        hidden_fields.push(("csrf_token", self.csrf_token.to_owned()));
+
+        let (action, fields, webauthn) = match &self.step {
+            Step::Password { email } => (
+                format!("{}/login", self.action),
+                format!(
+                    r#"<label>Email <input type="email" name="email" value="{}" autocomplete="username" required autofocus></label>
+<label>Password <input type="password" name="password" autocomplete="current-password" required></label>
+<button type="submit">Continue</button>"#,
+                    escape(email.unwrap_or_default())
+                ),
+                None,
+            ),
+            Step::TwoFactor {
+                two_factor_token,
+                authenticator_app,
+                webauthn,
+            } => {
+                hidden_fields.push(("two_factor_token", two_factor_token.to_string()));
+                let mut fields = Vec::new();
+                if *authenticator_app {
+                    fields.push(
+                        r#"<label>Code of your authenticator app <input type="text" name="code" inputmode="numeric" autocomplete="one-time-code" autofocus></label>
+<label>Or one of your recovery codes <input type="text" name="recovery_code" autocomplete="off"></label>
+<button type="submit">Continue</button>"#,
+                    );
+                }
+                if webauthn.is_some() {
+                    fields.push(
+                        r#"<input type="hidden" name="webauthn">
+<button type="button" id="passkey">Use a passkey</button>"#,
+                    );
+                }
+                (
+                    format!("{}/two-factor", self.action),
+                    fields.join("\n"),
+                    webauthn.map(|options| serde_json::to_string(options).unwrap_or_default()),
+                )
+            }
+        };
+        let hidden_fields = hidden_fields
+            .iter()
+            .map(|(name, value)| format!(r#"<input type="hidden" name="{}" value="{}">"#, name, escape(value)))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let (form_data, script) = match &webauthn {
+            Some(options) => (
+                format!(r#" data-webauthn="{}""#, escape(options)),
+                format!(r#"<script nonce="{}">{}</script>"#, escape(self.script_nonce), PASSKEY_SCRIPT),
+            ),
+            None => (String::new(), String::new()),
+        };
+        let error = self
+            .error
+            .map(|error| format!(r#"<p class="error">{}</p>"#, escape(error)))
+            .unwrap_or_default();
+
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Sign in to {client_name}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 22rem; margin: 4rem auto; padding: 0 1rem; }}
+label {{ display: block; margin-bottom: 1rem; }}
+input:not([type=hidden]) {{ display: block; width: 100%; box-sizing: border-box; padding: 0.5rem; margin-top: 0.25rem; }}
+button {{ width: 100%; padding: 0.5rem; margin-bottom: 1rem; }}
+.error {{ color: #b00020; }}
+</style>
+</head>
+<body>
+<h1>Sign in to {client_name}</h1>
+{error}
+<form id="sign-in" method="post" action="{action}"{form_data}>
+{hidden_fields}
+{fields}
+</form>
+{script}
+</body>
+</html>
+"#,
+            client_name = escape(self.client_name),
+            error = error,
+            action = escape(&action),
+            form_data = form_data,
+            hidden_fields = hidden_fields,
+            fields = fields,
+            script = script,
+        )
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::prelude::Uuid;
+
+    use super::*;
+
+    fn request(state: &str) -> AuthorizeRequest {
+        AuthorizeRequest {
+            response_type: "code".to_string(),
+            client_id: Uuid::nil(),
+            redirect_uri: "https://app.example.com/callback?a=1&b=2".to_string(),
+            scope: None,
+            state: Some(state.to_string()),
+            nonce: None,
+            code_challenge: Some("challenge".to_string()),
+            code_challenge_method: Some("S256".to_string()),
+        }
+    }
+
+    fn page<'a>(request: &'a AuthorizeRequest, error: Option<&'a str>, step: Step<'a>) -> String {
+        LoginPage {
+            action: "https://shield.example.com/realms/1/oauth/authorize",
+            client_name: "<App>",
+            request,
+            csrf_token: "csrf",
+            script_nonce: "nonce",
+            error,
+            step,
+        }
+        .render()
+    }
+
+    #[test]
+    fn carries_the_authorization_request_along() {
+        let request = request("xyz");
+        let html = page(&request, None, Step::Password { email: None });
+        assert!(html.contains(r#"action="https://shield.example.com/realms/1/oauth/authorize/login""#));
+        assert!(html.contains(r#"<input type="hidden" name="redirect_uri" value="https://app.example.com/callback?a=1&amp;b=2">"#));
+        assert!(html.contains(r#"<input type="hidden" name="state" value="xyz">"#));
+        assert!(html.contains(r#"<input type="hidden" name="csrf_token" value="csrf">"#));
+        // Parameters that were not given are not made up
+        assert!(!html.contains(r#"name="nonce""#));
+    }
+
+    #[test]
+    fn escapes_what_comes_from_requests_and_clients() {
+        let request = request(r#""><script>alert(1)</script>"#);
+        let html = page(&request, Some("<b>error</b>"), Step::Password { email: Some(r#"a"@b.c"#) });
+        assert!(!html.contains("<script>"));
+        assert!(html.contains(r#"value="&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;""#));
+        assert!(html.contains("Sign in to &lt;App&gt;"));
+        assert!(html.contains("&lt;b&gt;error&lt;/b&gt;"));
+        assert!(html.contains(r#"value="a&quot;@b.c""#));
+    }
+
+    #[test]
+    fn asks_for_the_second_factor_with_the_two_factor_token() {
+        let request = request("xyz");
+        let step = Step::TwoFactor {
+            two_factor_token: "token",
+            authenticator_app: true,
+            webauthn: None,
+        };
+        let html = page(&request, None, step);
+        assert!(html.contains(r#"action="https://shield.example.com/realms/1/oauth/authorize/two-factor""#));
+        assert!(html.contains(r#"<input type="hidden" name="two_factor_token" value="token">"#));
+        assert!(html.contains(r#"name="code""#));
+        assert!(html.contains(r#"name="recovery_code""#));
+        assert!(!html.contains(r#"name="password""#));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn offers_passkeys_with_their_options() {
+        let request = request("xyz");
+        let options = RequestOptions {
+            challenge: "challenge".to_string(),
+            rp_id: "example.com".to_string(),
+            timeout: 60000,
+            user_verification: "preferred",
+            allow_credentials: vec![],
+        };
+        let step = Step::TwoFactor {
+            two_factor_token: "token",
+            authenticator_app: false,
+            webauthn: Some(&options),
+        };
+        let html = page(&request, None, step);
+        assert!(html.contains(r#"data-webauthn="{&quot;challenge&quot;:&quot;challenge&quot;,&quot;rpId&quot;:&quot;example.com&quot;"#));
+        assert!(html.contains(r#"<input type="hidden" name="webauthn">"#));
+        assert!(html.contains(r#"<script nonce="nonce">"#));
+        // Users without an authenticator app are not asked for its codes
+        assert!(!html.contains(r#"name="code""#));
+    }
+}
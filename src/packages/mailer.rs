@@ -0,0 +1,250 @@
+use std::{fmt::Display, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use tracing::info;
+
+use super::{
+    errors::Error,
+    settings::{MailTransport, Smtp, SmtpSecurity, SETTINGS},
+};
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// The email as an RFC 5322 message, the recipient and subject come from users so they must not break out of their header.
+    fn to_message(&self, from: &str) -> Result<Message, Error> {
+        if [from, &self.to, &self.subject].iter().any(|header| header.contains(['\r', '\n'])) {
+            return Err(Error::Mail("Email headers cannot contain line breaks".to_string()));
+        }
+
+        Message::builder()
+            .from(from.parse::<Mailbox>().map_err(mail_error)?)
+            .to(self.to.parse::<Mailbox>().map_err(mail_error)?)
+            .subject(&self.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(self.body.clone())
+            .map_err(mail_error)
+    }
+}
+
+/// Delivers the emails Shield sends to users, chosen by `mailer.transport` in the settings.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), Error>;
+}
+
+pub fn from_settings() -> Arc<dyn Mailer> {
+    let settings = SETTINGS.read().mailer.clone();
+    match settings.transport {
+        MailTransport::Smtp => {
+            let smtp = settings.smtp.expect("mailer.smtp is required by the smtp transport");
+            Arc::new(SmtpMailer::new(settings.from, &smtp).expect("Invalid mailer.smtp settings"))
+        }
+        MailTransport::File => Arc::new(FileMailer {
+            from: settings.from,
+            directory: PathBuf::from(settings.directory),
+        }),
+        MailTransport::Log => Arc::new(LogMailer { from: settings.from }),
+    }
+}
+
+pub struct SmtpMailer {
+    from: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(from: String, smtp: &Smtp) -> Result<Self, Error> {
+        let builder = match smtp.security {
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host).map_err(mail_error)?,
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host).map_err(mail_error)?,
+        };
+        let builder = builder.port(smtp.port).timeout(Some(SMTP_TIMEOUT));
+        let builder = match &smtp.username {
+            Some(username) => builder.credentials(Credentials::new(username.clone(), smtp.password.clone().unwrap_or_default())),
+            None => builder,
+        };
+        Ok(Self {
+            from,
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), Error> {
+        let message = email.to_message(&self.from)?;
+        self.transport.send(message).await.map_err(mail_error)?;
+        Ok(())
+    }
+}
+
+/// Writes every email to its own `.eml` file, for development and tests.
+pub struct FileMailer {
+    from: String,
+    directory: PathBuf,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), Error> {
+        let message = email.to_message(&self.from)?;
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let id = AsyncFileTransport::<Tokio1Executor>::new(&self.directory)
+            .send(message)
+            .await
+            .map_err(mail_error)?;
+        info!(
+            "📧 Email to {} written to {}",
+            email.to,
+            self.directory.join(format!("{}.eml", id)).display()
+        );
+        Ok(())
+    }
+}
+
+/// Only logs the emails, so nothing is ever delivered.
+pub struct LogMailer {
+    from: String,
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), Error> {
+        let message = email.to_message(&self.from)?;
+        info!("📧 Email to {}\n{}", email.to, String::from_utf8_lossy(&message.formatted()));
+        Ok(())
+    }
+}
+
+fn mail_error(err: impl Display) -> Error {
+    Error::Mail(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const FROM: &str = "Shield <no-reply@localhost>";
+
+    fn email(to: &str, subject: &str, body: &str) -> Email {
+        Email {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn rejects_line_breaks_in_headers() {
+        let injected = ["user@example.com\r\nBcc: victim@example.com", "user@example.com\nBcc: victim@example.com"];
+        for to in injected {
+            assert!(email(to, "Verify your email", "Hello").to_message(FROM).is_err());
+        }
+        assert!(email("user@example.com", "Hello\r\nBcc: victim@example.com", "Hello")
+            .to_message(FROM)
+            .is_err());
+        assert!(email("user@example.com", "Hello", "Hello")
+            .to_message("a@example.com\nBcc: b@example.com")
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        assert!(email("not an address", "Hello", "Hello").to_message(FROM).is_err());
+        assert!(email("user@example.com", "Hello", "Hello").to_message("not an address").is_err());
+    }
+
+    #[test]
+    fn builds_plain_text_messages() {
+        let message = email("User <user@example.com>", "Réinitialiser", "Line one\nLine two")
+            .to_message(FROM)
+            .unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("From: Shield <no-reply@localhost>\r\n"));
+        assert!(formatted.contains("To: User <user@example.com>\r\n"));
+        // Non ASCII subjects are sent as RFC 2047 encoded words
+        assert!(formatted.contains("Subject: =?utf-8?b?"));
+        assert!(formatted.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert_eq!(message.envelope().to()[0].to_string(), "user@example.com");
+    }
+
+    // Lines starting with a dot are escaped on the wire so they do not end the data early, see RFC 5321 section 4.5.2
+    #[tokio::test]
+    async fn escapes_leading_dots_when_delivering_over_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost\r\n").await.unwrap();
+
+            let mut data = Vec::new();
+            let mut in_data = false;
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        writer.write_all(b"250 queued\r\n").await.unwrap();
+                    } else {
+                        data.push(line);
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match line.split(' ').next().unwrap_or_default().to_uppercase().as_str() {
+                    "EHLO" => b"250 localhost\r\n",
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 ok\r\n",
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+            data
+        });
+
+        let smtp = Smtp {
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+        };
+        let mailer = SmtpMailer::new(FROM.to_string(), &smtp).unwrap();
+        mailer
+            .send(email("user@example.com", "Hello", "First line\n.\n.hidden line"))
+            .await
+            .unwrap();
+        drop(mailer);
+
+        let data = server.await.unwrap();
+        assert!(data.contains(&"First line".to_string()));
+        assert!(data.contains(&"..".to_string()));
+        assert!(data.contains(&"..hidden line".to_string()));
+        assert!(!data.contains(&".".to_string()));
+    }
+}
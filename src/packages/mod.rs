@@ -1,7 +1,18 @@
+pub mod action_token;
 pub mod admin;
 pub mod api_token;
+pub mod claim_mapper;
 pub mod db;
+pub mod dpop;
 pub mod errors;
 pub mod jwt_token;
 pub mod logger;
+pub mod login_page;
+pub mod mailer;
+pub mod password_policy;
+pub mod session_cache;
 pub mod settings;
+pub mod signing_key;
+pub mod sso;
+pub mod totp;
+pub mod webauthn;
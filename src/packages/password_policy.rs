@@ -0,0 +1,165 @@
+use chrono::{Duration, Utc};
+use entity::{realm, user};
+use serde::{Deserialize, Serialize};
+
+use super::errors::{Error, FieldError};
+
+/// Rules the passwords of a realm's users have to follow, checked whenever a password is set.
+/// Realms without a policy of their own get the defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    // Compared case insensitively
+    pub banned_passwords: Vec<String>,
+    // How many of the user's last passwords, the current one included, cannot be used again. 0 allows any
+    pub history: usize,
+    // Passwords older than this must be changed on the next login
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            banned_passwords: vec![],
+            history: 0,
+            max_age_days: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn from(realm: &realm::Model) -> Self {
+        serde_json::from_value(realm.password_policy.clone()).unwrap_or_default()
+    }
+
+    /// Normalizes a policy an admin sets on a realm.
+    pub fn validated(mut self) -> Result<Self, Error> {
+        if self.min_length == 0 {
+            return Err(Error::cannot_perform_operation("Minimum password length must be at least 1"));
+        }
+        if self.max_age_days == Some(0) {
+            return Err(Error::cannot_perform_operation("Maximum password age must be at least 1 day"));
+        }
+        self.banned_passwords = self.banned_passwords.iter().map(|password| password.to_lowercase()).collect();
+        Ok(self)
+    }
+
+    /// Every rule the password breaks, reported against the request field it came in.
+    pub fn violations(&self, field: &str, password: &str) -> Vec<FieldError> {
+        let mut violations = vec![];
+        if password.chars().count() < self.min_length {
+            violations.push(FieldError::new(
+                field,
+                "too_short",
+                format!("Password must be at least {} characters long", self.min_length),
+            ));
+        }
+
+        let lacks = |matches: fn(&char) -> bool| !password.chars().any(|c| matches(&c));
+        if self.require_lowercase && lacks(|c| c.is_lowercase()) {
+            violations.push(FieldError::new(
+                field,
+                "missing_lowercase",
+                "Password must contain a lowercase letter".to_string(),
+            ));
+        }
+        if self.require_uppercase && lacks(|c| c.is_uppercase()) {
+            violations.push(FieldError::new(
+                field,
+                "missing_uppercase",
+                "Password must contain an uppercase letter".to_string(),
+            ));
+        }
+        if self.require_digit && lacks(char::is_ascii_digit) {
+            violations.push(FieldError::new(field, "missing_digit", "Password must contain a digit".to_string()));
+        }
+        if self.require_symbol && lacks(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+            violations.push(FieldError::new(field, "missing_symbol", "Password must contain a symbol".to_string()));
+        }
+
+        if self.banned_passwords.contains(&password.to_lowercase()) {
+            violations.push(FieldError::new(field, "banned", "Password is not allowed".to_string()));
+        }
+        violations
+    }
+
+    pub fn is_expired(&self, user: &user::Model) -> bool {
+        self.max_age_days
+            .is_some_and(|max_age_days| user.password_changed_at + Duration::days(max_age_days.into()) <= Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strict_policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 10,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            banned_passwords: vec!["Correct-Horse-1".to_string()],
+            ..Default::default()
+        }
+        .validated()
+        .unwrap()
+    }
+
+    fn codes(violations: &[FieldError]) -> Vec<&'static str> {
+        violations.iter().map(|violation| violation.code).collect()
+    }
+
+    #[test]
+    fn reports_every_broken_rule() {
+        let violations = strict_policy().violations("new_password", "abc");
+        assert_eq!(codes(&violations), ["too_short", "missing_uppercase", "missing_digit", "missing_symbol"]);
+        assert!(violations.iter().all(|violation| violation.field == "new_password"));
+        assert_eq!(violations[0].message, "Password must be at least 10 characters long");
+    }
+
+    #[test]
+    fn accepts_passwords_following_the_rules() {
+        assert!(strict_policy().violations("password", "Tr0ub4dor&3x").is_empty());
+        assert!(PasswordPolicy::default().violations("password", "password").is_empty());
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.violations("password", "ääääääää").is_empty());
+        assert_eq!(codes(&policy.violations("password", "äääääää")), ["too_short"]);
+    }
+
+    #[test]
+    fn bans_passwords_case_insensitively() {
+        let policy = strict_policy();
+        assert!(policy.violations("password", "correct-horse-1X").is_empty());
+        assert_eq!(codes(&policy.violations("password", "cORRECT-hORSE-1")), ["banned"]);
+    }
+
+    #[test]
+    fn refuses_policies_that_cannot_be_followed() {
+        let empty = PasswordPolicy {
+            min_length: 0,
+            ..Default::default()
+        };
+        assert!(empty.validated().is_err());
+        let expired = PasswordPolicy {
+            max_age_days: Some(0),
+            ..Default::default()
+        };
+        assert!(expired.validated().is_err());
+    }
+}
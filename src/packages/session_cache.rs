@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use entity::{client, revoked_session, session};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use sea_orm::{prelude::Uuid, sea_query::OnConflict, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Set};
+use tracing::{error, info};
+
+use super::{errors::Error, jwt_token::Claims};
+
+// Sessions, revocations and client settings looked up in the database are trusted for this long. Sessions revoked by
+// another instance, or deleted without going through `revoke`, keep working for at most this duration
+const CACHE_TTL: Duration = Duration::from_secs(30);
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Revoked session ID -> expiry of the session, after which its tokens are rejected anyway.
+// Only filled from the database, so it never holds a revocation whose transaction was rolled back
+static REVOKED: Lazy<RwLock<HashMap<Uuid, i64>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+// Session ID -> when it was last found missing from the revocation list
+static NOT_REVOKED: Lazy<RwLock<HashMap<Uuid, Instant>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+// Session ID -> when a transaction started revoking it. Until that transaction is surely over, whether the session
+// is revoked is read from the database every time instead of the caches
+static REVOKING: Lazy<RwLock<HashMap<Uuid, Instant>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+// Session ID -> (expiry of the session, when it was last seen in the database)
+static SESSIONS: Lazy<RwLock<HashMap<Uuid, (i64, Instant)>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+// Client ID -> (whether it uses stateful access tokens, when it was last read from the database)
+static STATEFUL_CLIENTS: Lazy<RwLock<HashMap<Uuid, (bool, Instant)>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Drops the revocations of expired sessions now and then, as their tokens are rejected anyway.
+pub async fn setup(db: &DatabaseConnection) -> Result<(), Error> {
+    delete_expired(db).await?;
+
+    let db = db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = delete_expired(&db).await {
+                error!("Failed to delete expired session revocations: {}", e);
+            }
+        }
+    });
+    info!("🚫 Session revocation cleanup scheduled");
+    Ok(())
+}
+
+/// Puts the sessions on the revocation list, their access tokens are rejected from now on whatever mode the client uses.
+/// Nothing is cached until the revocation is read back from the database, as the caller's transaction may still roll back.
+pub async fn revoke<C: ConnectionTrait>(db: &C, sessions: &[session::Model]) -> Result<(), Error> {
+    if sessions.is_empty() {
+        return Ok(());
+    }
+
+    {
+        let mut revoking = REVOKING.write();
+        revoking.retain(|_, since| since.elapsed() < CACHE_TTL);
+        revoking.extend(sessions.iter().map(|session| (session.id, Instant::now())));
+    }
+
+    let models = sessions.iter().map(|session| revoked_session::ActiveModel {
+        id: Set(session.id),
+        client_id: Set(session.client_id),
+        expires_at: Set(session.expires),
+        ..Default::default()
+    });
+    revoked_session::Entity::insert_many(models)
+        .on_conflict(OnConflict::column(revoked_session::Column::Id).do_nothing().to_owned())
+        .do_nothing()
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Drops the cached mode of the client, to be called whenever the client is updated.
+pub fn forget_client(client_id: Uuid) {
+    STATEFUL_CLIENTS.write().remove(&client_id);
+}
+
+/// Whether the session behind the access token is still usable.
+/// Tokens of stateless clients are only checked against the revocation list, stateful clients also require the session to exist.
+pub async fn is_active<C: ConnectionTrait>(db: &C, claims: &Claims) -> Result<bool, Error> {
+    if is_revoked(db, claims.sid).await? {
+        return Ok(false);
+    }
+
+    let Some(client_id) = claims.resource.as_ref().map(|resource| resource.client_id) else {
+        return Ok(true);
+    };
+    if !is_stateful(db, client_id).await? {
+        return Ok(true);
+    }
+
+    session_exists(db, claims.sid).await
+}
+
+async fn is_revoked<C: ConnectionTrait>(db: &C, session_id: Uuid) -> Result<bool, Error> {
+    if REVOKED.read().contains_key(&session_id) {
+        return Ok(true);
+    }
+    let is_revoking = REVOKING.read().get(&session_id).is_some_and(|since| since.elapsed() < CACHE_TTL);
+    if !is_revoking
+        && NOT_REVOKED
+            .read()
+            .get(&session_id)
+            .is_some_and(|checked_at| checked_at.elapsed() < CACHE_TTL)
+    {
+        return Ok(false);
+    }
+
+    match revoked_session::Entity::find_by_id(session_id).one(db).await? {
+        Some(revoked_session) => {
+            let now = Utc::now().timestamp();
+            let mut revoked = REVOKED.write();
+            revoked.retain(|_, expires| *expires > now);
+            revoked.insert(session_id, revoked_session.expires_at.timestamp());
+            drop(revoked);
+            NOT_REVOKED.write().remove(&session_id);
+            SESSIONS.write().remove(&session_id);
+            Ok(true)
+        }
+        None => {
+            if !is_revoking {
+                let mut not_revoked = NOT_REVOKED.write();
+                not_revoked.retain(|_, checked_at| checked_at.elapsed() < CACHE_TTL);
+                not_revoked.insert(session_id, Instant::now());
+            }
+            Ok(false)
+        }
+    }
+}
+
+async fn is_stateful<C: ConnectionTrait>(db: &C, client_id: Uuid) -> Result<bool, Error> {
+    if let Some((stateful, checked_at)) = STATEFUL_CLIENTS.read().get(&client_id).copied() {
+        if checked_at.elapsed() < CACHE_TTL {
+            return Ok(stateful);
+        }
+    }
+
+    let stateful = client::Entity::find_by_id(client_id)
+        .select_only()
+        .column(client::Column::UseStatefulAccessToken)
+        .into_tuple::<bool>()
+        .one(db)
+        .await?
+        .unwrap_or(false);
+    STATEFUL_CLIENTS.write().insert(client_id, (stateful, Instant::now()));
+    Ok(stateful)
+}
+
+async fn session_exists<C: ConnectionTrait>(db: &C, session_id: Uuid) -> Result<bool, Error> {
+    let now = Utc::now().timestamp();
+    if let Some((expires, checked_at)) = SESSIONS.read().get(&session_id).copied() {
+        if checked_at.elapsed() < CACHE_TTL {
+            return Ok(expires > now);
+        }
+    }
+
+    let session = session::Entity::find_by_id(session_id).one(db).await?;
+    let mut cached = SESSIONS.write();
+    cached.retain(|_, (_, checked_at)| checked_at.elapsed() < CACHE_TTL);
+    match session {
+        Some(session) => {
+            let expires = session.expires.timestamp();
+            cached.insert(session_id, (expires, Instant::now()));
+            Ok(expires > now)
+        }
+        None => Ok(false),
+    }
+}
+
+async fn delete_expired(db: &DatabaseConnection) -> Result<(), Error> {
+    revoked_session::Entity::delete_many()
+        .filter(revoked_session::Column::ExpiresAt.lte(Utc::now()))
+        .exec(db)
+        .await?;
+    Ok(())
+}
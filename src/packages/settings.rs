@@ -39,6 +39,76 @@ pub struct Secrets {
     pub signing_key: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    File,
+    Log,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Smtp {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Mailer {
+    pub transport: MailTransport,
+    pub from: String,
+    // Where the file transport writes the emails to
+    pub directory: String,
+    pub smtp: Option<Smtp>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Argon2id,
+    Bcrypt,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Argon2id {
+    pub memory_cost: u32, // in KiB
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Bcrypt {
+    pub cost: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PasswordHashing {
+    // New passwords are hashed with it, existing hashes of the other algorithm are still verified
+    pub algorithm: HashAlgorithm,
+    pub argon2id: Argon2id,
+    pub bcrypt: Bcrypt,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webauthn {
+    // Domain the passkeys are bound to, the origins have to be on it or one of its subdomains
+    pub rp_id: String,
+    pub rp_name: String,
+    // Origins allowed besides those of the clients' redirect URIs
+    pub origins: Vec<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     // pub environment: String,
@@ -47,6 +117,9 @@ pub struct Settings {
     pub database: Database,
     pub admin: Admin,
     pub secrets: Secrets,
+    pub mailer: Mailer,
+    pub password_hashing: PasswordHashing,
+    pub webauthn: Webauthn,
     pub default_cred: DefaultCred,
 }
 
@@ -87,6 +160,12 @@ impl Settings {
         if let Ok(admin_password) = env::var("ADMIN_PASSWORD") {
             builder = builder.set_override("admin.password", admin_password)?;
         }
+        if let Ok(smtp_username) = env::var("SMTP_USERNAME") {
+            builder = builder.set_override("mailer.smtp.username", smtp_username)?;
+        }
+        if let Ok(smtp_password) = env::var("SMTP_PASSWORD") {
+            builder = builder.set_override("mailer.smtp.password", smtp_password)?;
+        }
 
         // "./logs/default_cred.json" exists then read it else skip
         if Path::new("./logs/default_cred.json").exists() {
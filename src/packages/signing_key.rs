@@ -0,0 +1,320 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use entity::{
+    realm,
+    sea_orm_active_enums::{SigningAlgorithm, SigningKeyStatus},
+    signing_key,
+};
+use jsonwebtoken::{
+    errors::{Error as JwtError, ErrorKind},
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
+use once_cell::sync::Lazy;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use parking_lot::RwLock;
+use rsa::{
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding},
+    traits::PublicKeyParts,
+    RsaPrivateKey,
+};
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, Set};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::utils::{
+    default_resource_checker::is_default_realm,
+    helpers::generate_random_string::{generate_random_string, Length},
+};
+
+use super::{errors::Error, jwt_token, settings::SETTINGS};
+
+const RSA_KEY_BITS: usize = 2048;
+// Tokens carrying an unknown `kid` trigger a reload from the database, but not more often than this
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+pub static KEYS: Lazy<RwLock<KeySet>> = Lazy::new(|| RwLock::new(KeySet::default()));
+
+pub async fn setup<C: ConnectionTrait>(db: &C) -> Result<(), Error> {
+    let realms = realm::Entity::find().all(db).await?;
+    for realm in realms {
+        ensure_active_key(db, realm.id, &realm.signing_algorithm).await?;
+    }
+
+    reload(db).await?;
+    info!("🔑 Signing keys loaded");
+    Ok(())
+}
+
+pub async fn ensure_active_key<C: ConnectionTrait>(db: &C, realm_id: Uuid, algorithm: &SigningAlgorithm) -> Result<(), Error> {
+    let active_key = signing_key::Entity::find()
+        .filter(signing_key::Column::RealmId.eq(realm_id))
+        .filter(signing_key::Column::Algorithm.eq(algorithm.clone()))
+        .filter(signing_key::Column::Status.eq(SigningKeyStatus::Active))
+        .one(db)
+        .await?;
+    if active_key.is_some() {
+        return Ok(());
+    }
+
+    // The configured secret seeds the master realm's first HMAC key, every other realm gets its own random secret
+    let private_key = match algorithm {
+        SigningAlgorithm::HS256 if is_default_realm(realm_id) => SETTINGS.read().secrets.signing_key.clone(),
+        _ => generate_private_key(algorithm)?,
+    };
+    let key_model = signing_key::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        realm_id: Set(realm_id),
+        algorithm: Set(algorithm.clone()),
+        private_key: Set(private_key),
+        status: Set(SigningKeyStatus::Active),
+        ..Default::default()
+    };
+    key_model.insert(db).await?;
+    info!("🔐 Generated new {:?} signing key for realm {}", algorithm, realm_id);
+
+    Ok(())
+}
+
+pub async fn reload<C: ConnectionTrait>(db: &C) -> Result<(), Error> {
+    let models = signing_key::Entity::find_verifiable(db).await?;
+    let key_set = KeySet::from_models(models);
+    *KEYS.write() = key_set;
+    Ok(())
+}
+
+pub fn to_jwt_algorithm(algorithm: &SigningAlgorithm) -> Algorithm {
+    match algorithm {
+        SigningAlgorithm::HS256 => Algorithm::HS256,
+        SigningAlgorithm::RS256 => Algorithm::RS256,
+        SigningAlgorithm::ES256 => Algorithm::ES256,
+        SigningAlgorithm::EdDSA => Algorithm::EdDSA,
+    }
+}
+
+pub fn generate_private_key(algorithm: &SigningAlgorithm) -> Result<String, Error> {
+    let pem = match algorithm {
+        SigningAlgorithm::HS256 => return Ok(generate_random_string(Length::U64)),
+        SigningAlgorithm::RS256 => RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS)
+            .map_err(|e| Error::SigningKey(e.to_string()))?
+            .to_pkcs8_pem(LineEnding::LF),
+        SigningAlgorithm::ES256 => p256::SecretKey::random(&mut rand::thread_rng()).to_pkcs8_pem(LineEnding::LF),
+        SigningAlgorithm::EdDSA => ed25519_dalek::SigningKey::generate(&mut rand::thread_rng()).to_pkcs8_pem(LineEnding::LF),
+    };
+
+    pem.map(|pem| pem.to_string()).map_err(|e| Error::SigningKey(e.to_string()))
+}
+
+struct KeyPair {
+    realm_id: Uuid,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    expires_at: Option<chrono::DateTime<Utc>>,
+    // Only asymmetric keys have a public part which can be published
+    jwk: Option<Jwk>,
+}
+
+#[derive(Default)]
+pub struct KeySet {
+    keys: HashMap<String, KeyPair>,
+    active: HashMap<(Uuid, Algorithm), String>,
+    loaded_at: Option<Instant>,
+}
+
+impl KeySet {
+    fn from_models(models: Vec<signing_key::Model>) -> Self {
+        let mut key_set = Self {
+            loaded_at: Some(Instant::now()),
+            ..Default::default()
+        };
+
+        for model in models {
+            let kid = model.id.to_string();
+            match KeyPair::from_model(&model) {
+                Ok(key_pair) => {
+                    if model.status == SigningKeyStatus::Active {
+                        key_set.active.insert((key_pair.realm_id, key_pair.algorithm), kid.clone());
+                    }
+                    key_set.keys.insert(kid, key_pair);
+                }
+                Err(e) => error!("Failed to load signing key {}: {}", kid, e),
+            }
+        }
+
+        key_set
+    }
+
+    fn can_reload(&self) -> bool {
+        self.loaded_at.is_none_or(|loaded_at| loaded_at.elapsed() >= RELOAD_INTERVAL)
+    }
+
+    pub fn jwks(&self, realm_id: Uuid) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .values()
+                .filter(|key| key.realm_id == realm_id)
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+impl KeyPair {
+    fn from_model(model: &signing_key::Model) -> Result<Self, Error> {
+        let kid = model.id.to_string();
+        let expires_at = model.expires_at.map(|expires_at| expires_at.with_timezone(&Utc));
+        let pem = model.private_key.as_bytes();
+
+        let (key_algorithm, params, encoding_key) = match model.algorithm {
+            SigningAlgorithm::HS256 => {
+                return Ok(Self {
+                    realm_id: model.realm_id,
+                    algorithm: Algorithm::HS256,
+                    encoding_key: EncodingKey::from_secret(pem),
+                    decoding_key: DecodingKey::from_secret(pem),
+                    expires_at,
+                    jwk: None,
+                })
+            }
+            SigningAlgorithm::RS256 => {
+                let key = RsaPrivateKey::from_pkcs8_pem(&model.private_key).map_err(|e| Error::SigningKey(e.to_string()))?;
+                let params = AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                    e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+                });
+                (KeyAlgorithm::RS256, params, EncodingKey::from_rsa_pem(pem))
+            }
+            SigningAlgorithm::ES256 => {
+                let key = p256::SecretKey::from_pkcs8_pem(&model.private_key).map_err(|e| Error::SigningKey(e.to_string()))?;
+                let point = key.public_key().to_encoded_point(false);
+                let (x, y) = match (point.x(), point.y()) {
+                    (Some(x), Some(y)) => (x, y),
+                    _ => return Err(Error::SigningKey("Invalid EC public key".to_owned())),
+                };
+                let params = AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: URL_SAFE_NO_PAD.encode(x),
+                    y: URL_SAFE_NO_PAD.encode(y),
+                });
+                (KeyAlgorithm::ES256, params, EncodingKey::from_ec_pem(pem))
+            }
+            SigningAlgorithm::EdDSA => {
+                let key = ed25519_dalek::SigningKey::from_pkcs8_pem(&model.private_key).map_err(|e| Error::SigningKey(e.to_string()))?;
+                let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(key.verifying_key().to_bytes()),
+                });
+                (KeyAlgorithm::EdDSA, params, EncodingKey::from_ed_pem(pem))
+            }
+        };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(key_algorithm),
+                key_id: Some(kid),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+
+        Ok(Self {
+            realm_id: model.realm_id,
+            algorithm: to_jwt_algorithm(&model.algorithm),
+            encoding_key: encoding_key.map_err(|e| Error::SigningKey(e.to_string()))?,
+            decoding_key: DecodingKey::from_jwk(&jwk).map_err(|e| Error::SigningKey(e.to_string()))?,
+            expires_at,
+            jwk: Some(jwk),
+        })
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+#[derive(Deserialize)]
+struct RealmClaim {
+    rli: Uuid,
+}
+
+/// Reads the realm a token claims to belong to without verifying it, so that the realm's own keys can verify it afterwards.
+pub fn peek_realm_id(token: &str) -> Result<Uuid, JwtError> {
+    let header = jsonwebtoken::decode_header(token)?;
+    let mut validation = Validation::new(header.alg);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+
+    let token_data = jsonwebtoken::decode::<RealmClaim>(token, &DecodingKey::from_secret(&[]), &validation)?;
+    Ok(token_data.claims.rli)
+}
+
+pub fn sign<T: Serialize>(claims: &T, realm: &realm::Model) -> Result<String, JwtError> {
+    let algorithm = to_jwt_algorithm(&realm.signing_algorithm);
+    let keys = KEYS.read();
+    let kid = keys.active.get(&(realm.id, algorithm)).ok_or(ErrorKind::InvalidAlgorithm)?;
+    let key = keys.keys.get(kid).ok_or(ErrorKind::InvalidAlgorithm)?;
+
+    let mut header = Header::new(algorithm);
+    header.kid = Some(kid.clone());
+    jsonwebtoken::encode(&header, claims, &key.encoding_key)
+}
+
+/// Verifies the token with the realm's keys and checks it was issued by the realm.
+/// The audience is only checked when one is given, callers that need the claims to tell which audience to expect check it themselves.
+pub async fn verify<T: DeserializeOwned, C: ConnectionTrait>(
+    db: &C,
+    token: &str,
+    realm_id: Uuid,
+    audience: Option<Uuid>,
+) -> Result<TokenData<T>, JwtError> {
+    let header = jsonwebtoken::decode_header(token)?;
+    let kid = header.kid.ok_or(ErrorKind::InvalidToken)?;
+
+    let should_reload = {
+        let keys = KEYS.read();
+        !keys.keys.contains_key(&kid) && keys.can_reload()
+    };
+    if should_reload {
+        if let Err(e) = reload(db).await {
+            error!("Failed to reload signing keys: {}", e);
+        }
+    }
+
+    // A key of another realm never verifies the token, even when the signature would match
+    let keys = KEYS.read();
+    let key = keys
+        .keys
+        .get(&kid)
+        .filter(|key| key.realm_id == realm_id && key.algorithm == header.alg && !key.is_expired())
+        .ok_or(ErrorKind::InvalidToken)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[jwt_token::issuer(realm_id)]);
+    match audience {
+        Some(audience) => {
+            validation.set_audience(&[audience]);
+            validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        }
+        None => {
+            validation.validate_aud = false;
+            validation.set_required_spec_claims(&["exp", "iss"]);
+        }
+    }
+
+    jsonwebtoken::decode::<T>(token, &key.decoding_key, &validation)
+}
@@ -0,0 +1,85 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use entity::{realm, session};
+use jsonwebtoken::errors::Error as JwtError;
+use sea_orm::{prelude::Uuid, ConnectionTrait};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::{jwt_token, signing_key};
+
+pub const SESSION_COOKIE: &str = "shield_session";
+pub const CSRF_COOKIE: &str = "shield_csrf";
+const CSRF_TOKEN_LENGTH: usize = 32; // in bytes
+const CSRF_COOKIE_LIFETIME: i64 = 60 * 60; // in seconds
+
+/// Proof that the browser signed in at Shield, so the user can authorize the realm's clients without signing in again.
+/// It is tied to the session the sign in started, ending that session ends it too.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SsoClaims {
+    pub exp: usize,  // Expiration time (as UTC timestamp)
+    pub iat: usize,  // Issued at (as UTC timestamp)
+    pub iss: String, // Issuer
+    pub sub: Uuid,   // Subject --> User ID
+    pub sid: Uuid,   // Session ID
+    pub auth_time: usize,
+    // Whether the user proved a second factor when signing in
+    pub two_factor: bool,
+}
+
+impl SsoClaims {
+    pub fn new(session: &session::Model, realm_id: Uuid, two_factor: bool) -> Self {
+        let now = Utc::now().timestamp();
+        Self {
+            exp: session.expires.timestamp() as usize,
+            iat: now as usize,
+            iss: jwt_token::issuer(realm_id),
+            sub: session.user_id,
+            sid: session.id,
+            auth_time: session.created_at.timestamp() as usize,
+            two_factor,
+        }
+    }
+
+    pub fn create_token(&self, realm: &realm::Model) -> Result<String, JwtError> {
+        signing_key::sign(&self, realm)
+    }
+}
+
+/// Reads the claims of a session cookie of the realm, `None` when it is invalid or expired.
+pub async fn decode<C: ConnectionTrait>(db: &C, token: &str, realm_id: Uuid) -> Option<SsoClaims> {
+    signing_key::verify::<SsoClaims, C>(db, token, realm_id, None)
+        .await
+        .ok()
+        .map(|token_data| token_data.claims)
+}
+
+pub fn session_cookie(realm_id: Uuid, claims: &SsoClaims, token: &str) -> String {
+    let max_age = claims.exp as i64 - Utc::now().timestamp();
+    cookie(SESSION_COOKIE, token, realm_id, max_age, "Lax")
+}
+
+// The cookie is only sent by pages of Shield, so a form posted from another site cannot match it
+pub fn csrf_cookie(realm_id: Uuid, csrf_token: &str) -> String {
+    cookie(CSRF_COOKIE, csrf_token, realm_id, CSRF_COOKIE_LIFETIME, "Strict")
+}
+
+pub fn new_csrf_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; CSRF_TOKEN_LENGTH]>())
+}
+
+// Cookies are scoped to the realm, as other realms sign in their users with other keys
+fn cookie(name: &str, value: &str, realm_id: Uuid, max_age: i64, same_site: &str) -> String {
+    let issuer = jwt_token::issuer(realm_id);
+    let path = Url::parse(&issuer).map(|url| url.path().to_owned()).unwrap_or_default();
+    let secure = if issuer.starts_with("https://") { "; Secure" } else { "" };
+    format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite={}{}",
+        name,
+        value,
+        path,
+        max_age.max(0),
+        same_site,
+        secure
+    )
+}
@@ -0,0 +1,120 @@
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use url::Url;
+
+const SECRET_LENGTH: usize = 20; // in bytes, the length of an HMAC-SHA1 key
+const DIGITS: u32 = 6;
+const PERIOD: u64 = 30; // in seconds
+
+// Codes of the previous and next period are accepted too, to allow for clocks drifting apart
+const ALLOWED_DRIFT: u64 = 1;
+
+const SECRET_ALPHABET: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+/// A new random secret, base32 encoded the way authenticator apps expect it.
+pub fn generate_secret() -> String {
+    let secret: [u8; SECRET_LENGTH] = rand::random();
+    base32::encode(SECRET_ALPHABET, &secret)
+}
+
+/// The `otpauth://` URI authenticator apps read from a QR code.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    let mut url = Url::parse("otpauth://totp/").unwrap();
+    url.set_path(&format!("{}:{}", issuer, account));
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &PERIOD.to_string());
+    url.to_string()
+}
+
+/// The time step the code was generated for, if it is a valid code for the secret around `now` (as UTC timestamp).
+/// Codes only work once, so none of the step last used or an earlier one is accepted.
+pub fn verify(secret: &str, code: &str, now: u64, last_used_step: Option<u64>) -> Option<u64> {
+    let secret = base32::decode(SECRET_ALPHABET, &secret.to_uppercase())?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let current_step = now / PERIOD;
+    let first_step = match last_used_step {
+        Some(last_used_step) => current_step.saturating_sub(ALLOWED_DRIFT).max(last_used_step + 1),
+        None => current_step.saturating_sub(ALLOWED_DRIFT),
+    };
+    (first_step..=current_step + ALLOWED_DRIFT).find(|step| code_at(&secret, *step) == code)
+}
+
+// HOTP of RFC 4226, with the counter being the number of periods since the epoch as RFC 6238 defines it
+fn code_at(secret: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA1 secret of RFC 6238, appendix B, base32 encoded
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn generates_the_codes_of_the_rfc() {
+        let secret = base32::decode(SECRET_ALPHABET, SECRET).unwrap();
+        // The RFC lists 8 digit codes, of which the last 6 are the 6 digit ones
+        assert_eq!(code_at(&secret, 59 / PERIOD), "287082");
+        assert_eq!(code_at(&secret, 1111111109 / PERIOD), "081804");
+        assert_eq!(code_at(&secret, 1234567890 / PERIOD), "005924");
+        assert_eq!(code_at(&secret, 20000000000 / PERIOD), "353130");
+    }
+
+    #[test]
+    fn accepts_codes_of_the_neighbouring_periods() {
+        let now = 1111111109;
+        assert_eq!(verify(SECRET, "081804", now, None), Some(now / PERIOD));
+        assert_eq!(verify(SECRET, "081804", now - PERIOD, None), Some(now / PERIOD));
+        assert_eq!(verify(SECRET, "081804", now + PERIOD, None), Some(now / PERIOD));
+        assert_eq!(verify(SECRET, "081804", now - 2 * PERIOD, None), None);
+        assert_eq!(verify(SECRET, "081804", now + 2 * PERIOD, None), None);
+    }
+
+    #[test]
+    fn refuses_used_codes() {
+        let now = 1111111109;
+        let step = now / PERIOD;
+        assert_eq!(verify(SECRET, "081804", now, Some(step)), None);
+        // Nor may an older code be used once a newer one was
+        assert_eq!(verify(SECRET, "081804", now + PERIOD, Some(step + 1)), None);
+        assert_eq!(verify(SECRET, "081804", now, Some(step - 1)), Some(step));
+    }
+
+    #[test]
+    fn refuses_malformed_codes() {
+        let now = 1111111109;
+        assert_eq!(verify(SECRET, " 081804 ", now, None), Some(now / PERIOD));
+        assert_eq!(verify(SECRET, "81804", now, None), None);
+        assert_eq!(verify(SECRET, "0818040", now, None), None);
+        assert_eq!(verify(SECRET, "08180a", now, None), None);
+        assert_eq!(verify("not base32!", "081804", now, None), None);
+    }
+
+    #[test]
+    fn provisions_authenticator_apps() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert!(base32::decode(SECRET_ALPHABET, &secret).is_some_and(|secret| secret.len() == SECRET_LENGTH));
+
+        let uri = provisioning_uri(&secret, "Shield", "user@example.com");
+        assert!(uri.starts_with("otpauth://totp/Shield:user@example.com?"));
+        assert!(uri.contains(&format!("secret={}", secret)));
+        assert!(uri.contains("digits=6&period=30"));
+    }
+}
@@ -0,0 +1,650 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use ciborium::Value;
+use coset::{iana, AsCborValue, CborSerializable, CoseKey, KeyType, Label, RegisteredLabelWithPrivate};
+use entity::{client, realm};
+use jsonwebtoken::{crypto, errors::Error as JwtError, Algorithm, DecodingKey};
+use sea_orm::{prelude::Uuid, ConnectionTrait};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::{jwt_token, settings::SETTINGS, signing_key};
+
+// Ceremonies have to be completed this long after they were started
+const CEREMONY_TIMEOUT: i64 = 5 * 60; // in seconds
+
+// Algorithms of the credentials Shield accepts, in order of preference
+const ALGORITHMS: [iana::Algorithm; 3] = [iana::Algorithm::ES256, iana::Algorithm::EdDSA, iana::Algorithm::RS256];
+
+// Flags of the authenticator data, see https://www.w3.org/TR/webauthn-2/#sctn-authenticator-data
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+/// What a ceremony was started for. The challenge is these claims signed by the realm, so starting a ceremony stores nothing
+/// and anyone can start one, the challenge is only recorded once it was used so it cannot be used again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub exp: usize,  // Expiration time (as UTC timestamp)
+    pub iat: usize,  // Issued at (as UTC timestamp)
+    pub iss: String, // Issuer
+    pub aud: Uuid,   // Audience --> Client ID
+    pub jti: Uuid,   // Challenge ID
+    pub ceremony: Ceremony,
+    // The user the ceremony was started for, `None` when it was started without knowing who is signing in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Uuid>,
+}
+
+/// Options for `navigator.credentials.create()`, in the JSON form of `PublicKeyCredential.parseCreationOptionsFromJSON()`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: i64, // in milliseconds
+    pub attestation: &'static str,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+}
+
+/// Options for `navigator.credentials.get()`, in the JSON form of `PublicKeyCredential.parseRequestOptionsFromJSON()`.
+/// Without credentials to allow, the authenticator offers the discoverable credentials it holds for the relying party.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: i64, // in milliseconds
+    pub user_verification: &'static str,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub require_resident_key: bool,
+    pub user_verification: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+}
+
+/// A new credential, in the JSON form of `PublicKeyCredential.toJSON()`.
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// An assertion of an existing credential, in the JSON form of `PublicKeyCredential.toJSON()`.
+#[derive(Debug, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Public key of a credential the authenticator created, the key is stored as the COSE key it came as.
+#[derive(Debug)]
+pub struct NewCredential {
+    // The challenge of the ceremony, which still has to be checked
+    pub challenge: String,
+    pub credential_id: String,
+    pub public_key: String,
+    pub algorithm: i64,
+    pub sign_count: u32,
+}
+
+#[derive(Debug)]
+pub struct VerifiedAssertion {
+    // The challenge of the ceremony, which still has to be checked
+    pub challenge: String,
+    pub sign_count: u32,
+}
+
+impl ChallengeClaims {
+    pub fn new(ceremony: Ceremony, client: &client::Model, user_id: Option<Uuid>) -> Self {
+        let now = Utc::now().timestamp();
+        Self {
+            exp: (now + CEREMONY_TIMEOUT) as usize,
+            iat: now as usize,
+            iss: jwt_token::issuer(client.realm_id),
+            aud: client.id,
+            jti: Uuid::now_v7(),
+            ceremony,
+            sub: user_id,
+        }
+    }
+
+    /// The challenge for the authenticator to sign, which is the signed claims themselves.
+    pub fn create_challenge(&self, realm: &realm::Model) -> Result<String, JwtError> {
+        Ok(URL_SAFE_NO_PAD.encode(signing_key::sign(&self, realm)?))
+    }
+}
+
+impl CreationOptions {
+    pub fn new(challenge: String, user_id: Uuid, email: &str, display_name: &str, exclude_credentials: Vec<String>) -> Self {
+        let settings = &SETTINGS.read().webauthn;
+        Self {
+            challenge,
+            rp: RelyingParty {
+                id: settings.rp_id.clone(),
+                name: settings.rp_name.clone(),
+            },
+            user: UserEntity {
+                id: user_handle(user_id),
+                name: email.to_owned(),
+                display_name: display_name.to_owned(),
+            },
+            pub_key_cred_params: ALGORITHMS
+                .into_iter()
+                .map(|alg| CredentialParameters {
+                    kind: "public-key",
+                    alg: alg as i64,
+                })
+                .collect(),
+            timeout: CEREMONY_TIMEOUT * 1000,
+            // Passkeys are trusted for being bound to the relying party, not for the authenticator they are kept in
+            attestation: "none",
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "required",
+                require_resident_key: true,
+                user_verification: "required",
+            },
+            exclude_credentials: descriptors(exclude_credentials),
+        }
+    }
+}
+
+impl RequestOptions {
+    pub fn new(challenge: String, allow_credentials: Vec<String>) -> Self {
+        Self {
+            challenge,
+            rp_id: SETTINGS.read().webauthn.rp_id.clone(),
+            timeout: CEREMONY_TIMEOUT * 1000,
+            user_verification: "required",
+            allow_credentials: descriptors(allow_credentials),
+        }
+    }
+}
+
+/// Verifies the challenge of a ceremony the client started, challenges of other ceremonies or clients are rejected.
+pub async fn decode_challenge<C: ConnectionTrait>(
+    db: &C,
+    challenge: &str,
+    client: &client::Model,
+    ceremony: Ceremony,
+) -> Result<ChallengeClaims, String> {
+    let token = String::from_utf8(decode(challenge)?).map_err(|_| "Invalid challenge".to_string())?;
+    signing_key::verify::<ChallengeClaims, C>(db, &token, client.realm_id, Some(client.id))
+        .await
+        .ok()
+        .map(|token_data| token_data.claims)
+        .filter(|claims| claims.ceremony == ceremony)
+        .ok_or_else(|| "Unknown or expired challenge".to_string())
+}
+
+/// The user handle of the user's credentials, which authenticators return with assertions of discoverable credentials.
+pub fn user_handle(user_id: Uuid) -> String {
+    URL_SAFE_NO_PAD.encode(user_id.as_bytes())
+}
+
+/// Origins the client's pages are served from, which the browser reports in the client data.
+pub fn allowed_origins(client: &client::Model) -> Vec<String> {
+    let mut origins = SETTINGS.read().webauthn.origins.clone();
+    origins.extend(
+        client
+            .redirect_uris
+            .iter()
+            .filter_map(|uri| Url::parse(uri).ok())
+            .map(|url| url.origin().ascii_serialization()),
+    );
+    origins
+}
+
+/// Verifies the response to a registration ceremony from one of the origins, see https://www.w3.org/TR/webauthn-2/#sctn-registering-a-new-credential.
+/// Attestation statements are not verified, as none are asked for.
+pub fn verify_registration(credential: &RegistrationCredential, origins: &[String]) -> Result<NewCredential, String> {
+    let (challenge, _) = verify_client_data(&credential.response.client_data_json, Ceremony::Registration, origins)?;
+
+    let attestation_object = ciborium::from_reader::<Value, _>(decode(&credential.response.attestation_object)?.as_slice())
+        .map_err(|_| "Invalid attestation object".to_string())?;
+    let authenticator_data = map_value(&attestation_object, |key| key.as_text() == Some("authData"))
+        .and_then(Value::as_bytes)
+        .ok_or("Invalid attestation object")?;
+
+    let (flags, sign_count, attested_credential_data) = verify_authenticator_data(authenticator_data)?;
+    if flags & ATTESTED_CREDENTIAL_DATA == 0 {
+        return Err("The authenticator data holds no credential".to_string());
+    }
+
+    // AAGUID (16 bytes), credential ID length (2 bytes), credential ID, credential public key
+    let credential_id_length = attested_credential_data
+        .get(16..18)
+        .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+        .ok_or("Invalid attested credential data")?;
+    let credential_id = attested_credential_data
+        .get(18..18 + credential_id_length)
+        .ok_or("Invalid attested credential data")?;
+    if URL_SAFE_NO_PAD.encode(credential_id) != credential.id {
+        return Err("The credential ID does not match the authenticator data".to_string());
+    }
+    // Extensions may follow the key, so only the first CBOR item is read
+    let public_key = ciborium::from_reader::<Value, _>(&attested_credential_data[18 + credential_id_length..])
+        .ok()
+        .and_then(|public_key| CoseKey::from_cbor_value(public_key).ok())
+        .ok_or("Invalid credential public key")?;
+    let (_, algorithm) = decoding_key(&public_key)?;
+
+    Ok(NewCredential {
+        challenge,
+        credential_id: credential.id.clone(),
+        public_key: URL_SAFE_NO_PAD.encode(public_key.to_vec().map_err(|e| e.to_string())?),
+        algorithm: algorithm as i64,
+        sign_count,
+    })
+}
+
+/// Verifies the response to an authentication ceremony from one of the origins with the stored public key of the credential,
+/// see https://www.w3.org/TR/webauthn-2/#sctn-verifying-assertion.
+pub fn verify_assertion(credential: &AssertionCredential, origins: &[String], public_key: &str) -> Result<VerifiedAssertion, String> {
+    let (challenge, client_data_hash) = verify_client_data(&credential.response.client_data_json, Ceremony::Authentication, origins)?;
+
+    let authenticator_data = decode(&credential.response.authenticator_data)?;
+    let (_, sign_count, _) = verify_authenticator_data(&authenticator_data)?;
+
+    let public_key = CoseKey::from_slice(&decode(public_key)?).map_err(|_| "Invalid credential public key".to_string())?;
+    let (key, algorithm) = decoding_key(&public_key)?;
+    let signature = decode(&credential.response.signature)?;
+    // ECDSA signatures come DER encoded, JWS expects the two integers concatenated
+    let signature = match algorithm {
+        iana::Algorithm::ES256 => p256::ecdsa::Signature::from_der(&signature)
+            .map_err(|_| "Invalid signature".to_string())?
+            .to_bytes()
+            .to_vec(),
+        _ => signature,
+    };
+
+    let message = [authenticator_data.as_slice(), client_data_hash.as_slice()].concat();
+    let verified = crypto::verify(&URL_SAFE_NO_PAD.encode(signature), &message, &key, jws_algorithm(algorithm)?).unwrap_or(false);
+    if !verified {
+        return Err("Invalid signature".to_string());
+    }
+    Ok(VerifiedAssertion { challenge, sign_count })
+}
+
+// Returns the challenge of the ceremony with the hash of the client data
+fn verify_client_data(client_data_json: &str, ceremony: Ceremony, origins: &[String]) -> Result<(String, Vec<u8>), String> {
+    let client_data_json = decode(client_data_json)?;
+    let client_data = serde_json::from_slice::<ClientData>(&client_data_json).map_err(|_| "Invalid client data".to_string())?;
+
+    let expected_type = match ceremony {
+        Ceremony::Registration => "webauthn.create",
+        Ceremony::Authentication => "webauthn.get",
+    };
+    if client_data.kind != expected_type {
+        return Err(format!("The client data is not of type {}", expected_type));
+    }
+    if !origins.contains(&client_data.origin) {
+        return Err(format!("Origin {} is not allowed", client_data.origin));
+    }
+    Ok((client_data.challenge, Sha256::digest(&client_data_json).to_vec()))
+}
+
+// Checks the data is meant for the relying party and the user was verified, and returns the flags, the signature counter
+// and the attested credential data if any
+fn verify_authenticator_data(authenticator_data: &[u8]) -> Result<(u8, u32, &[u8]), String> {
+    if authenticator_data.len() < 37 {
+        return Err("Invalid authenticator data".to_string());
+    }
+    let rp_id_hash = Sha256::digest(SETTINGS.read().webauthn.rp_id.as_bytes());
+    if authenticator_data[..32] != rp_id_hash[..] {
+        return Err("The credential belongs to another relying party".to_string());
+    }
+
+    let flags = authenticator_data[32];
+    // User verification makes the passkey a second factor of its own, a PIN or biometric on top of holding the authenticator
+    if flags & USER_PRESENT == 0 || flags & USER_VERIFIED == 0 {
+        return Err("The user was not verified".to_string());
+    }
+    let sign_count = u32::from_be_bytes(authenticator_data[33..37].try_into().unwrap());
+    Ok((flags, sign_count, &authenticator_data[37..]))
+}
+
+// Reads the public key of a credential, see RFC 9053 section 7
+fn decoding_key(cose_key: &CoseKey) -> Result<(DecodingKey, iana::Algorithm), String> {
+    let param = |label: i64| cose_key.params.iter().find(|(key, _)| *key == Label::Int(label)).map(|(_, value)| value);
+    let curve = |label: i64| param(label).and_then(Value::as_integer).and_then(|value| i64::try_from(value).ok());
+    let bytes = |label: i64| param(label).and_then(Value::as_bytes).map(|value| URL_SAFE_NO_PAD.encode(value));
+
+    let algorithm = match &cose_key.alg {
+        Some(RegisteredLabelWithPrivate::Assigned(algorithm)) => *algorithm,
+        _ => return Err("The credential public key has no supported algorithm".to_string()),
+    };
+    let key = match (algorithm, &cose_key.kty) {
+        (iana::Algorithm::ES256, KeyType::Assigned(iana::KeyType::EC2))
+            if curve(iana::Ec2KeyParameter::Crv as i64) == Some(iana::EllipticCurve::P_256 as i64) =>
+        {
+            bytes(iana::Ec2KeyParameter::X as i64)
+                .zip(bytes(iana::Ec2KeyParameter::Y as i64))
+                .and_then(|(x, y)| DecodingKey::from_ec_components(&x, &y).ok())
+        }
+        (iana::Algorithm::EdDSA, KeyType::Assigned(iana::KeyType::OKP))
+            if curve(iana::OkpKeyParameter::Crv as i64) == Some(iana::EllipticCurve::Ed25519 as i64) =>
+        {
+            bytes(iana::OkpKeyParameter::X as i64).and_then(|x| DecodingKey::from_ed_components(&x).ok())
+        }
+        (iana::Algorithm::RS256, KeyType::Assigned(iana::KeyType::RSA)) => bytes(iana::RsaKeyParameter::N as i64)
+            .zip(bytes(iana::RsaKeyParameter::E as i64))
+            .and_then(|(n, e)| DecodingKey::from_rsa_components(&n, &e).ok()),
+        _ => return Err(format!("Algorithm {} is not supported", algorithm as i64)),
+    };
+    Ok((key.ok_or("Invalid credential public key")?, algorithm))
+}
+
+fn jws_algorithm(algorithm: iana::Algorithm) -> Result<Algorithm, String> {
+    match algorithm {
+        iana::Algorithm::ES256 => Ok(Algorithm::ES256),
+        iana::Algorithm::EdDSA => Ok(Algorithm::EdDSA),
+        iana::Algorithm::RS256 => Ok(Algorithm::RS256),
+        _ => Err(format!("Algorithm {} is not supported", algorithm as i64)),
+    }
+}
+
+fn map_value(map: &Value, matches: impl Fn(&Value) -> bool) -> Option<&Value> {
+    map.as_map()?.iter().find(|(key, _)| matches(key)).map(|(_, value)| value)
+}
+
+fn descriptors(credential_ids: Vec<String>) -> Vec<CredentialDescriptor> {
+    credential_ids
+        .into_iter()
+        .map(|id| CredentialDescriptor { kind: "public-key", id })
+        .collect()
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| "Invalid base64url encoding".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use coset::CoseKeyBuilder;
+    use ed25519_dalek::Signer as _;
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    const ORIGIN: &str = "https://app.example.com";
+    const CHALLENGE: &str = "Y2hhbGxlbmdl";
+
+    fn origins() -> Vec<String> {
+        vec![ORIGIN.to_string()]
+    }
+
+    fn authenticator_data(flags: u8, sign_count: u32, attested_credential_data: &[u8]) -> Vec<u8> {
+        let rp_id_hash = Sha256::digest(SETTINGS.read().webauthn.rp_id.as_bytes());
+        [&rp_id_hash[..], &[flags], &sign_count.to_be_bytes(), attested_credential_data].concat()
+    }
+
+    fn client_data(kind: &str, origin: &str) -> String {
+        let client_data = serde_json::json!({ "type": kind, "challenge": CHALLENGE, "origin": origin });
+        URL_SAFE_NO_PAD.encode(client_data.to_string())
+    }
+
+    fn encode_key(key: CoseKey) -> String {
+        URL_SAFE_NO_PAD.encode(key.to_vec().unwrap())
+    }
+
+    fn es256_key(signing_key: &p256::ecdsa::SigningKey) -> CoseKey {
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        CoseKeyBuilder::new_ec2_pub_key(iana::EllipticCurve::P_256, point.x().unwrap().to_vec(), point.y().unwrap().to_vec())
+            .algorithm(iana::Algorithm::ES256)
+            .build()
+    }
+
+    fn ed25519_key(signing_key: &ed25519_dalek::SigningKey) -> CoseKey {
+        CoseKeyBuilder::new_okp_key()
+            .algorithm(iana::Algorithm::EdDSA)
+            .param(iana::OkpKeyParameter::Crv as i64, Value::from(iana::EllipticCurve::Ed25519 as i64))
+            .param(
+                iana::OkpKeyParameter::X as i64,
+                Value::Bytes(signing_key.verifying_key().to_bytes().to_vec()),
+            )
+            .build()
+    }
+
+    // An assertion signed over the authenticator data and the hash of the client data, the way authenticators do
+    fn assertion(authenticator_data: &[u8], client_data_json: &str, sign: impl Fn(&[u8]) -> Vec<u8>) -> AssertionCredential {
+        let message = [authenticator_data, &Sha256::digest(decode(client_data_json).unwrap())].concat();
+        AssertionCredential {
+            id: "credential".to_string(),
+            response: AssertionResponse {
+                client_data_json: client_data_json.to_string(),
+                authenticator_data: URL_SAFE_NO_PAD.encode(authenticator_data),
+                signature: URL_SAFE_NO_PAD.encode(sign(&message)),
+                user_handle: None,
+            },
+        }
+    }
+
+    fn es256_assertion(signing_key: &p256::ecdsa::SigningKey, authenticator_data: &[u8], client_data_json: &str) -> AssertionCredential {
+        assertion(authenticator_data, client_data_json, |message| {
+            let signature: p256::ecdsa::Signature = signing_key.sign(message);
+            signature.to_der().as_bytes().to_vec()
+        })
+    }
+
+    fn registration(credential_id: &[u8], authenticator_data: Vec<u8>) -> RegistrationCredential {
+        let attestation_object = Value::Map(vec![
+            (Value::Text("fmt".to_string()), Value::Text("none".to_string())),
+            (Value::Text("attStmt".to_string()), Value::Map(vec![])),
+            (Value::Text("authData".to_string()), Value::Bytes(authenticator_data)),
+        ]);
+        let mut encoded = vec![];
+        ciborium::into_writer(&attestation_object, &mut encoded).unwrap();
+        RegistrationCredential {
+            id: URL_SAFE_NO_PAD.encode(credential_id),
+            response: AttestationResponse {
+                client_data_json: client_data("webauthn.create", ORIGIN),
+                attestation_object: URL_SAFE_NO_PAD.encode(encoded),
+            },
+        }
+    }
+
+    // AAGUID, credential ID length and ID, and the public key, followed by the extensions when there are any
+    fn attested_credential_data(credential_id: &[u8], public_key: CoseKey, extensions: Option<Value>) -> Vec<u8> {
+        let mut data = [&[0u8; 16][..], &(credential_id.len() as u16).to_be_bytes(), credential_id].concat();
+        data.extend(public_key.to_vec().unwrap());
+        if let Some(extensions) = extensions {
+            ciborium::into_writer(&extensions, &mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn verifies_es256_assertions() {
+        let signing_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let data = authenticator_data(USER_PRESENT | USER_VERIFIED, 7, &[]);
+        let credential = es256_assertion(&signing_key, &data, &client_data("webauthn.get", ORIGIN));
+
+        let verified = verify_assertion(&credential, &origins(), &encode_key(es256_key(&signing_key))).unwrap();
+        assert_eq!(verified.challenge, CHALLENGE);
+        assert_eq!(verified.sign_count, 7);
+    }
+
+    #[test]
+    fn verifies_ed25519_assertions() {
+        let signing_key = ed25519_dalek::SigningKey::generate(&mut OsRng);
+        let data = authenticator_data(USER_PRESENT | USER_VERIFIED, 0, &[]);
+        let credential = assertion(&data, &client_data("webauthn.get", ORIGIN), |message| {
+            signing_key.sign(message).to_bytes().to_vec()
+        });
+
+        let verified = verify_assertion(&credential, &origins(), &encode_key(ed25519_key(&signing_key))).unwrap();
+        assert_eq!(verified.sign_count, 0);
+    }
+
+    #[test]
+    fn rejects_signatures_of_other_keys_and_data() {
+        let signing_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let other_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let data = authenticator_data(USER_PRESENT | USER_VERIFIED, 1, &[]);
+        let client_data_json = client_data("webauthn.get", ORIGIN);
+        let public_key = encode_key(es256_key(&signing_key));
+
+        let credential = es256_assertion(&other_key, &data, &client_data_json);
+        assert!(verify_assertion(&credential, &origins(), &public_key).is_err());
+
+        // The counter is part of the signed data
+        let mut credential = es256_assertion(&signing_key, &data, &client_data_json);
+        credential.response.authenticator_data = URL_SAFE_NO_PAD.encode(authenticator_data(USER_PRESENT | USER_VERIFIED, 2, &[]));
+        assert!(verify_assertion(&credential, &origins(), &public_key).is_err());
+    }
+
+    #[test]
+    fn rejects_client_data_of_other_ceremonies_and_origins() {
+        let signing_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let data = authenticator_data(USER_PRESENT | USER_VERIFIED, 1, &[]);
+        let public_key = encode_key(es256_key(&signing_key));
+
+        let credential = es256_assertion(&signing_key, &data, &client_data("webauthn.create", ORIGIN));
+        assert!(verify_assertion(&credential, &origins(), &public_key).is_err());
+        let credential = es256_assertion(&signing_key, &data, &client_data("webauthn.get", "https://evil.example.com"));
+        assert!(verify_assertion(&credential, &origins(), &public_key).is_err());
+    }
+
+    #[test]
+    fn rejects_authenticator_data_of_other_relying_parties_and_unverified_users() {
+        let data = authenticator_data(USER_PRESENT | USER_VERIFIED, 1, &[]);
+        assert!(verify_authenticator_data(&data).is_ok());
+
+        let mut other_relying_party = data.clone();
+        other_relying_party[..32].copy_from_slice(&Sha256::digest(b"evil.example.com"));
+        assert!(verify_authenticator_data(&other_relying_party).is_err());
+        assert!(verify_authenticator_data(&authenticator_data(USER_PRESENT, 1, &[])).is_err());
+        assert!(verify_authenticator_data(&authenticator_data(USER_VERIFIED, 1, &[])).is_err());
+        assert!(verify_authenticator_data(&data[..36]).is_err());
+    }
+
+    #[test]
+    fn reads_registered_credentials() {
+        let signing_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let credential_id = b"credential id";
+        let extensions = Value::Map(vec![(Value::Text("credProtect".to_string()), Value::from(2))]);
+        let attested = attested_credential_data(credential_id, es256_key(&signing_key), Some(extensions));
+        let flags = USER_PRESENT | USER_VERIFIED | ATTESTED_CREDENTIAL_DATA;
+        let credential = registration(credential_id, authenticator_data(flags, 0, &attested));
+
+        let new_credential = verify_registration(&credential, &origins()).unwrap();
+        assert_eq!(new_credential.challenge, CHALLENGE);
+        assert_eq!(new_credential.credential_id, URL_SAFE_NO_PAD.encode(credential_id));
+        assert_eq!(new_credential.algorithm, iana::Algorithm::ES256 as i64);
+
+        // The stored key verifies the assertions of the credential
+        let data = authenticator_data(USER_PRESENT | USER_VERIFIED, 1, &[]);
+        let assertion = es256_assertion(&signing_key, &data, &client_data("webauthn.get", ORIGIN));
+        assert!(verify_assertion(&assertion, &origins(), &new_credential.public_key).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_registrations() {
+        let signing_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let credential_id = b"credential id";
+        let flags = USER_PRESENT | USER_VERIFIED | ATTESTED_CREDENTIAL_DATA;
+        let attested = attested_credential_data(credential_id, es256_key(&signing_key), None);
+
+        // Another credential than the one in the authenticator data
+        let credential = registration(b"other id", authenticator_data(flags, 0, &attested));
+        assert!(verify_registration(&credential, &origins()).is_err());
+        // No credential in the authenticator data
+        let credential = registration(credential_id, authenticator_data(USER_PRESENT | USER_VERIFIED, 0, &[]));
+        assert!(verify_registration(&credential, &origins()).is_err());
+        // Truncated credential data
+        let credential = registration(credential_id, authenticator_data(flags, 0, &attested[..20]));
+        assert!(verify_registration(&credential, &origins()).is_err());
+        // A key of an algorithm that is not supported
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let es384_key = CoseKeyBuilder::new_ec2_pub_key(iana::EllipticCurve::P_256, point.x().unwrap().to_vec(), point.y().unwrap().to_vec())
+            .algorithm(iana::Algorithm::ES384)
+            .build();
+        let attested = attested_credential_data(credential_id, es384_key, None);
+        let credential = registration(credential_id, authenticator_data(flags, 0, &attested));
+        assert!(verify_registration(&credential, &origins()).is_err());
+    }
+
+    #[test]
+    fn rejects_keys_that_do_not_match_their_algorithm() {
+        let signing_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let mut key = es256_key(&signing_key);
+        key.alg = Some(RegisteredLabelWithPrivate::Assigned(iana::Algorithm::EdDSA));
+        assert!(decoding_key(&key).is_err());
+
+        let mut key = es256_key(&signing_key);
+        key.alg = None;
+        assert!(decoding_key(&key).is_err());
+    }
+}
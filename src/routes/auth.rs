@@ -5,7 +5,12 @@ use axum::{
 };
 
 use crate::{
-    handlers::auth::{introspect, login, logout, logout_all, logout_current_session, logout_my_all_sessions, refresh_token, register},
+    handlers::auth::{
+        change_password, confirm_two_factor_authentication, enroll_two_factor_authentication, forgot_password, introspect, login, logout, logout_all,
+        logout_current_session, logout_my_all_sessions, passkey_login, passkey_login_options, passkey_registration_options, refresh_token, register,
+        register_passkey, resend_verification, reset_password, set_password, setup_two_factor_authentication, signup, verify_email,
+        verify_two_factor,
+    },
     middleware::session_info_extractor::session_info_middleware,
 };
 
@@ -15,6 +20,21 @@ pub fn create_routes() -> Router {
         .route("/logout", get(logout_current_session).post(logout))
         .route("/logout-all", get(logout_my_all_sessions).post(logout_all))
         .route("/register", post(register))
+        .route("/signup", post(signup))
+        .route("/verify-email", get(verify_email))
+        .route("/resend-verification", post(resend_verification))
+        .route("/forgot-password", post(forgot_password))
+        .route("/reset-password", post(reset_password))
+        .route("/change-password", post(change_password))
+        .route("/set-password", post(set_password))
+        .route("/two-factor/verify", post(verify_two_factor))
+        .route("/two-factor/enroll", post(enroll_two_factor_authentication))
+        .route("/two-factor/setup", post(setup_two_factor_authentication))
+        .route("/two-factor/confirm", post(confirm_two_factor_authentication))
+        .route("/webauthn/register/options", post(passkey_registration_options))
+        .route("/webauthn/register", post(register_passkey))
+        .route("/webauthn/login/options", post(passkey_login_options))
+        .route("/webauthn/login", post(passkey_login))
         .route("/refresh-token", post(refresh_token))
         .route("/introspect", post(introspect))
         .layer(middleware::from_fn(session_info_middleware))
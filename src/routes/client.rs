@@ -1,11 +1,14 @@
 use axum::{
-    routing::{get, patch},
+    routing::{delete, get, patch, post},
     Router,
 };
 
 use crate::handlers::client::{
     api_user::{create_api_user, delete_api_user, get_api_users, update_api_user},
-    create_client, delete_client, get_client, get_clients, update_client,
+    claim_mapper::{create_claim_mapper, delete_claim_mapper, get_claim_mappers, update_claim_mapper},
+    create_client, delete_client, get_client, get_clients,
+    registration::{approve, get_registrations, reject},
+    update_client,
 };
 
 use super::auth;
@@ -21,6 +24,19 @@ pub fn create_routes() -> Router {
                     .route("/", get(get_api_users).post(create_api_user))
                     .route("/:api_user_id", patch(update_api_user).delete(delete_api_user)),
             )
+            .nest(
+                "/claim-mappers",
+                Router::new()
+                    .route("/", get(get_claim_mappers).post(create_claim_mapper))
+                    .route("/:claim_mapper_id", patch(update_claim_mapper).delete(delete_claim_mapper)),
+            )
+            .nest(
+                "/registrations",
+                Router::new()
+                    .route("/", get(get_registrations))
+                    .route("/:user_id", delete(reject))
+                    .route("/:user_id/approve", post(approve)),
+            )
             .nest("/auth", auth::create_routes()),
     )
 }
@@ -3,8 +3,11 @@ use axum::Router;
 pub mod auth;
 pub mod client;
 pub mod health;
+pub mod oauth;
 pub mod realm;
+pub mod signing_key;
 pub mod user;
+pub mod well_known;
 
 pub fn create_routes() -> Router {
     Router::new()
@@ -0,0 +1,37 @@
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::oauth::{
+        authorize, authorize_form, authorize_login, authorize_two_factor, device_authorization, get_device, introspect, revoke, token, userinfo,
+        verify_device,
+    },
+    middleware::session_info_extractor::session_info_middleware,
+};
+
+pub const AUTHORIZE_PATH: &str = "/authorize";
+const AUTHORIZE_LOGIN_PATH: &str = "/authorize/login";
+const AUTHORIZE_TWO_FACTOR_PATH: &str = "/authorize/two-factor";
+pub const TOKEN_PATH: &str = "/token";
+pub const INTROSPECT_PATH: &str = "/introspect";
+pub const REVOKE_PATH: &str = "/revoke";
+pub const DEVICE_AUTHORIZATION_PATH: &str = "/device_authorization";
+pub const DEVICE_PATH: &str = "/device";
+pub const USERINFO_PATH: &str = "/userinfo";
+
+pub fn create_routes() -> Router {
+    Router::new()
+        .route(AUTHORIZE_PATH, get(authorize).post(authorize_form))
+        .route(AUTHORIZE_LOGIN_PATH, post(authorize_login))
+        .route(AUTHORIZE_TWO_FACTOR_PATH, post(authorize_two_factor))
+        .route(TOKEN_PATH, post(token))
+        .route(INTROSPECT_PATH, post(introspect))
+        .route(REVOKE_PATH, post(revoke))
+        .route(DEVICE_AUTHORIZATION_PATH, post(device_authorization))
+        .route(DEVICE_PATH, get(get_device).post(verify_device))
+        .route(USERINFO_PATH, get(userinfo).post(userinfo))
+        .layer(middleware::from_fn(session_info_middleware))
+}
@@ -2,7 +2,10 @@ use axum::{routing::get, Router};
 
 use crate::handlers::realm::{create_realm, delete_realm, get_realm, get_realms, update_realm};
 
-use super::{client, user};
+use super::{client, oauth, signing_key, user, well_known};
+
+pub const OAUTH_PATH: &str = "/oauth";
+pub const WELL_KNOWN_PATH: &str = "/.well-known";
 
 pub fn create_routes() -> Router {
     Router::new().route("/", get(get_realms).post(create_realm)).nest(
@@ -10,6 +13,9 @@ pub fn create_routes() -> Router {
         Router::new()
             .route("/", get(get_realm).patch(update_realm).delete(delete_realm))
             .nest("/clients", client::create_routes())
-            .nest("/users", user::create_routes()),
+            .nest("/users", user::create_routes())
+            .nest("/keys", signing_key::create_routes())
+            .nest(OAUTH_PATH, oauth::create_routes())
+            .nest(WELL_KNOWN_PATH, well_known::create_routes()),
     )
 }
@@ -0,0 +1,13 @@
+use axum::{
+    routing::{delete, get, post},
+    Router,
+};
+
+use crate::handlers::signing_key::{create_signing_key, delete_signing_key, get_signing_keys, rotate_signing_key};
+
+pub fn create_routes() -> Router {
+    Router::new()
+        .route("/", get(get_signing_keys).post(create_signing_key))
+        .route("/rotate", post(rotate_signing_key))
+        .route("/:key_id", delete(delete_signing_key))
+}
@@ -0,0 +1,12 @@
+use axum::{routing::get, Router};
+
+use crate::handlers::well_known::{get_jwks, get_openid_configuration};
+
+pub const JWKS_PATH: &str = "/jwks.json";
+pub const OPENID_CONFIGURATION_PATH: &str = "/openid-configuration";
+
+pub fn create_routes() -> Router {
+    Router::new()
+        .route(JWKS_PATH, get(get_jwks))
+        .route(OPENID_CONFIGURATION_PATH, get(get_openid_configuration))
+}
@@ -1,36 +1,313 @@
-use crate::packages::{api_token::RefreshTokenClaims, errors::Error};
-use entity::{client, refresh_token};
-use sea_orm::{prelude::Uuid, ActiveModelTrait, DatabaseTransaction, EntityTrait, Set};
+use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
+use entity::{client, refresh_token, resource_group, session, user};
+use sea_orm::{
+    prelude::{Expr, Uuid},
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DatabaseTransaction, EntityTrait, PaginatorTrait, QueryFilter,
+    QuerySelect, Set,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    middleware::session_info_extractor::SessionInfo,
+    packages::{
+        api_token::RefreshTokenClaims,
+        errors::{AuthenticateError, Error},
+        session_cache,
+    },
+    utils::hash::{generate_password_hash, needs_rehash, verify_password},
+};
+
+/// Verifies the user's password and returns the user with their resource group for the client.
+/// Clients requiring verified emails refuse users who have not verified theirs yet.
+pub async fn authenticate_user(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    email: &str,
+    password: &str,
+) -> Result<(user::Model, resource_group::Model), Error> {
+    let user_with_resource_groups = user::Entity::find()
+        .filter(user::Column::Email.eq(email))
+        .find_also_related(resource_group::Entity)
+        .filter(resource_group::Column::RealmId.eq(client.realm_id))
+        .filter(resource_group::Column::ClientId.eq(client.id))
+        .one(db)
+        .await?;
+
+    if user_with_resource_groups.is_none() {
+        debug!("No matching data found");
+        return Err(Error::not_found());
+    }
+
+    let (user, resource_groups) = user_with_resource_groups.unwrap();
+    let (verifying, given) = (user.clone(), password.to_owned());
+    if !verify_password(move || verifying.verify_password(&given)).await? {
+        debug!("Wrong password");
+        return Err(Error::Authenticate(AuthenticateError::WrongCredentials));
+    }
+
+    let user = upgrade_password_hash(db, user, password).await;
+    ensure_can_sign_in(client, user, resource_groups)
+}
+
+// Hashes made with a weaker algorithm or parameters than the settings are replaced while the password is at hand.
+// It is not a password change, so neither the history nor the age of the password are touched.
+async fn upgrade_password_hash(db: &DatabaseConnection, user: user::Model, password: &str) -> user::Model {
+    if !user.password_hash.as_deref().is_some_and(needs_rehash) {
+        return user;
+    }
+
+    let upgraded = async {
+        let password_hash = generate_password_hash(password.to_owned()).await?;
+        let user = user::ActiveModel {
+            id: Set(user.id),
+            password_hash: Set(Some(password_hash)),
+            ..Default::default()
+        };
+        Ok::<_, Error>(user.update(db).await?)
+    };
+    match upgraded.await {
+        Ok(user) => {
+            debug!("Upgraded password hash");
+            user
+        }
+        Err(err) => {
+            // The user proved their password, failing to store a stronger hash should not stop them from signing in
+            warn!("Failed to upgrade password hash: {}", err);
+            user
+        }
+    }
+}
+
+/// Returns the user with their resource group for the client, for users who proved who they are with a token instead of their password.
+pub async fn authenticate_user_by_id(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    user_id: Uuid,
+) -> Result<(user::Model, resource_group::Model), Error> {
+    let (user, resource_groups) = user::Entity::find_by_id(user_id)
+        .find_also_related(resource_group::Entity)
+        .filter(resource_group::Column::RealmId.eq(client.realm_id))
+        .filter(resource_group::Column::ClientId.eq(client.id))
+        .one(db)
+        .await?
+        .ok_or_else(Error::not_found)?;
+
+    ensure_can_sign_in(client, user, resource_groups)
+}
+
+fn ensure_can_sign_in(
+    client: &client::Model,
+    user: user::Model,
+    resource_groups: Option<resource_group::Model>,
+) -> Result<(user::Model, resource_group::Model), Error> {
+    if user.locked_at.is_some() {
+        debug!("User is locked");
+        return Err(Error::Authenticate(AuthenticateError::Locked));
+    }
+
+    if resource_groups.is_none() {
+        debug!("No matching resource group found");
+        return Err(Error::not_found());
+    }
+
+    let resource_groups = resource_groups.unwrap();
+    if resource_groups.awaiting_approval {
+        debug!("Registration is awaiting approval");
+        return Err(Error::Authenticate(AuthenticateError::AwaitingApproval));
+    }
+    if resource_groups.locked_at.is_some() {
+        debug!("Resource group is locked");
+        return Err(Error::Authenticate(AuthenticateError::Locked));
+    }
+    if client.require_verified_email && user.email_verified_at.is_none() {
+        debug!("Email is not verified");
+        return Err(Error::Authenticate(AuthenticateError::EmailNotVerified));
+    }
+
+    Ok((user, resource_groups))
+}
+
+pub async fn insert_session<C: ConnectionTrait>(
+    db: &C,
+    client: &client::Model,
+    user: &user::Model,
+    session_info: Arc<SessionInfo>,
+    refresh_token_id: Option<Uuid>,
+    scope: Option<String>,
+    dpop_jkt: Option<String>,
+) -> Result<session::Model, Error> {
+    let sessions = session::Entity::find()
+        .filter(session::Column::ClientId.eq(client.id))
+        .filter(session::Column::UserId.eq(user.id))
+        .filter(session::Column::Expires.gt(Utc::now()))
+        .count(db)
+        .await?;
+
+    if sessions >= client.max_concurrent_sessions as u64 {
+        debug!("Client has reached max concurrent sessions");
+        return Err(Error::Authenticate(AuthenticateError::MaxConcurrentSessions));
+    }
+
+    let now = Utc::now();
+    let session_model = session::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        user_id: Set(user.id),
+        client_id: Set(client.id),
+        ip_address: Set(session_info.ip_address.to_string()),
+        user_agent: Set(Some(session_info.user_agent.to_string())),
+        browser: Set(Some(session_info.browser.to_string())),
+        browser_version: Set(Some(session_info.browser_version.to_string())),
+        operating_system: Set(Some(session_info.operating_system.to_string())),
+        device_type: Set(Some(session_info.device_type.to_string())),
+        country_code: Set(session_info.country_code.to_string()),
+        refresh_token_id: Set(refresh_token_id),
+        expires: Set(session_expiry(client, now).into()),
+        scope: Set(scope),
+        dpop_jkt: Set(dpop_jkt),
+        // Doubles as the authentication time of ID tokens, so it cannot rely on the column default
+        created_at: Set(now.into()),
+        ..Default::default()
+    };
+    Ok(session_model.insert(db).await?)
+}
+
+/// A session ends once it has been idle for the client's idle timeout, and at the latest when its lifetime has passed.
+fn session_expiry(client: &client::Model, created_at: DateTime<Utc>) -> DateTime<Utc> {
+    let idle_expiry = Utc::now() + chrono::Duration::seconds(client.session_idle_timeout as i64);
+    let absolute_expiry = created_at + chrono::Duration::seconds(client.session_lifetime as i64);
+    idle_expiry.min(absolute_expiry)
+}
+
+/// Hands the session over to the rotated refresh token and pushes back its idle timeout.
+pub async fn extend_session(
+    txn: &DatabaseTransaction,
+    session: &session::Model,
+    client: &client::Model,
+    refresh_token_id: Uuid,
+) -> Result<session::Model, Error> {
+    let session_model = session::ActiveModel {
+        id: Set(session.id),
+        refresh_token_id: Set(Some(refresh_token_id)),
+        expires: Set(session_expiry(client, session.created_at.to_utc()).into()),
+        ..Default::default()
+    };
+    Ok(session_model.update(txn).await?)
+}
+
+/// Starts a new refresh token family, every rotation of the returned token stays in it.
+pub async fn insert_refresh_token<C: ConnectionTrait>(db: &C, user_id: Uuid, client: &client::Model) -> Result<refresh_token::Model, Error> {
+    let id = Uuid::now_v7();
+    let model = refresh_token::ActiveModel {
+        id: Set(id),
+        user_id: Set(user_id),
+        client_id: Set(Some(client.id)),
+        realm_id: Set(client.realm_id),
+        re_used_count: Set(0),
+        family_id: Set(id),
+        replaced_by_id: Set(None),
+        locked_at: Set(None),
+        ..Default::default()
+    };
+    Ok(model.insert(db).await?)
+}
+
+/// Rotates the refresh token: the presented token is locked and replaced by a new one of the same family.
+/// Returns `None` when the token has already been rotated, meaning it is being reused.
 pub async fn handle_refresh_token(
     txn: &DatabaseTransaction,
     refresh_token: &refresh_token::Model,
     client: &client::Model,
-) -> Result<RefreshTokenClaims, Error> {
-    let refresh_token_model = if refresh_token.re_used_count >= client.refresh_token_reuse_limit {
-        refresh_token::Entity::delete_by_id(refresh_token.id).exec(txn).await?;
-        let model = refresh_token::ActiveModel {
-            id: Set(Uuid::now_v7()),
-            user_id: Set(refresh_token.user_id),
-            client_id: Set(Some(client.id)),
-            realm_id: Set(client.realm_id),
-            re_used_count: Set(0),
-            locked_at: Set(None),
-            ..Default::default()
-        };
-        model.insert(txn).await?
-    } else {
-        let model = refresh_token::ActiveModel {
-            id: Set(refresh_token.id),
-            user_id: Set(refresh_token.user_id),
-            client_id: Set(refresh_token.client_id),
-            realm_id: Set(refresh_token.realm_id),
-            re_used_count: Set(refresh_token.re_used_count + 1),
-            locked_at: Set(None),
-            ..Default::default()
-        };
-        model.update(txn).await?
+) -> Result<Option<RefreshTokenClaims>, Error> {
+    let id = Uuid::now_v7();
+    // Only one of two concurrent refreshes with the same token can win the rotation
+    let rotated = refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::LockedAt, Expr::value(Utc::now()))
+        .col_expr(refresh_token::Column::ReplacedById, Expr::value(id))
+        .filter(refresh_token::Column::Id.eq(refresh_token.id))
+        .filter(refresh_token::Column::ReplacedById.is_null())
+        .filter(refresh_token::Column::LockedAt.is_null())
+        .exec(txn)
+        .await?;
+    if rotated.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    let model = refresh_token::ActiveModel {
+        id: Set(id),
+        user_id: Set(refresh_token.user_id),
+        client_id: Set(Some(client.id)),
+        realm_id: Set(client.realm_id),
+        re_used_count: Set(refresh_token.re_used_count + 1),
+        family_id: Set(refresh_token.family_id),
+        replaced_by_id: Set(None),
+        locked_at: Set(None),
+        ..Default::default()
     };
+    let refresh_token_model = model.insert(txn).await?;
+
+    Ok(Some(RefreshTokenClaims::from(&refresh_token_model, client)))
+}
+
+/// A rotated refresh token must never be presented again, if it is then it has leaked.
+/// The whole family is revoked as there is no telling whether the attacker or the legitimate client holds the latest token.
+pub async fn handle_refresh_token_reuse(db: &DatabaseConnection, refresh_token: &refresh_token::Model) -> Result<(), Error> {
+    let rows_affected = revoke_refresh_token_family(db, refresh_token.family_id).await?;
+    warn!(
+        event = "refresh_token_reuse",
+        realm_id = %refresh_token.realm_id,
+        user_id = %refresh_token.user_id,
+        refresh_token_id = %refresh_token.id,
+        family_id = %refresh_token.family_id,
+        revoked_sessions = rows_affected,
+        "Rotated refresh token was reused, revoked its family"
+    );
+    Ok(())
+}
+
+/// Locks every token of the refresh token family and ends the sessions they started.
+pub async fn revoke_refresh_token_family<C: ConnectionTrait>(db: &C, family_id: Uuid) -> Result<u64, Error> {
+    let refresh_token_ids = refresh_token::Entity::find()
+        .select_only()
+        .column(refresh_token::Column::Id)
+        .filter(refresh_token::Column::FamilyId.eq(family_id))
+        .into_tuple::<Uuid>()
+        .all(db)
+        .await?;
+
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::LockedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::FamilyId.eq(family_id))
+        .filter(refresh_token::Column::LockedAt.is_null())
+        .exec(db)
+        .await?;
+
+    revoke_sessions(db, Condition::all().add(session::Column::RefreshTokenId.is_in(refresh_token_ids))).await
+}
+
+/// Ends the matching sessions and locks their refresh tokens, so that none of them can start a new session.
+pub async fn revoke_sessions<C: ConnectionTrait>(db: &C, condition: Condition) -> Result<u64, Error> {
+    let sessions = session::Entity::find().filter(condition).all(db).await?;
+    if sessions.is_empty() {
+        return Ok(0);
+    }
+
+    let refresh_token_ids = sessions.iter().filter_map(|session| session.refresh_token_id).collect::<Vec<Uuid>>();
+    if !refresh_token_ids.is_empty() {
+        refresh_token::Entity::update_many()
+            .col_expr(refresh_token::Column::LockedAt, Expr::value(Utc::now()))
+            .filter(refresh_token::Column::Id.is_in(refresh_token_ids))
+            .filter(refresh_token::Column::LockedAt.is_null())
+            .exec(db)
+            .await?;
+    }
+
+    session_cache::revoke(db, &sessions).await?;
 
-    Ok(RefreshTokenClaims::from(&refresh_token_model, client))
+    let result = session::Entity::delete_many()
+        .filter(session::Column::Id.is_in(sessions.iter().map(|session| session.id)))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
 }
@@ -0,0 +1,69 @@
+use chrono::Utc;
+use entity::claim_mapper;
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DeleteResult, EntityTrait, QueryFilter};
+
+use crate::{
+    mappers::client::claim_mapper::{CreateClaimMapperRequest, UpdateClaimMapperRequest},
+    packages::{claim_mapper::validate, errors::Error},
+};
+
+pub async fn get_all_claim_mappers<C: ConnectionTrait>(db: &C, client_id: Uuid) -> Result<Vec<claim_mapper::Model>, Error> {
+    Ok(claim_mapper::Entity::find()
+        .filter(claim_mapper::Column::ClientId.eq(client_id))
+        .all(db)
+        .await?)
+}
+
+pub async fn insert_claim_mapper(db: &DatabaseConnection, client_id: Uuid, payload: CreateClaimMapperRequest) -> Result<claim_mapper::Model, Error> {
+    let now = Utc::now();
+    let claim_mapper = claim_mapper::Model {
+        id: Uuid::now_v7(),
+        client_id,
+        mapper_type: payload.mapper_type,
+        claim_name: payload.claim_name,
+        source: payload.source,
+        value: payload.value,
+        multivalued: payload.multivalued.unwrap_or(false),
+        created_at: now.into(),
+        updated_at: now.into(),
+    };
+    validate(&claim_mapper)?;
+
+    let claim_mapper: claim_mapper::ActiveModel = claim_mapper.into();
+    Ok(claim_mapper.reset_all().insert(db).await?)
+}
+
+pub async fn update_claim_mapper_by_id(
+    db: &DatabaseConnection,
+    client_id: Uuid,
+    claim_mapper_id: Uuid,
+    payload: UpdateClaimMapperRequest,
+) -> Result<claim_mapper::Model, Error> {
+    let claim_mapper = claim_mapper::Entity::find_by_id(claim_mapper_id)
+        .filter(claim_mapper::Column::ClientId.eq(client_id))
+        .one(db)
+        .await?
+        .ok_or_else(Error::not_found)?;
+
+    let claim_mapper = claim_mapper::Model {
+        mapper_type: payload.mapper_type.unwrap_or(claim_mapper.mapper_type),
+        claim_name: payload.claim_name.unwrap_or(claim_mapper.claim_name),
+        source: payload.source.or(claim_mapper.source),
+        value: payload.value.or(claim_mapper.value),
+        multivalued: payload.multivalued.unwrap_or(claim_mapper.multivalued),
+        updated_at: Utc::now().into(),
+        ..claim_mapper
+    };
+    validate(&claim_mapper)?;
+
+    let claim_mapper: claim_mapper::ActiveModel = claim_mapper.into();
+    Ok(claim_mapper.reset_all().update(db).await?)
+}
+
+pub async fn delete_claim_mapper_by_id(db: &DatabaseConnection, client_id: Uuid, claim_mapper_id: Uuid) -> Result<DeleteResult, Error> {
+    Ok(claim_mapper::Entity::delete_many()
+        .filter(claim_mapper::Column::Id.eq(claim_mapper_id))
+        .filter(claim_mapper::Column::ClientId.eq(client_id))
+        .exec(db)
+        .await?)
+}
@@ -2,11 +2,87 @@ use chrono::Utc;
 use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, DatabaseConnection, DeleteResult, EntityTrait, QueryFilter, Set};
 
 use crate::{
-    mappers::client::{CreateClientRequest, UpdateClientRequest},
-    packages::errors::{AuthenticateError, Error},
-    utils::default_resource_checker::is_default_client,
+    mappers::{
+        auth::ResourceSubset,
+        client::{CreateClientRequest, UpdateClientRequest},
+    },
+    packages::{
+        errors::{AuthenticateError, Error},
+        session_cache,
+    },
+    utils::{
+        default_resource_checker::is_default_client,
+        helpers::generate_random_string::{generate_random_string, Length},
+    },
 };
-use entity::client;
+use entity::{client, sea_orm_active_enums::ClientType};
+use url::Url;
+
+// Redirect URIs are matched exactly, so they have to be absolute and must not carry a fragment
+fn validate_redirect_uris(redirect_uris: &[String]) -> Result<(), Error> {
+    for redirect_uri in redirect_uris {
+        match Url::parse(redirect_uri) {
+            Ok(url) if url.fragment().is_none() => {}
+            _ => return Err(Error::cannot_perform_operation(&format!("Invalid redirect URI: {}", redirect_uri))),
+        }
+    }
+    Ok(())
+}
+
+// Audiences end up in the `aud` claim next to the client's own id, where resource servers match them verbatim
+fn validate_audiences(audiences: &[String]) -> Result<(), Error> {
+    for audience in audiences {
+        if audience.is_empty() || audience.chars().any(char::is_whitespace) {
+            return Err(Error::cannot_perform_operation(&format!("Invalid audience: {}", audience)));
+        }
+    }
+    Ok(())
+}
+
+// A client may only exchange tokens for other clients of its own realm
+async fn validate_token_exchange_audiences(db: &DatabaseConnection, realm_id: Uuid, audiences: &[Uuid]) -> Result<(), Error> {
+    let clients = client::Entity::find()
+        .filter(client::Column::RealmId.eq(realm_id))
+        .filter(client::Column::Id.is_in(audiences.to_vec()))
+        .all(db)
+        .await?;
+    match audiences.iter().find(|audience| !clients.iter().any(|client| client.id == **audience)) {
+        Some(audience) => Err(Error::cannot_perform_operation(&format!("Invalid token exchange audience: {}", audience))),
+        None => Ok(()),
+    }
+}
+
+// Users signing up on their own get the template's resources, and users without any resource cannot log in
+fn validate_registration(allow_self_registration: bool, template: Option<&ResourceSubset>) -> Result<(), Error> {
+    match template {
+        Some(template) if template.group_name.is_empty() || template.identifiers.is_empty() => Err(Error::cannot_perform_operation(
+            "Registration template needs a group name and at least one resource",
+        )),
+        None if allow_self_registration => Err(Error::cannot_perform_operation("Self registration needs a registration template")),
+        _ => Ok(()),
+    }
+}
+
+// Domains are matched against the part of the email after the `@`, ignoring case
+fn normalize_email_domains(domains: Vec<String>) -> Result<Vec<String>, Error> {
+    domains
+        .into_iter()
+        .map(|domain| {
+            if domain.is_empty() || domain.contains('@') || domain.chars().any(char::is_whitespace) {
+                return Err(Error::cannot_perform_operation(&format!("Invalid email domain: {}", domain)));
+            }
+            Ok(domain.to_lowercase())
+        })
+        .collect()
+}
+
+// Only confidential clients hold a secret
+fn client_secret(client_type: &ClientType, secret: Option<String>) -> Option<String> {
+    match client_type {
+        ClientType::Confidential => Some(secret.unwrap_or_else(|| generate_random_string(Length::U64))),
+        ClientType::Public => None,
+    }
+}
 
 pub async fn get_all_clients(db: &DatabaseConnection, realm_id: Uuid) -> Result<Vec<client::Model>, Error> {
     Ok(client::Entity::find().filter(client::Column::RealmId.eq(realm_id)).all(db).await?)
@@ -17,10 +93,37 @@ pub async fn get_client_by_id(db: &DatabaseConnection, client_id: Uuid) -> Resul
 }
 
 pub async fn insert_client(db: &DatabaseConnection, payload: CreateClientRequest) -> Result<client::Model, Error> {
+    let redirect_uris = payload.redirect_uris.unwrap_or_default();
+    validate_redirect_uris(&redirect_uris)?;
+
+    let audiences = payload.audiences.unwrap_or_default();
+    validate_audiences(&audiences)?;
+
+    let token_exchange_audiences = payload.token_exchange_audiences.unwrap_or_default();
+    validate_token_exchange_audiences(db, payload.realm_id, &token_exchange_audiences).await?;
+
+    let allow_self_registration = payload.allow_self_registration.unwrap_or(false);
+    validate_registration(allow_self_registration, payload.registration_template.as_ref())?;
+    let registration_email_domains = normalize_email_domains(payload.registration_email_domains.unwrap_or_default())?;
+
+    let client_type = payload.client_type.unwrap_or(ClientType::Public);
     let client = client::ActiveModel {
         id: Set(Uuid::now_v7()),
         name: Set(payload.name.to_owned()),
         realm_id: Set(payload.realm_id),
+        secret: Set(client_secret(&client_type, None)),
+        client_type: Set(client_type),
+        redirect_uris: Set(redirect_uris),
+        use_stateful_access_token: Set(payload.use_stateful_access_token.unwrap_or(false)),
+        audiences: Set(audiences),
+        token_exchange_audiences: Set(token_exchange_audiences),
+        require_dpop: Set(payload.require_dpop.unwrap_or(false)),
+        allow_self_registration: Set(allow_self_registration),
+        registration_template: Set(payload.registration_template.map(serde_json::to_value).transpose()?),
+        registration_email_domains: Set(registration_email_domains),
+        require_registration_approval: Set(payload.require_registration_approval.unwrap_or(false)),
+        require_verified_email: Set(payload.require_verified_email.unwrap_or(false)),
+        two_factor_enabled_at: Set(payload.require_two_factor.unwrap_or(false).then(|| Utc::now().into())),
         ..Default::default()
     };
     Ok(client.insert(db).await?)
@@ -31,14 +134,38 @@ pub async fn update_client_by_id(db: &DatabaseConnection, client_id: Uuid, paylo
         return Err(Error::cannot_perform_operation("Cannot lock the default client"));
     }
 
+    if let Some(redirect_uris) = &payload.redirect_uris {
+        validate_redirect_uris(redirect_uris)?;
+    }
+    if let Some(audiences) = &payload.audiences {
+        validate_audiences(audiences)?;
+    }
+
+    let registration_email_domains = payload.registration_email_domains.map(normalize_email_domains).transpose()?;
+
     let client = get_client_by_id(db, client_id).await?;
     match client {
         Some(client) => {
+            if let Some(token_exchange_audiences) = &payload.token_exchange_audiences {
+                validate_token_exchange_audiences(db, client.realm_id, token_exchange_audiences).await?;
+            }
+            let allow_self_registration = payload.allow_self_registration.unwrap_or(client.allow_self_registration);
+            let registration_template = match payload.registration_template {
+                Some(registration_template) => Some(registration_template),
+                None => client.registration_template.map(serde_json::from_value).transpose()?,
+            };
+            validate_registration(allow_self_registration, registration_template.as_ref())?;
+
             let locked_at = match payload.lock {
                 Some(true) => Some(client.locked_at.unwrap_or_else(|| Utc::now().into())),
                 Some(false) => None,
                 None => client.locked_at,
             };
+            let two_factor_enabled_at = match payload.require_two_factor {
+                Some(true) => Some(client.two_factor_enabled_at.unwrap_or_else(|| Utc::now().into())),
+                Some(false) => None,
+                None => client.two_factor_enabled_at,
+            };
 
             let updated_client = client::ActiveModel {
                 id: Set(client.id),
@@ -55,18 +182,63 @@ pub async fn update_client_by_id(db: &DatabaseConnection, client_id: Uuid, paylo
                     Some(session_lifetime) => session_lifetime,
                     None => client.session_lifetime,
                 }),
+                session_idle_timeout: Set(match payload.session_idle_timeout {
+                    Some(session_idle_timeout) => session_idle_timeout,
+                    None => client.session_idle_timeout,
+                }),
+                access_token_lifetime: Set(match payload.access_token_lifetime {
+                    Some(access_token_lifetime) => access_token_lifetime,
+                    None => client.access_token_lifetime,
+                }),
                 refresh_token_lifetime: Set(match payload.refresh_token_lifetime {
                     Some(refresh_token_lifetime) => refresh_token_lifetime,
                     None => client.refresh_token_lifetime,
                 }),
-                refresh_token_reuse_limit: Set(match payload.refresh_token_reuse_limit {
-                    Some(refresh_token_reuse_limit) => refresh_token_reuse_limit,
-                    None => client.refresh_token_reuse_limit,
+                secret: Set(client_secret(payload.client_type.as_ref().unwrap_or(&client.client_type), client.secret)),
+                client_type: Set(match payload.client_type {
+                    Some(client_type) => client_type,
+                    None => client.client_type,
+                }),
+                redirect_uris: Set(match payload.redirect_uris {
+                    Some(redirect_uris) => redirect_uris,
+                    None => client.redirect_uris,
+                }),
+                use_stateful_access_token: Set(match payload.use_stateful_access_token {
+                    Some(use_stateful_access_token) => use_stateful_access_token,
+                    None => client.use_stateful_access_token,
+                }),
+                audiences: Set(match payload.audiences {
+                    Some(audiences) => audiences,
+                    None => client.audiences,
+                }),
+                token_exchange_audiences: Set(match payload.token_exchange_audiences {
+                    Some(token_exchange_audiences) => token_exchange_audiences,
+                    None => client.token_exchange_audiences,
+                }),
+                require_dpop: Set(match payload.require_dpop {
+                    Some(require_dpop) => require_dpop,
+                    None => client.require_dpop,
+                }),
+                allow_self_registration: Set(allow_self_registration),
+                registration_template: Set(registration_template.map(serde_json::to_value).transpose()?),
+                registration_email_domains: Set(match registration_email_domains {
+                    Some(registration_email_domains) => registration_email_domains,
+                    None => client.registration_email_domains,
+                }),
+                require_registration_approval: Set(match payload.require_registration_approval {
+                    Some(require_registration_approval) => require_registration_approval,
+                    None => client.require_registration_approval,
+                }),
+                require_verified_email: Set(match payload.require_verified_email {
+                    Some(require_verified_email) => require_verified_email,
+                    None => client.require_verified_email,
                 }),
+                two_factor_enabled_at: Set(two_factor_enabled_at),
                 locked_at: Set(locked_at),
                 ..Default::default()
             };
             let updated_client = updated_client.update(db).await?;
+            session_cache::forget_client(updated_client.id);
             Ok(updated_client)
         }
         None => Err(Error::Authenticate(AuthenticateError::NoResource)),
@@ -1,4 +1,12 @@
 pub mod auth;
+pub mod claim_mapper;
 pub mod client;
+pub mod oauth;
+pub mod password;
 pub mod realm;
+pub mod registration;
+pub mod signing_key;
+pub mod two_factor;
 pub mod user;
+pub mod verification;
+pub mod webauthn;
@@ -0,0 +1,1135 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use entity::{
+    api_user, authorization_code, client, device_code, realm, refresh_token, resource, resource_group, sea_orm_active_enums::ClientType, session,
+    user,
+};
+use rand::Rng;
+use sea_orm::{
+    prelude::{Expr, Uuid},
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DatabaseTransaction, EntityTrait, QueryFilter, Set,
+    TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    mappers::oauth::{AuthorizeRequest, IntrospectionRequest, IntrospectionResponse, TokenRequest, TokenResponse, ACCESS_TOKEN_TYPE},
+    middleware::session_info_extractor::SessionInfo,
+    packages::{
+        api_token::{ApiUser, RefreshTokenClaims, ServiceClaims},
+        dpop::{self, Confirmation, DpopKey},
+        errors::{AuthenticateError, Error, OAuthError},
+        jwt_token::{self, Actor, Claims, IdTokenClaims, JwtUser, StandardClaims},
+        session_cache, signing_key,
+        sso::SsoClaims,
+    },
+    services::{
+        auth::{authenticate_user_by_id, insert_refresh_token, insert_session, revoke_refresh_token_family, revoke_sessions},
+        claim_mapper::get_all_claim_mappers,
+        password::expire_outdated_password,
+        two_factor::requires_two_factor,
+    },
+    utils::{
+        helpers::generate_random_string::{generate_random_string, Length},
+        role_checker::{is_current_realm_admin, is_master_realm_admin},
+    },
+};
+use tracing::info;
+
+const AUTHORIZATION_CODE_LIFETIME: i64 = 60; // in seconds
+const DEVICE_CODE_LIFETIME: i64 = 600; // in seconds
+pub const DEVICE_CODE_INTERVAL: i64 = 5; // in seconds
+const IMPERSONATION_LIFETIME: i64 = 900; // in seconds
+const USER_CODE_LENGTH: usize = 8;
+// Consonants only, so that codes cannot spell words and are hard to mistype
+const USER_CODE_CHARSET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+pub async fn authenticate_client(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    client_id: Option<Uuid>,
+    client_secret: Option<&str>,
+) -> Result<client::Model, Error> {
+    let client_id = client_id.ok_or(OAuthError::InvalidClient)?;
+    let client = client::Entity::find_active_by_id(db, client_id)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or(OAuthError::InvalidClient)?;
+
+    // Public clients have no secret to present, PKCE binds their codes instead
+    if client.client_type == ClientType::Confidential && (client.secret.is_none() || client.secret.as_deref() != client_secret) {
+        return Err(OAuthError::InvalidClient.into());
+    }
+
+    Ok(client)
+}
+
+pub fn validate_authorize_request(client: &client::Model, payload: &AuthorizeRequest) -> Result<(), OAuthError> {
+    if payload.response_type != "code" {
+        return Err(OAuthError::UnsupportedResponseType);
+    }
+
+    match (&payload.code_challenge, payload.code_challenge_method.as_deref()) {
+        (Some(_), Some("S256")) => Ok(()),
+        (Some(_), _) => Err(OAuthError::InvalidRequest("Only the S256 code_challenge_method is supported".to_string())),
+        (None, _) if client.client_type == ClientType::Public => {
+            Err(OAuthError::InvalidRequest("code_challenge is required for public clients".to_string()))
+        }
+        (None, _) => Ok(()),
+    }
+}
+
+/// Starts a session for the authenticated user and issues a single-use code bound to it.
+pub async fn create_authorization_code(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    user: &user::Model,
+    session_info: Arc<SessionInfo>,
+    payload: &AuthorizeRequest,
+) -> Result<authorization_code::Model, Error> {
+    let client = client.clone();
+    let user = user.clone();
+    let redirect_uri = payload.redirect_uri.clone();
+    let scope = payload.scope.clone();
+    let nonce = payload.nonce.clone();
+    let code_challenge = payload.code_challenge.clone();
+
+    Ok(db
+        .transaction(|txn| {
+            Box::pin(async move {
+                let session = insert_session(txn, &client, &user, session_info, None, scope.clone(), None).await?;
+                let code_model = authorization_code::ActiveModel {
+                    id: Set(Uuid::now_v7()),
+                    code: Set(generate_random_string(Length::U32)),
+                    realm_id: Set(client.realm_id),
+                    client_id: Set(client.id),
+                    user_id: Set(user.id),
+                    session_id: Set(session.id),
+                    redirect_uri: Set(redirect_uri),
+                    scope: Set(scope),
+                    nonce: Set(nonce),
+                    code_challenge: Set(code_challenge),
+                    expires_at: Set((Utc::now() + Duration::seconds(AUTHORIZATION_CODE_LIFETIME)).into()),
+                    ..Default::default()
+                };
+                Ok::<_, Error>(code_model.insert(txn).await?)
+            })
+        })
+        .await?)
+}
+
+/// The user the browser signed in at Shield as, when they can authorize the client without signing in again.
+/// Users who have to change their password or prove a second factor they did not prove when signing in have to sign in again.
+pub async fn find_signed_in_user(
+    db: &DatabaseConnection,
+    realm: &realm::Model,
+    client: &client::Model,
+    claims: &SsoClaims,
+) -> Result<Option<user::Model>, Error> {
+    let session = session::Entity::find_by_id(claims.sid)
+        .filter(session::Column::UserId.eq(claims.sub))
+        .filter(session::Column::Expires.gt(Utc::now()))
+        .one(db)
+        .await?;
+    if session.is_none() {
+        return Ok(None);
+    }
+    let Ok((user, _)) = authenticate_user_by_id(db, client, claims.sub).await else {
+        return Ok(None);
+    };
+
+    let user = expire_outdated_password(db, realm, user).await?;
+    if user.is_temp_password || (requires_two_factor(client, &user) && !claims.two_factor) {
+        return Ok(None);
+    }
+    Ok(Some(user))
+}
+
+// RFC 7636: 43 to 128 characters from the unreserved set
+fn is_valid_code_verifier(code_verifier: &str) -> bool {
+    (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'))
+}
+
+fn verify_code_challenge(code_challenge: &str, code_verifier: &str) -> bool {
+    is_valid_code_verifier(code_verifier) && URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes())) == code_challenge
+}
+
+// Whether the code can be exchanged by the client, with the redirect URI and code verifier of its authorization request
+fn check_authorization_code(
+    code: &authorization_code::Model,
+    client_id: Uuid,
+    redirect_uri: Option<&str>,
+    code_verifier: Option<&str>,
+) -> Result<(), OAuthError> {
+    if code.used_at.is_some() {
+        return Err(OAuthError::InvalidGrant("Authorization code has already been used".to_string()));
+    }
+    if code.client_id != client_id || code.expires_at < Utc::now() {
+        return Err(OAuthError::InvalidGrant("Invalid authorization code".to_string()));
+    }
+    if redirect_uri != Some(code.redirect_uri.as_str()) {
+        return Err(OAuthError::InvalidGrant(
+            "redirect_uri does not match the authorization request".to_string(),
+        ));
+    }
+    if let Some(code_challenge) = &code.code_challenge {
+        match code_verifier {
+            Some(code_verifier) if verify_code_challenge(code_challenge, code_verifier) => {}
+            _ => return Err(OAuthError::InvalidGrant("Invalid code_verifier".to_string())),
+        }
+    }
+    Ok(())
+}
+
+pub async fn exchange_authorization_code(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    dpop: &DpopKey,
+    payload: &TokenRequest,
+) -> Result<TokenResponse, Error> {
+    let dpop_jkt = dpop.binding(client)?;
+    let code = payload
+        .code
+        .as_deref()
+        .ok_or_else(|| OAuthError::InvalidRequest("code is required".to_string()))?;
+    let code = authorization_code::Entity::find()
+        .filter(authorization_code::Column::Code.eq(code))
+        .one(db)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidGrant("Invalid authorization code".to_string()))?;
+
+    if let Err(err) = check_authorization_code(&code, client.id, payload.redirect_uri.as_deref(), payload.code_verifier.as_deref()) {
+        // A replayed code means it leaked, so the session it started is ended as well
+        if code.used_at.is_some() {
+            revoke_sessions(db, Condition::all().add(session::Column::Id.eq(code.session_id))).await?;
+        }
+        return Err(err.into());
+    }
+
+    let client = client.clone();
+    db.transaction(|txn| {
+        Box::pin(async move {
+            let result = authorization_code::Entity::update_many()
+                .col_expr(authorization_code::Column::UsedAt, Expr::value(Utc::now()))
+                .col_expr(authorization_code::Column::UpdatedAt, Expr::value(Utc::now()))
+                .filter(authorization_code::Column::Id.eq(code.id))
+                .filter(authorization_code::Column::UsedAt.is_null())
+                .exec(txn)
+                .await?;
+            if result.rows_affected != 1 {
+                return Err(OAuthError::InvalidGrant("Authorization code has already been used".to_string()).into());
+            }
+
+            let session = session::Entity::find_by_id(code.session_id)
+                .filter(session::Column::Expires.gt(Utc::now()))
+                .one(txn)
+                .await?
+                .ok_or_else(|| OAuthError::InvalidGrant("Session is no longer valid".to_string()))?;
+
+            issue_session_tokens(txn, &client, code.user_id, session, code.nonce, dpop_jkt).await
+        })
+    })
+    .await
+    .map_err(Error::from)
+}
+
+fn is_openid_scope(scope: &str) -> bool {
+    scope.split_whitespace().any(|scope| scope == "openid")
+}
+
+/// Loads what an access token for the client carries about the user, `None` if the user has no access to the client.
+async fn find_client_access<C: ConnectionTrait>(
+    db: &C,
+    client: &client::Model,
+    user_id: Uuid,
+) -> Result<Option<(user::Model, resource_group::Model, Vec<resource::Model>)>, Error> {
+    let Some(user) = user::Entity::find_by_id(user_id)
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .filter(user::Column::LockedAt.is_null())
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(resource_group) = resource_group::Entity::find()
+        .filter(resource_group::Column::RealmId.eq(client.realm_id))
+        .filter(resource_group::Column::ClientId.eq(client.id))
+        .filter(resource_group::Column::UserId.eq(user.id))
+        .filter(resource_group::Column::LockedAt.is_null())
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let resources = resource::Entity::find()
+        .filter(resource::Column::GroupId.eq(resource_group.id))
+        .filter(resource::Column::LockedAt.is_null())
+        .all(db)
+        .await?;
+    if resources.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some((user, resource_group, resources)))
+}
+
+/// Mints the same access and refresh token pair as a password login for a session the user has already started.
+async fn issue_session_tokens(
+    txn: &DatabaseTransaction,
+    client: &client::Model,
+    user_id: Uuid,
+    session: session::Model,
+    nonce: Option<String>,
+    dpop_jkt: Option<String>,
+) -> Result<TokenResponse, Error> {
+    let (user, resource_group, resources) = find_client_access(txn, client, user_id)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidGrant("Session is no longer valid".to_string()))?;
+    let realm = realm::Entity::find_by_id(client.realm_id).one(txn).await?.ok_or_else(Error::not_found)?;
+
+    // Binding the session rather than the tokens alone keeps the refreshed tokens bound to the same key
+    let session = match dpop_jkt {
+        Some(dpop_jkt) => {
+            session::ActiveModel {
+                id: Set(session.id),
+                dpop_jkt: Set(Some(dpop_jkt)),
+                ..Default::default()
+            }
+            .update(txn)
+            .await?
+        }
+        None => session,
+    };
+
+    let refresh_token = if client.use_refresh_token {
+        let refresh_token = insert_refresh_token(txn, user.id, client).await?;
+
+        let session_model = session::ActiveModel {
+            id: Set(session.id),
+            refresh_token_id: Set(Some(refresh_token.id)),
+            ..Default::default()
+        };
+        session_model.update(txn).await?;
+
+        let claims = RefreshTokenClaims::from(&refresh_token, client);
+        Some(claims.create_token(&realm).map_err(|e| Error::SigningKey(e.to_string()))?)
+    } else {
+        None
+    };
+
+    // ID tokens are only issued to clients that asked for them with the openid scope
+    let id_token = if session.scope.as_deref().is_some_and(is_openid_scope) {
+        let claims = IdTokenClaims::new(&user, client, &session, nonce);
+        Some(claims.create_token(&realm).map_err(|e| Error::SigningKey(e.to_string()))?)
+    } else {
+        None
+    };
+    let mappers = get_all_claim_mappers(txn, client.id).await?;
+    let access_token =
+        jwt_token::create(user, client, resource_group, resources, &session, &mappers, &realm).map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    Ok(TokenResponse {
+        access_token,
+        token_type: dpop::token_type(session.dpop_jkt.is_some()),
+        expires_in: jwt_token::expiry(client, &session) as i64 - Utc::now().timestamp(),
+        refresh_token,
+        id_token,
+        scope: session.scope,
+        issued_token_type: None,
+    })
+}
+
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    let user_code = (0..USER_CODE_LENGTH)
+        .map(|_| USER_CODE_CHARSET[rng.gen_range(0..USER_CODE_CHARSET.len())] as char)
+        .collect::<String>();
+    format_user_code(&user_code)
+}
+
+fn format_user_code(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(USER_CODE_LENGTH / 2);
+    format!("{}-{}", first, second)
+}
+
+// Users may type the code in lowercase, without the dash or with extra spaces
+fn normalize_user_code(user_code: &str) -> String {
+    let user_code = user_code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+    if user_code.len() != USER_CODE_LENGTH {
+        return user_code;
+    }
+    format_user_code(&user_code)
+}
+
+/// Starts a device authorization, the device polls with the device code while the user approves the user code elsewhere.
+pub async fn create_device_code(db: &DatabaseConnection, client: &client::Model, scope: Option<String>) -> Result<device_code::Model, Error> {
+    let device_code_model = device_code::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        device_code: Set(generate_random_string(Length::U32)),
+        user_code: Set(generate_user_code()),
+        realm_id: Set(client.realm_id),
+        client_id: Set(client.id),
+        scope: Set(scope),
+        expires_at: Set((Utc::now() + Duration::seconds(DEVICE_CODE_LIFETIME)).into()),
+        ..Default::default()
+    };
+    Ok(device_code_model.insert(db).await?)
+}
+
+async fn get_pending_device_code(db: &DatabaseConnection, realm_id: Uuid, user_code: &str) -> Result<device_code::Model, Error> {
+    device_code::Entity::find()
+        .filter(device_code::Column::UserCode.eq(normalize_user_code(user_code)))
+        .filter(device_code::Column::RealmId.eq(realm_id))
+        .filter(device_code::Column::ApprovedAt.is_null())
+        .filter(device_code::Column::DeniedAt.is_null())
+        .filter(device_code::Column::ExpiresAt.gt(Utc::now()))
+        .one(db)
+        .await?
+        .ok_or_else(Error::not_found)
+}
+
+/// Finds the pending device authorization the signed in user is about to decide on.
+/// Users decide with a token of the device's client, so they signed in to that client, with its second factor if it requires one.
+pub async fn get_device_code_to_verify(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    jwt_user: &JwtUser,
+    user_code: &str,
+) -> Result<device_code::Model, Error> {
+    let device_code = get_pending_device_code(db, realm_id, user_code).await?;
+    if jwt_user.resource.as_ref().map(|resource| resource.client_id) != Some(device_code.client_id) {
+        return Err(Error::Authenticate(AuthenticateError::ActionForbidden));
+    }
+    Ok(device_code)
+}
+
+/// Records the signed in user's decision on a pending device authorization.
+pub async fn verify_device_code(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    jwt_user: &JwtUser,
+    user_code: &str,
+    approve: bool,
+) -> Result<device_code::Model, Error> {
+    let device_code = get_device_code_to_verify(db, realm_id, jwt_user, user_code).await?;
+    let user = user::Entity::find_by_id(jwt_user.sub)
+        .filter(user::Column::RealmId.eq(realm_id))
+        .filter(user::Column::LockedAt.is_null())
+        .one(db)
+        .await?
+        .ok_or(AuthenticateError::ActionForbidden)?;
+
+    // The device would otherwise only find out that the user has no access to the client when redeeming the code
+    if approve {
+        resource_group::Entity::find()
+            .filter(resource_group::Column::ClientId.eq(device_code.client_id))
+            .filter(resource_group::Column::UserId.eq(user.id))
+            .filter(resource_group::Column::LockedAt.is_null())
+            .one(db)
+            .await?
+            .ok_or(AuthenticateError::NoResource)?;
+    }
+
+    let decision_column = if approve {
+        device_code::Column::ApprovedAt
+    } else {
+        device_code::Column::DeniedAt
+    };
+    let result = device_code::Entity::update_many()
+        .col_expr(decision_column, Expr::value(Utc::now()))
+        .col_expr(device_code::Column::UserId, Expr::value(user.id))
+        .col_expr(device_code::Column::UpdatedAt, Expr::value(Utc::now()))
+        .filter(device_code::Column::Id.eq(device_code.id))
+        .filter(device_code::Column::ApprovedAt.is_null())
+        .filter(device_code::Column::DeniedAt.is_null())
+        .exec(db)
+        .await?;
+    if result.rows_affected != 1 {
+        return Err(Error::not_found());
+    }
+
+    Ok(device_code)
+}
+
+pub async fn exchange_device_code(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    session_info: Arc<SessionInfo>,
+    dpop: &DpopKey,
+    payload: &TokenRequest,
+) -> Result<TokenResponse, Error> {
+    let dpop_jkt = dpop.binding(client)?;
+    let device_code = payload
+        .device_code
+        .as_deref()
+        .ok_or_else(|| OAuthError::InvalidRequest("device_code is required".to_string()))?;
+    let device_code = device_code::Entity::find()
+        .filter(device_code::Column::DeviceCode.eq(device_code))
+        .filter(device_code::Column::ClientId.eq(client.id))
+        .one(db)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidGrant("Invalid device code".to_string()))?;
+
+    if device_code.expires_at < Utc::now() {
+        device_code::Entity::delete_by_id(device_code.id).exec(db).await?;
+        return Err(OAuthError::ExpiredToken.into());
+    }
+    if device_code.denied_at.is_some() {
+        device_code::Entity::delete_by_id(device_code.id).exec(db).await?;
+        return Err(OAuthError::AccessDenied.into());
+    }
+    let (Some(_), Some(user_id)) = (device_code.approved_at, device_code.user_id) else {
+        let polled_too_soon = device_code
+            .last_polled_at
+            .is_some_and(|last_polled_at| Utc::now() - last_polled_at.to_utc() < Duration::seconds(DEVICE_CODE_INTERVAL));
+        device_code::ActiveModel {
+            id: Set(device_code.id),
+            last_polled_at: Set(Some(Utc::now().into())),
+            ..Default::default()
+        }
+        .update(db)
+        .await?;
+        return Err(if polled_too_soon {
+            OAuthError::SlowDown
+        } else {
+            OAuthError::AuthorizationPending
+        }
+        .into());
+    };
+
+    let client = client.clone();
+    db.transaction(|txn| {
+        Box::pin(async move {
+            // Deleting the approved code makes sure that only one poll can redeem it
+            let result = device_code::Entity::delete_many()
+                .filter(device_code::Column::Id.eq(device_code.id))
+                .filter(device_code::Column::ApprovedAt.is_not_null())
+                .exec(txn)
+                .await?;
+            if result.rows_affected != 1 {
+                return Err(OAuthError::InvalidGrant("Invalid device code".to_string()).into());
+            }
+
+            let user = user::Entity::find_by_id(user_id)
+                .one(txn)
+                .await?
+                .ok_or_else(|| OAuthError::InvalidGrant("Invalid device code".to_string()))?;
+            let session = insert_session(txn, &client, &user, session_info, None, device_code.scope, None).await?;
+
+            issue_session_tokens(txn, &client, user.id, session, None, dpop_jkt).await
+        })
+    })
+    .await
+    .map_err(Error::from)
+}
+
+// Exchanged tokens are never refreshed and are only good for the session they belong to
+fn exchanged_token_response(claims: &Claims, access_token: String, scope: Option<String>) -> TokenResponse {
+    TokenResponse {
+        access_token,
+        token_type: dpop::token_type(claims.cnf.is_some()),
+        expires_in: claims.exp as i64 - Utc::now().timestamp(),
+        refresh_token: None,
+        id_token: None,
+        scope,
+        issued_token_type: Some(ACCESS_TOKEN_TYPE.to_string()),
+    }
+}
+
+/// RFC 8693 token exchange. With a `requested_subject` an admin impersonates that user, otherwise a service trades
+/// a user token it received for one aimed at the `audience` client, optionally narrowed down to some of the user's resources.
+pub async fn exchange_token(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    client_id: Option<Uuid>,
+    client_secret: Option<&str>,
+    session_info: Arc<SessionInfo>,
+    dpop: &DpopKey,
+    payload: &TokenRequest,
+) -> Result<TokenResponse, Error> {
+    let subject_token = payload
+        .subject_token
+        .as_deref()
+        .ok_or_else(|| OAuthError::InvalidRequest("subject_token is required".to_string()))?;
+    if payload.subject_token_type.as_deref() != Some(ACCESS_TOKEN_TYPE) {
+        return Err(OAuthError::InvalidRequest("Only access tokens can be exchanged".to_string()).into());
+    }
+    if payload
+        .requested_token_type
+        .as_deref()
+        .is_some_and(|token_type| token_type != ACCESS_TOKEN_TYPE)
+    {
+        return Err(OAuthError::InvalidRequest("Only access tokens can be issued".to_string()).into());
+    }
+
+    // Admins of the master realm hold tokens of their own realm, so the subject token is verified against the realm it names
+    let invalid_subject_token = || OAuthError::InvalidGrant("Invalid subject_token".to_string());
+    let subject_realm_id = signing_key::peek_realm_id(subject_token).map_err(|_| invalid_subject_token())?;
+    let claims = jwt_token::decode(db, subject_token, subject_realm_id, None)
+        .await
+        .map_err(|_| invalid_subject_token())?
+        .claims;
+    if !session_cache::is_active(db, &claims).await? {
+        return Err(invalid_subject_token().into());
+    }
+
+    match payload.requested_subject {
+        Some(user_id) => {
+            let client = authenticate_client(db, realm_id, client_id, client_secret).await?;
+            impersonate(db, &client, session_info, dpop, claims, user_id, payload).await
+        }
+        None => downscope(db, realm_id, client_id, client_secret, dpop, claims, payload).await,
+    }
+}
+
+async fn find_audience(db: &DatabaseConnection, realm_id: Uuid, audience: Uuid) -> Result<client::Model, Error> {
+    Ok(client::Entity::find_active_by_id(db, audience)
+        .await?
+        .filter(|client| client.realm_id == realm_id)
+        .ok_or_else(|| OAuthError::InvalidTarget("Unknown audience".to_string()))?)
+}
+
+async fn impersonate(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    session_info: Arc<SessionInfo>,
+    dpop: &DpopKey,
+    claims: Claims,
+    user_id: Uuid,
+    payload: &TokenRequest,
+) -> Result<TokenResponse, Error> {
+    // A bound admin token only lets the holder of its key impersonate
+    if claims.cnf.as_ref().is_some_and(|cnf| dpop.0.as_ref() != Some(&cnf.jkt)) {
+        return Err(OAuthError::InvalidGrant("Invalid subject_token".to_string()).into());
+    }
+    let dpop_jkt = dpop.binding(client)?;
+    let realm_id = client.realm_id;
+    let admin_realm_id = claims.rli;
+    let admin = JwtUser::from_claim(claims);
+    // An impersonation token must not be usable to impersonate someone else in turn
+    if admin.act.is_some() || !(is_master_realm_admin(&admin) || is_current_realm_admin(&admin, &realm_id.to_string())) {
+        return Err(AuthenticateError::ActionForbidden.into());
+    }
+    let admin_id = admin.sub;
+    let actor = Actor {
+        sub: admin.sub,
+        iss: jwt_token::issuer(admin_realm_id),
+    };
+
+    let target = match payload.audience {
+        Some(audience) => find_audience(db, realm_id, audience).await?,
+        None => client.clone(),
+    };
+    let (user, resource_group, resources) = find_client_access(db, &target, user_id)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidRequest("requested_subject has no access to the client".to_string()))?;
+    let realm = realm::Entity::find_by_id(realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let mappers = get_all_claim_mappers(db, target.id).await?;
+
+    let scope = payload.scope.clone();
+    let (session, claims, access_token) = db
+        .transaction(|txn| {
+            Box::pin(async move {
+                let session = insert_session(txn, &target, &user, session_info, None, scope, dpop_jkt).await?;
+                let expires = session.expires.to_utc().min(Utc::now() + Duration::seconds(IMPERSONATION_LIFETIME));
+                let session = session::ActiveModel {
+                    id: Set(session.id),
+                    expires: Set(expires.into()),
+                    ..Default::default()
+                }
+                .update(txn)
+                .await?;
+
+                let mut claims = Claims::new(user, &target, resource_group, resources, &session, &mappers);
+                claims.act = Some(actor);
+                let access_token = signing_key::sign(&claims, &realm).map_err(|e| Error::SigningKey(e.to_string()))?;
+                Ok::<_, Error>((session, claims, access_token))
+            })
+        })
+        .await?;
+
+    info!(
+        event = "impersonation",
+        admin_id = %admin_id,
+        admin_realm_id = %admin_realm_id,
+        realm_id = %realm_id,
+        user_id = %claims.sub,
+        client_id = %session.client_id,
+        session_id = %session.id,
+        expires = %session.expires,
+        "Admin impersonated a user"
+    );
+
+    Ok(exchanged_token_response(&claims, access_token, session.scope))
+}
+
+async fn downscope(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    client_id: Option<Uuid>,
+    client_secret: Option<&str>,
+    dpop: &DpopKey,
+    claims: Claims,
+    payload: &TokenRequest,
+) -> Result<TokenResponse, Error> {
+    let caller = match authenticate_service(db, realm_id, client_id, client_secret).await? {
+        ServiceCaller::ApiUser(_, client) => client,
+        ServiceCaller::Client(client) => client,
+    };
+    // A service may only pass on tokens it is an audience of
+    if claims.rli != realm_id || !claims.aud.contains(&caller.id.to_string()) {
+        return Err(OAuthError::InvalidGrant("subject_token was not issued to this client".to_string()).into());
+    }
+    // The exchanged token belongs to the service rather than to the user, so it is bound to the key of the service
+    let dpop_jkt = dpop.binding(&caller)?;
+
+    // Services only reach the audiences they were set up to call on behalf of the user
+    let target = match payload.audience {
+        Some(audience) if audience == caller.id => caller,
+        Some(audience) if caller.token_exchange_audiences.contains(&audience) => find_audience(db, realm_id, audience).await?,
+        Some(_) => return Err(OAuthError::InvalidTarget("The client may not exchange tokens for this audience".to_string()).into()),
+        None => caller,
+    };
+    let (user, resource_group, mut resources) = find_client_access(db, &target, claims.sub)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidTarget("The user has no access to the audience".to_string()))?;
+
+    // The exchanged token can never carry a resource the subject token did not carry already
+    let carried = claims.resource.as_ref().map(|resource| &resource.identifiers);
+    resources.retain(|resource| carried.is_some_and(|identifiers| identifiers.get(&resource.name) == Some(&resource.value)));
+    if resources.is_empty() {
+        return Err(OAuthError::InvalidTarget("The subject_token carries no resource of the audience".to_string()).into());
+    }
+
+    // Scopes name the resources to keep, the exchanged token can only ever carry fewer of them
+    if let Some(scope) = &payload.scope {
+        let requested = scope.split_whitespace().collect::<Vec<&str>>();
+        if let Some(unknown) = requested.iter().find(|name| !resources.iter().any(|resource| resource.name == **name)) {
+            return Err(OAuthError::InvalidScope(format!("Unknown scope: {}", unknown)).into());
+        }
+        resources.retain(|resource| requested.contains(&resource.name.as_str()));
+    }
+
+    let session = session::Entity::find_by_id(claims.sid)
+        .filter(session::Column::Expires.gt(Utc::now()))
+        .one(db)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidGrant("Invalid subject_token".to_string()))?;
+    let realm = realm::Entity::find_by_id(realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+
+    let mappers = get_all_claim_mappers(db, target.id).await?;
+    let mut exchanged = Claims::new(user, &target, resource_group, resources, &session, &mappers);
+    exchanged.exp = exchanged.exp.min(claims.exp);
+    exchanged.act = claims.act;
+    exchanged.cnf = Confirmation::from(dpop_jkt);
+    let access_token = signing_key::sign(&exchanged, &realm).map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    Ok(exchanged_token_response(&exchanged, access_token, payload.scope.clone()))
+}
+
+/// Claims of the user behind the access token, limited to the scopes granted to its session.
+pub async fn get_userinfo(db: &DatabaseConnection, realm_id: Uuid, session_id: Uuid) -> Result<StandardClaims, Error> {
+    let session = session::Entity::find_by_id(session_id)
+        .filter(session::Column::Expires.gt(Utc::now()))
+        .one(db)
+        .await?
+        .ok_or(AuthenticateError::InvalidToken)?;
+    // OAuth clients only get to see the user if they asked for an OpenID Connect session
+    if session.scope.as_deref().is_some_and(|scope| !is_openid_scope(scope)) {
+        return Err(AuthenticateError::ActionForbidden.into());
+    }
+
+    let user = user::Entity::find_by_id(session.user_id)
+        .filter(user::Column::RealmId.eq(realm_id))
+        .one(db)
+        .await?
+        .ok_or(AuthenticateError::InvalidToken)?;
+    Ok(StandardClaims::new(&user, session.scope.as_deref()))
+}
+
+pub enum ServiceCaller {
+    ApiUser(ApiUser, client::Model),
+    Client(client::Model),
+}
+
+/// Authenticates an api user or a confidential client, api users are looked up first since both are identified by a uuid.
+pub async fn authenticate_service(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    client_id: Option<Uuid>,
+    client_secret: Option<&str>,
+) -> Result<ServiceCaller, Error> {
+    let id = client_id.ok_or(OAuthError::InvalidClient)?;
+    match api_user::Entity::find_active_by_id(db, id).await? {
+        Some(_) => {
+            let api_user = ApiUser::validate(db, id, client_secret.unwrap_or_default())
+                .await
+                .map_err(|_| OAuthError::InvalidClient)?;
+            if api_user.realm_id != realm_id {
+                return Err(OAuthError::InvalidClient.into());
+            }
+            let client = client::Entity::find_active_by_id(db, api_user.client_id)
+                .await?
+                .ok_or(OAuthError::InvalidClient)?;
+            Ok(ServiceCaller::ApiUser(api_user, client))
+        }
+        None => {
+            let client = authenticate_client(db, realm_id, client_id, client_secret).await?;
+            if client.client_type != ClientType::Confidential {
+                return Err(OAuthError::UnauthorizedClient.into());
+            }
+            Ok(ServiceCaller::Client(client))
+        }
+    }
+}
+
+pub async fn issue_client_credentials_token(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    client_id: Option<Uuid>,
+    client_secret: Option<&str>,
+    dpop: &DpopKey,
+) -> Result<TokenResponse, Error> {
+    let (mut claims, client) = match authenticate_service(db, realm_id, client_id, client_secret).await? {
+        ServiceCaller::ApiUser(api_user, client) => (ServiceClaims::from_api_user(&api_user, &client), client),
+        ServiceCaller::Client(client) => (ServiceClaims::from_client(&client), client),
+    };
+    claims.cnf = Confirmation::from(dpop.binding(&client)?);
+
+    let realm = realm::Entity::find_by_id(realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let access_token = claims.create_token(&realm).map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    Ok(TokenResponse {
+        access_token,
+        token_type: dpop::token_type(claims.cnf.is_some()),
+        expires_in: claims.exp as i64 - claims.iat as i64,
+        refresh_token: None,
+        id_token: None,
+        scope: None,
+        issued_token_type: None,
+    })
+}
+
+/// Resolves any token issued by the realm for the calling client, a token that fails verification, is aimed at another audience
+/// or whose backing records are gone is simply inactive.
+pub async fn introspect_token(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    payload: &IntrospectionRequest,
+) -> Result<IntrospectionResponse, Error> {
+    let token = payload.token.as_str();
+    let response = match payload.token_type_hint.as_deref() {
+        Some("refresh_token") => match introspect_refresh_token(db, client, token).await? {
+            Some(response) => Some(response),
+            None => introspect_access_token(db, client, payload).await?,
+        },
+        _ => match introspect_access_token(db, client, payload).await? {
+            Some(response) => Some(response),
+            None => introspect_refresh_token(db, client, token).await?,
+        },
+    };
+
+    Ok(response.unwrap_or_default())
+}
+
+// Bound tokens are only active when the resource server forwards a proof of possession of their key
+fn proves_possession(cnf: Option<&Confirmation>, payload: &IntrospectionRequest) -> bool {
+    cnf.is_none_or(|cnf| {
+        dpop::proves_possession(
+            cnf,
+            &payload.token,
+            payload.dpop_proof.as_deref(),
+            payload.htm.as_deref(),
+            payload.htu.as_deref(),
+        )
+    })
+}
+
+async fn introspect_access_token(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    payload: &IntrospectionRequest,
+) -> Result<Option<IntrospectionResponse>, Error> {
+    let token = payload.token.as_str();
+    if let Ok(token_data) = signing_key::verify::<Claims, _>(db, token, client.realm_id, Some(client.id)).await {
+        let claims = token_data.claims;
+        if !proves_possession(claims.cnf.as_ref(), payload) {
+            return Ok(None);
+        }
+        let session = session::Entity::find_by_id(claims.sid)
+            .filter(session::Column::Expires.gt(Utc::now()))
+            .one(db)
+            .await?;
+        let Some(session) = session else { return Ok(None) };
+        let user = user::Entity::find_by_id(claims.sub)
+            .filter(user::Column::LockedAt.is_null())
+            .one(db)
+            .await?;
+        let Some(user) = user else { return Ok(None) };
+        if client::Entity::find_active_by_id(db, session.client_id).await?.is_none() {
+            return Ok(None);
+        }
+
+        return Ok(Some(IntrospectionResponse {
+            active: true,
+            client_id: Some(session.client_id),
+            username: Some(user.email),
+            token_type: Some(dpop::token_type(claims.cnf.is_some())),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            sub: Some(claims.sub),
+            iss: Some(claims.iss),
+            aud: Some(claims.aud),
+            sid: Some(session.id),
+            act: claims.act,
+            cnf: claims.cnf,
+            claims: claims.mapped,
+            ..Default::default()
+        }));
+    }
+
+    if let Ok(token_data) = signing_key::verify::<ServiceClaims, _>(db, token, client.realm_id, Some(client.id)).await {
+        let claims = token_data.claims;
+        if !proves_possession(claims.cnf.as_ref(), payload) {
+            return Ok(None);
+        }
+        let is_active = match claims.role {
+            Some(_) => api_user::Entity::find_active_by_id(db, claims.sub)
+                .await?
+                .is_some_and(|api_user| api_user.expires > Utc::now()),
+            None => client::Entity::find_active_by_id(db, claims.cli).await?.is_some(),
+        };
+        if !is_active {
+            return Ok(None);
+        }
+
+        return Ok(Some(IntrospectionResponse {
+            active: true,
+            client_id: Some(claims.cli),
+            token_type: Some(dpop::token_type(claims.cnf.is_some())),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            sub: Some(claims.sub),
+            iss: Some(claims.iss),
+            aud: Some(claims.aud),
+            role: claims.role,
+            access: claims.access,
+            cnf: claims.cnf,
+            ..Default::default()
+        }));
+    }
+
+    Ok(None)
+}
+
+async fn introspect_refresh_token(db: &DatabaseConnection, client: &client::Model, token: &str) -> Result<Option<IntrospectionResponse>, Error> {
+    let Ok(token_data) = signing_key::verify::<RefreshTokenClaims, _>(db, token, client.realm_id, Some(client.id)).await else {
+        return Ok(None);
+    };
+    let claims = token_data.claims;
+
+    let refresh_token = refresh_token::Entity::find_active_by_id(db, claims.sub).await?;
+    let Some(refresh_token) = refresh_token else { return Ok(None) };
+    let user = user::Entity::find_by_id(refresh_token.user_id)
+        .filter(user::Column::LockedAt.is_null())
+        .one(db)
+        .await?;
+    let Some(user) = user else { return Ok(None) };
+    if client::Entity::find_active_by_id(db, claims.cli).await?.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(IntrospectionResponse {
+        active: true,
+        client_id: Some(claims.cli),
+        username: Some(user.email),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+        sub: Some(user.id),
+        iss: Some(claims.iss),
+        ..Default::default()
+    }))
+}
+
+/// Revokes a token issued to the client, unknown or already invalid tokens are ignored as RFC 7009 requires.
+pub async fn revoke_token(db: &DatabaseConnection, client: &client::Model, token: &str, token_type_hint: Option<&str>) -> Result<(), Error> {
+    match token_type_hint {
+        Some("refresh_token") => {
+            if !revoke_refresh_token(db, client, token).await? {
+                revoke_access_token(db, client, token).await?;
+            }
+        }
+        _ => {
+            if !revoke_access_token(db, client, token).await? {
+                revoke_refresh_token(db, client, token).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn revoke_access_token(db: &DatabaseConnection, client: &client::Model, token: &str) -> Result<bool, Error> {
+    if let Ok(token_data) = signing_key::verify::<Claims, _>(db, token, client.realm_id, None).await {
+        if let Some(session) = session::Entity::find_by_id(token_data.claims.sid).one(db).await? {
+            if session.client_id != client.id {
+                return Err(OAuthError::UnauthorizedClient.into());
+            }
+            revoke_sessions(db, Condition::all().add(session::Column::Id.eq(session.id))).await?;
+        }
+        return Ok(true);
+    }
+
+    // Service tokens are not backed by a session, so there is nothing to end
+    if signing_key::verify::<ServiceClaims, _>(db, token, client.realm_id, None).await.is_ok() {
+        return Err(OAuthError::UnsupportedTokenType.into());
+    }
+
+    Ok(false)
+}
+
+async fn revoke_refresh_token(db: &DatabaseConnection, client: &client::Model, token: &str) -> Result<bool, Error> {
+    let Ok(token_data) = signing_key::verify::<RefreshTokenClaims, _>(db, token, client.realm_id, Some(client.id)).await else {
+        return Ok(false);
+    };
+    if token_data.claims.cli != client.id {
+        return Err(OAuthError::UnauthorizedClient.into());
+    }
+
+    // Every token rotated from the same grant goes with it
+    if let Some(refresh_token) = refresh_token::Entity::find_by_id(token_data.claims.sub).one(db).await? {
+        let family_id = refresh_token.family_id;
+        db.transaction(|txn| Box::pin(async move { revoke_refresh_token_family(txn, family_id).await }))
+            .await?;
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of RFC 7636, appendix B
+    const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CODE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+    const REDIRECT_URI: &str = "https://app.example.com/callback";
+
+    fn authorization_code(client_id: Uuid, code_challenge: Option<&str>) -> authorization_code::Model {
+        let now = Utc::now();
+        authorization_code::Model {
+            id: Uuid::now_v7(),
+            code: "code".to_string(),
+            realm_id: Uuid::now_v7(),
+            client_id,
+            user_id: Uuid::now_v7(),
+            session_id: Uuid::now_v7(),
+            redirect_uri: REDIRECT_URI.to_string(),
+            scope: None,
+            nonce: None,
+            code_challenge: code_challenge.map(str::to_string),
+            expires_at: (now + Duration::seconds(60)).into(),
+            used_at: None,
+            created_at: now.into(),
+            updated_at: now.into(),
+        }
+    }
+
+    fn grant_error(result: Result<(), OAuthError>) -> String {
+        match result {
+            Err(OAuthError::InvalidGrant(description)) => description,
+            Err(err) => panic!("expected an invalid grant, got {:?}", err),
+            Ok(()) => panic!("expected an invalid grant"),
+        }
+    }
+
+    #[test]
+    fn verifies_code_challenges() {
+        assert!(verify_code_challenge(CODE_CHALLENGE, CODE_VERIFIER));
+        assert!(!verify_code_challenge(CODE_CHALLENGE, &CODE_VERIFIER.replace('d', "e")));
+        // The plain method is not supported, so the verifier itself is no challenge
+        assert!(!verify_code_challenge(CODE_VERIFIER, CODE_VERIFIER));
+    }
+
+    #[test]
+    fn requires_well_formed_code_verifiers() {
+        assert!(is_valid_code_verifier(&"a".repeat(43)));
+        assert!(is_valid_code_verifier(&"a".repeat(128)));
+        assert!(is_valid_code_verifier("0123456789-._~abcdefghijklmnopqrstuvwxyzABCDEFGHIJ"));
+        assert!(!is_valid_code_verifier(&"a".repeat(42)));
+        assert!(!is_valid_code_verifier(&"a".repeat(129)));
+        assert!(!is_valid_code_verifier(&format!("{}+", "a".repeat(43))));
+    }
+
+    #[test]
+    fn exchanges_codes_with_the_code_verifier() {
+        let client_id = Uuid::now_v7();
+        let code = authorization_code(client_id, Some(CODE_CHALLENGE));
+        assert!(check_authorization_code(&code, client_id, Some(REDIRECT_URI), Some(CODE_VERIFIER)).is_ok());
+        assert_eq!(
+            grant_error(check_authorization_code(&code, client_id, Some(REDIRECT_URI), None)),
+            "Invalid code_verifier"
+        );
+        assert_eq!(
+            grant_error(check_authorization_code(&code, client_id, Some(REDIRECT_URI), Some(&"a".repeat(43)))),
+            "Invalid code_verifier"
+        );
+    }
+
+    #[test]
+    fn exchanges_codes_without_challenge_for_confidential_clients() {
+        let client_id = Uuid::now_v7();
+        let code = authorization_code(client_id, None);
+        assert!(check_authorization_code(&code, client_id, Some(REDIRECT_URI), None).is_ok());
+    }
+
+    #[test]
+    fn refuses_replayed_codes() {
+        let client_id = Uuid::now_v7();
+        let mut code = authorization_code(client_id, Some(CODE_CHALLENGE));
+        code.used_at = Some(Utc::now().into());
+        assert_eq!(
+            grant_error(check_authorization_code(&code, client_id, Some(REDIRECT_URI), Some(CODE_VERIFIER))),
+            "Authorization code has already been used"
+        );
+    }
+
+    #[test]
+    fn refuses_codes_of_other_requests() {
+        let client_id = Uuid::now_v7();
+        let code = authorization_code(client_id, Some(CODE_CHALLENGE));
+        assert_eq!(
+            grant_error(check_authorization_code(&code, Uuid::now_v7(), Some(REDIRECT_URI), Some(CODE_VERIFIER))),
+            "Invalid authorization code"
+        );
+        assert_eq!(
+            grant_error(check_authorization_code(
+                &code,
+                client_id,
+                Some("https://evil.example.com/callback"),
+                Some(CODE_VERIFIER)
+            )),
+            "redirect_uri does not match the authorization request"
+        );
+        assert_eq!(
+            grant_error(check_authorization_code(&code, client_id, None, Some(CODE_VERIFIER))),
+            "redirect_uri does not match the authorization request"
+        );
+
+        let mut code = authorization_code(client_id, Some(CODE_CHALLENGE));
+        code.expires_at = (Utc::now() - Duration::seconds(1)).into();
+        assert_eq!(
+            grant_error(check_authorization_code(&code, client_id, Some(REDIRECT_URI), Some(CODE_VERIFIER))),
+            "Invalid authorization code"
+        );
+    }
+}
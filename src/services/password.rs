@@ -0,0 +1,359 @@
+use chrono::Utc;
+use entity::{client, password_history, realm, refresh_token, resource_group, session, user};
+use sea_orm::{
+    prelude::{Expr, Uuid},
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+    TransactionTrait,
+};
+use url::Url;
+
+use crate::{
+    mappers::auth::{ChangePasswordRequest, ForgotPasswordRequest, PasswordChangeRequiredResponse, ResetPasswordRequest, SetPasswordRequest},
+    packages::{
+        action_token::{self, Action, ActionTokenClaims},
+        errors::{AuthenticateError, Error, FieldError},
+        jwt_token::JwtUser,
+        mailer::{Email, Mailer},
+        password_policy::PasswordPolicy,
+    },
+    services::auth::revoke_sessions,
+    utils::hash::{generate_password_hash, verify_password},
+};
+
+const RESET_TOKEN_LIFETIME: i64 = 60 * 60; // in seconds
+const PASSWORD_CHANGE_TOKEN_LIFETIME: i64 = 5 * 60; // in seconds
+
+/// Mails a reset link to a user of the client, which is valid for an hour and only until their password changes.
+/// Unknown emails are ignored so the response does not tell which emails are registered.
+pub async fn request_password_reset(
+    db: &DatabaseConnection,
+    mailer: &dyn Mailer,
+    client: &client::Model,
+    payload: ForgotPasswordRequest,
+) -> Result<(), Error> {
+    if let Some(redirect_uri) = &payload.redirect_uri {
+        if !client.redirect_uris.contains(redirect_uri) {
+            return Err(Error::cannot_perform_operation("redirect_uri is not registered for this client"));
+        }
+    }
+
+    let user = user::Entity::find()
+        .filter(user::Column::Email.eq(&payload.email))
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .inner_join(resource_group::Entity)
+        .filter(resource_group::Column::ClientId.eq(client.id))
+        .one(db)
+        .await?;
+    let Some(user) = user else {
+        return Ok(());
+    };
+
+    let realm = realm::Entity::find_by_id(client.realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let token = ActionTokenClaims::new(&user, client, Action::ResetPassword, RESET_TOKEN_LIFETIME)
+        .create_token(&realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))?;
+    // Without a page of the client to open, the user pastes the token into it themselves
+    let instructions = match payload.redirect_uri.as_deref().map(Url::parse).transpose() {
+        Ok(Some(mut url)) => {
+            url.query_pairs_mut().append_pair("token", &token);
+            format!("Choose a new password by opening the link below, it expires in 1 hour.\n\n{}", url)
+        }
+        _ => format!("Use the token below to choose a new password, it expires in 1 hour.\n\n{}", token),
+    };
+
+    mailer
+        .send(Email {
+            to: user.email.clone(),
+            subject: format!("Reset your password for {}", client.name),
+            body: format!(
+                "Hi {},\n\nSomeone asked to reset the password of your account. {}\n\nIf it was not you, you can ignore this email.",
+                user.first_name, instructions
+            ),
+        })
+        .await
+}
+
+/// Sets the password a reset token was issued for and signs the user out everywhere.
+pub async fn reset_password(db: &DatabaseConnection, client: &client::Model, payload: ResetPasswordRequest) -> Result<user::Model, Error> {
+    let claims = action_token::decode(db, &payload.token, client, Action::ResetPassword).await?;
+    let user = user::Entity::find_by_id(claims.sub)
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .one(db)
+        .await?
+        .filter(|user| claims.holds_for(user))
+        .ok_or(Error::Authenticate(AuthenticateError::InvalidToken))?;
+    let password_hash = hash_new_password(db, user.realm_id, Some(&user), "password", payload.password).await?;
+
+    Ok(db
+        .transaction(|txn| {
+            Box::pin(async move {
+                let user = set_password_hash(txn, user, password_hash).await?;
+                revoke_sessions(txn, Condition::all().add(session::Column::UserId.eq(user.id))).await?;
+                lock_refresh_tokens(txn, user.id, None).await?;
+                Ok::<_, Error>(user)
+            })
+        })
+        .await?)
+}
+
+/// Changes the password of the signed in user, whose other sessions are ended.
+/// Admins impersonating the user cannot change it, as they do not know the current password.
+pub async fn change_password(db: &DatabaseConnection, jwt_user: &JwtUser, payload: ChangePasswordRequest) -> Result<user::Model, Error> {
+    if jwt_user.act.is_some() {
+        return Err(Error::Authenticate(AuthenticateError::ActionForbidden));
+    }
+
+    let user = user::Entity::find_by_id(jwt_user.sub).one(db).await?.ok_or_else(Error::not_found)?;
+    let verifying = user.clone();
+    if !verify_password(move || verifying.verify_password(&payload.current_password)).await? {
+        return Err(Error::Authenticate(AuthenticateError::WrongCredentials));
+    }
+    let password_hash = hash_new_password(db, user.realm_id, Some(&user), "new_password", payload.new_password).await?;
+
+    let session_id = jwt_user.sid;
+    Ok(db
+        .transaction(|txn| {
+            Box::pin(async move {
+                let user = set_password_hash(txn, user, password_hash).await?;
+                revoke_sessions(
+                    txn,
+                    Condition::all()
+                        .add(session::Column::UserId.eq(user.id))
+                        .add(session::Column::Id.ne(session_id)),
+                )
+                .await?;
+                let current_family_id = match session::Entity::find_by_id(session_id)
+                    .one(txn)
+                    .await?
+                    .and_then(|session| session.refresh_token_id)
+                {
+                    Some(refresh_token_id) => refresh_token::Entity::find_by_id(refresh_token_id)
+                        .one(txn)
+                        .await?
+                        .map(|refresh_token| refresh_token.family_id),
+                    None => None,
+                };
+                lock_refresh_tokens(txn, user.id, current_family_id).await?;
+                Ok::<_, Error>(user)
+            })
+        })
+        .await?)
+}
+
+// Refresh tokens outliving their session would still get new ones, so all of the user's are locked but the family of a session kept
+async fn lock_refresh_tokens<C: ConnectionTrait>(db: &C, user_id: Uuid, kept_family_id: Option<Uuid>) -> Result<(), Error> {
+    let mut condition = Condition::all()
+        .add(refresh_token::Column::UserId.eq(user_id))
+        .add(refresh_token::Column::LockedAt.is_null());
+    if let Some(kept_family_id) = kept_family_id {
+        condition = condition.add(refresh_token::Column::FamilyId.ne(kept_family_id));
+    }
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::LockedAt, Expr::value(Utc::now()))
+        .filter(condition)
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// What users with a temporary password get from logging in instead of a session, a token that only lets them choose their own.
+pub fn issue_password_change_token(
+    realm: &realm::Model,
+    client: &client::Model,
+    user: &user::Model,
+) -> Result<PasswordChangeRequiredResponse, Error> {
+    let token = ActionTokenClaims::new(user, client, Action::ChangeTemporaryPassword, PASSWORD_CHANGE_TOKEN_LIFETIME)
+        .create_token(realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    Ok(PasswordChangeRequiredResponse {
+        password_change_required: true,
+        password_change_token: token,
+        expires_in: PASSWORD_CHANGE_TOKEN_LIFETIME,
+    })
+}
+
+/// Replaces the temporary password a password change token was issued for.
+pub async fn replace_temporary_password(db: &DatabaseConnection, client: &client::Model, payload: SetPasswordRequest) -> Result<user::Model, Error> {
+    let claims = action_token::decode(db, &payload.password_change_token, client, Action::ChangeTemporaryPassword).await?;
+    let user = user::Entity::find_by_id(claims.sub)
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .one(db)
+        .await?
+        .filter(|user| claims.holds_for(user))
+        .ok_or(Error::Authenticate(AuthenticateError::InvalidToken))?;
+    let password_hash = hash_new_password(db, user.realm_id, Some(&user), "password", payload.password).await?;
+
+    Ok(db
+        .transaction(|txn| Box::pin(async move { set_password_hash(txn, user, password_hash).await }))
+        .await?)
+}
+
+/// Whether the user has to replace their password before they get a session.
+pub fn requires_password_change(policy: &PasswordPolicy, user: &user::Model) -> bool {
+    user.is_temp_password || policy.is_expired(user)
+}
+
+/// Users whose password is older than the realm allows have to replace it, the same way as a temporary one.
+pub async fn expire_outdated_password(db: &DatabaseConnection, realm: &realm::Model, user: user::Model) -> Result<user::Model, Error> {
+    if user.is_temp_password || !requires_password_change(&PasswordPolicy::from(realm), &user) {
+        return Ok(user);
+    }
+
+    let user = user::ActiveModel {
+        id: Set(user.id),
+        is_temp_password: Set(true),
+        ..Default::default()
+    };
+    Ok(user.update(db).await?)
+}
+
+/// Checks a new password against the realm's policy, and against the last passwords of an existing user, before hashing it.
+pub async fn hash_new_password<C: ConnectionTrait>(
+    db: &C,
+    realm_id: Uuid,
+    user: Option<&user::Model>,
+    field: &str,
+    password: String,
+) -> Result<String, Error> {
+    let realm = realm::Entity::find_by_id(realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let policy = PasswordPolicy::from(&realm);
+
+    let mut violations = policy.violations(field, &password);
+    if let Some(user) = user {
+        if policy.history > 0 && is_recent_password(db, user, policy.history, &password).await? {
+            violations.push(FieldError::new(
+                field,
+                "reused",
+                format!("Password must differ from the last {} passwords", policy.history),
+            ));
+        }
+    }
+    if !violations.is_empty() {
+        return Err(Error::Validation(violations));
+    }
+
+    generate_password_hash(password).await
+}
+
+async fn is_recent_password<C: ConnectionTrait>(db: &C, user: &user::Model, history: usize, password: &str) -> Result<bool, Error> {
+    let (verifying, given) = (user.clone(), password.to_owned());
+    if verify_password(move || verifying.verify_password(&given)).await? {
+        return Ok(true);
+    }
+
+    let previous = password_history::Entity::find()
+        .filter(password_history::Column::UserId.eq(user.id))
+        .order_by_desc(password_history::Column::CreatedAt)
+        .limit((history - 1) as u64)
+        .all(db)
+        .await?;
+    for previous in previous {
+        let given = password.to_owned();
+        if verify_password(move || previous.verify_password(&given)).await? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// The replaced password goes to the history, which only keeps the previous passwords the realm's policy checks
+async fn set_password_hash<C: ConnectionTrait>(db: &C, user: user::Model, password_hash: String) -> Result<user::Model, Error> {
+    let realm = realm::Entity::find_by_id(user.realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let kept = PasswordPolicy::from(&realm).history.saturating_sub(1);
+
+    if let Some(previous_hash) = user.password_hash.filter(|_| kept > 0) {
+        let previous = password_history::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            user_id: Set(user.id),
+            password_hash: Set(previous_hash),
+            created_at: Set(Utc::now().into()),
+        };
+        previous.insert(db).await?;
+    }
+    let stale = password_history::Entity::find()
+        .select_only()
+        .column(password_history::Column::Id)
+        .filter(password_history::Column::UserId.eq(user.id))
+        .order_by_desc(password_history::Column::CreatedAt)
+        .offset(kept as u64)
+        .into_tuple::<Uuid>()
+        .all(db)
+        .await?;
+    if !stale.is_empty() {
+        password_history::Entity::delete_many()
+            .filter(password_history::Column::Id.is_in(stale))
+            .exec(db)
+            .await?;
+    }
+
+    let user = user::ActiveModel {
+        id: Set(user.id),
+        password_hash: Set(Some(password_hash)),
+        // Only the user themselves ever gets here, so the password is no longer one an admin handed out
+        is_temp_password: Set(false),
+        password_changed_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+    Ok(user.update(db).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    // A user as they were before temporary passwords were looked at, with the flag cleared by the migration
+    fn existing_user() -> user::Model {
+        let created_at = Utc::now() - Duration::days(400);
+        user::Model {
+            id: Uuid::now_v7(),
+            first_name: "Jane".to_string(),
+            last_name: None,
+            email: "jane@example.com".to_string(),
+            email_verified_at: None,
+            phone: None,
+            image: None,
+            two_factor_enabled_at: None,
+            two_factor_failed_attempts: 0,
+            two_factor_locked_until: None,
+            password_hash: None,
+            is_temp_password: false,
+            password_changed_at: created_at.into(),
+            locked_at: None,
+            realm_id: Uuid::now_v7(),
+            created_at: created_at.into(),
+            updated_at: created_at.into(),
+        }
+    }
+
+    #[test]
+    fn lets_existing_users_sign_in() {
+        assert!(!requires_password_change(&PasswordPolicy::default(), &existing_user()));
+    }
+
+    #[test]
+    fn requires_temporary_passwords_to_be_changed() {
+        let user = user::Model {
+            is_temp_password: true,
+            ..existing_user()
+        };
+        assert!(requires_password_change(&PasswordPolicy::default(), &user));
+    }
+
+    #[test]
+    fn requires_outdated_passwords_to_be_changed() {
+        let policy = PasswordPolicy {
+            max_age_days: Some(365),
+            ..Default::default()
+        };
+        assert!(requires_password_change(&policy, &existing_user()));
+
+        let user = user::Model {
+            password_changed_at: Utc::now().into(),
+            ..existing_user()
+        };
+        assert!(!requires_password_change(&policy, &user));
+    }
+}
@@ -1,9 +1,13 @@
 use chrono::Utc;
-use sea_orm::{prelude::Uuid, ActiveModelTrait, DatabaseConnection, DeleteResult, EntityTrait, Set};
+use sea_orm::{prelude::Uuid, ActiveModelTrait, DatabaseConnection, DeleteResult, EntityTrait, Set, TransactionTrait};
 
 use crate::{
     mappers::realm::UpdateRealmRequest,
-    packages::errors::{AuthenticateError, Error},
+    packages::{
+        errors::{AuthenticateError, Error},
+        password_policy::PasswordPolicy,
+        signing_key::{ensure_active_key, reload},
+    },
     utils::default_resource_checker::is_default_realm,
 };
 use entity::realm;
@@ -17,12 +21,23 @@ pub async fn get_realm_by_id(db: &DatabaseConnection, id: Uuid) -> Result<Option
 }
 
 pub async fn insert_realm(db: &DatabaseConnection, name: String) -> Result<realm::Model, Error> {
-    let realm = realm::ActiveModel {
-        id: Set(Uuid::now_v7()),
-        name: Set(name),
-        ..Default::default()
-    };
-    Ok(realm.insert(db).await?)
+    let realm = db
+        .transaction(|txn| {
+            Box::pin(async move {
+                let realm = realm::ActiveModel {
+                    id: Set(Uuid::now_v7()),
+                    name: Set(name),
+                    ..Default::default()
+                };
+                let realm = realm.insert(txn).await?;
+                ensure_active_key(txn, realm.id, &realm.signing_algorithm).await?;
+                Ok::<realm::Model, Error>(realm)
+            })
+        })
+        .await?;
+
+    reload(db).await?;
+    Ok(realm)
 }
 
 pub async fn update_realm_by_id(db: &DatabaseConnection, id: Uuid, payload: UpdateRealmRequest) -> Result<realm::Model, Error> {
@@ -30,6 +45,8 @@ pub async fn update_realm_by_id(db: &DatabaseConnection, id: Uuid, payload: Upda
         return Err(Error::cannot_perform_operation("Cannot lock the default realm"));
     }
 
+    let password_policy = payload.password_policy.map(PasswordPolicy::validated).transpose()?;
+
     let realm = get_realm_by_id(db, id).await?;
     match realm {
         Some(realm) => {
@@ -51,14 +68,22 @@ pub async fn update_realm_by_id(db: &DatabaseConnection, id: Uuid, payload: Upda
                     Some(refresh_token_lifetime) => refresh_token_lifetime,
                     None => realm.refresh_token_lifetime,
                 }),
-                refresh_token_reuse_limit: Set(match payload.refresh_token_reuse_limit {
-                    Some(refresh_token_reuse_limit) => refresh_token_reuse_limit,
-                    None => realm.refresh_token_reuse_limit,
+                signing_algorithm: Set(match payload.signing_algorithm {
+                    Some(signing_algorithm) => signing_algorithm,
+                    None => realm.signing_algorithm.clone(),
+                }),
+                password_policy: Set(match password_policy {
+                    Some(password_policy) => serde_json::to_value(password_policy)?,
+                    None => realm.password_policy.clone(),
                 }),
                 locked_at: Set(locked_at),
                 ..Default::default()
             };
             let updated_realm = updated_realm.update(db).await?;
+            if updated_realm.signing_algorithm != realm.signing_algorithm {
+                ensure_active_key(db, updated_realm.id, &updated_realm.signing_algorithm).await?;
+                reload(db).await?;
+            }
             Ok(updated_realm)
         }
         None => Err(Error::Authenticate(AuthenticateError::NoResource)),
@@ -66,5 +91,7 @@ pub async fn update_realm_by_id(db: &DatabaseConnection, id: Uuid, payload: Upda
 }
 
 pub async fn delete_realm_by_id(db: &DatabaseConnection, id: Uuid) -> Result<DeleteResult, Error> {
-    Ok(realm::Entity::delete_by_id(id).exec(db).await?)
+    let result = realm::Entity::delete_by_id(id).exec(db).await?;
+    reload(db).await?;
+    Ok(result)
 }
@@ -0,0 +1,118 @@
+use chrono::Utc;
+use entity::{client, resource_group, user};
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set, TransactionTrait};
+
+use crate::{
+    mappers::auth::{CreateUserRequest, ResourceSubset, SignupRequest},
+    packages::errors::{AuthenticateError, Error},
+    services::user::insert_user,
+};
+
+fn is_allowed_email(client: &client::Model, email: &str) -> bool {
+    if client.registration_email_domains.is_empty() {
+        return true;
+    }
+
+    email
+        .rsplit_once('@')
+        .is_some_and(|(_, domain)| client.registration_email_domains.contains(&domain.to_lowercase()))
+}
+
+/// Signs a user up on the client with the client's resource group template, the same way an admin would create them.
+/// Clients requiring approval keep the resource group locked until an admin approves the registration.
+pub async fn register_user(db: &DatabaseConnection, client: &client::Model, payload: SignupRequest) -> Result<user::Model, Error> {
+    if !client.allow_self_registration {
+        return Err(Error::Authenticate(AuthenticateError::ActionForbidden));
+    }
+    if !is_allowed_email(client, &payload.email) {
+        return Err(Error::cannot_perform_operation("Email domain is not allowed to register"));
+    }
+    if payload.password.is_empty() {
+        return Err(Error::cannot_perform_operation("Password cannot be empty"));
+    }
+    let template = client
+        .registration_template
+        .clone()
+        .map(serde_json::from_value::<ResourceSubset>)
+        .transpose()?
+        .ok_or_else(|| Error::cannot_perform_operation("Self registration needs a registration template"))?;
+
+    let existing_user = user::Entity::find().filter(user::Column::Email.eq(&payload.email)).one(db).await?;
+    if existing_user.is_some() {
+        return Err(Error::cannot_perform_operation("Email is already registered"));
+    }
+
+    let client = client.clone();
+    Ok(db
+        .transaction(|txn| {
+            Box::pin(async move {
+                let payload = CreateUserRequest {
+                    email: payload.email,
+                    password: payload.password,
+                    first_name: payload.first_name,
+                    last_name: payload.last_name,
+                    phone: payload.phone,
+                    image: payload.image,
+                    resource: template,
+                    is_temp_password: Some(false),
+                };
+                let user = insert_user(txn, client.realm_id, client.id, payload).await?;
+
+                if client.require_registration_approval {
+                    resource_group::Entity::update_many()
+                        .col_expr(resource_group::Column::AwaitingApproval, true.into())
+                        .col_expr(resource_group::Column::LockedAt, Utc::now().into())
+                        .filter(resource_group::Column::ClientId.eq(client.id))
+                        .filter(resource_group::Column::UserId.eq(user.id))
+                        .exec(txn)
+                        .await?;
+                }
+                Ok::<_, Error>(user)
+            })
+        })
+        .await?)
+}
+
+async fn find_pending_resource_group(
+    db: &DatabaseConnection,
+    realm_id: Uuid,
+    client_id: Uuid,
+    user_id: Uuid,
+) -> Result<resource_group::Model, Error> {
+    resource_group::Entity::find()
+        .filter(resource_group::Column::RealmId.eq(realm_id))
+        .filter(resource_group::Column::ClientId.eq(client_id))
+        .filter(resource_group::Column::UserId.eq(user_id))
+        .filter(resource_group::Column::AwaitingApproval.eq(true))
+        .one(db)
+        .await?
+        .ok_or_else(Error::not_found)
+}
+
+pub async fn get_pending_registrations(db: &DatabaseConnection, realm_id: Uuid, client_id: Uuid) -> Result<Vec<user::Model>, Error> {
+    Ok(user::Entity::find()
+        .inner_join(resource_group::Entity)
+        .filter(resource_group::Column::RealmId.eq(realm_id))
+        .filter(resource_group::Column::ClientId.eq(client_id))
+        .filter(resource_group::Column::AwaitingApproval.eq(true))
+        .all(db)
+        .await?)
+}
+
+pub async fn approve_registration(db: &DatabaseConnection, realm_id: Uuid, client_id: Uuid, user_id: Uuid) -> Result<resource_group::Model, Error> {
+    let resource_group = find_pending_resource_group(db, realm_id, client_id, user_id).await?;
+    let resource_group = resource_group::ActiveModel {
+        id: Set(resource_group.id),
+        awaiting_approval: Set(false),
+        locked_at: Set(None),
+        ..Default::default()
+    };
+    Ok(resource_group.update(db).await?)
+}
+
+/// Rejected users are deleted, they only ever got access to the client they registered on.
+pub async fn reject_registration(db: &DatabaseConnection, realm_id: Uuid, client_id: Uuid, user_id: Uuid) -> Result<bool, Error> {
+    let resource_group = find_pending_resource_group(db, realm_id, client_id, user_id).await?;
+    let result = user::Entity::delete_by_id(resource_group.user_id).exec(db).await?;
+    Ok(result.rows_affected == 1)
+}
@@ -0,0 +1,144 @@
+use chrono::Utc;
+use entity::{
+    client, realm,
+    sea_orm_active_enums::{SigningAlgorithm, SigningKeyStatus},
+    signing_key,
+};
+use sea_orm::{
+    prelude::{Expr, Uuid},
+    ActiveEnum, ActiveModelTrait, ColumnTrait, DatabaseConnection, DeleteResult, EntityTrait, QueryFilter, QueryOrder, Set, TransactionTrait,
+};
+
+use crate::packages::{
+    errors::Error,
+    signing_key::{generate_private_key, reload},
+};
+
+pub async fn get_all_signing_keys(db: &DatabaseConnection, realm_id: Uuid) -> Result<Vec<signing_key::Model>, Error> {
+    Ok(signing_key::Entity::find()
+        .filter(signing_key::Column::RealmId.eq(realm_id))
+        .order_by_desc(signing_key::Column::Id)
+        .all(db)
+        .await?)
+}
+
+pub async fn insert_next_signing_key(db: &DatabaseConnection, realm_id: Uuid, algorithm: SigningAlgorithm) -> Result<signing_key::Model, Error> {
+    let next_key = signing_key::Entity::find()
+        .filter(signing_key::Column::RealmId.eq(realm_id))
+        .filter(signing_key::Column::Algorithm.eq(algorithm.clone()))
+        .filter(signing_key::Column::Status.eq(SigningKeyStatus::Next))
+        .one(db)
+        .await?;
+    if next_key.is_some() {
+        return Err(Error::cannot_perform_operation("A next key already exists for this algorithm"));
+    }
+
+    let key_model = signing_key::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        realm_id: Set(realm_id),
+        private_key: Set(generate_private_key(&algorithm)?),
+        algorithm: Set(algorithm),
+        status: Set(SigningKeyStatus::Next),
+        ..Default::default()
+    };
+    let key = key_model.insert(db).await?;
+
+    reload(db).await?;
+    Ok(key)
+}
+
+/// Promotes the `next` key (or a fresh one) to `active`, retires the current active key and stages a new `next` key.
+/// Retired keys keep verifying tokens until the longest lifetime of a token of the realm or any of its clients has passed.
+pub async fn rotate_signing_keys(db: &DatabaseConnection, realm_id: Uuid, algorithm: SigningAlgorithm) -> Result<Vec<signing_key::Model>, Error> {
+    let realm = realm::Entity::find_by_id(realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let clients = client::Entity::find().filter(client::Column::RealmId.eq(realm_id)).all(db).await?;
+    let grace_period = clients
+        .iter()
+        .flat_map(|client| [client.session_lifetime, client.refresh_token_lifetime, client.access_token_lifetime])
+        .fold(realm.session_lifetime.max(realm.refresh_token_lifetime), i32::max);
+
+    let new_active_private_key = generate_private_key(&algorithm)?;
+    let new_next_private_key = generate_private_key(&algorithm)?;
+
+    let rotation_algorithm = algorithm.clone();
+    db.transaction(|txn| {
+        Box::pin(async move {
+            let next_key = signing_key::Entity::find()
+                .filter(signing_key::Column::RealmId.eq(realm_id))
+                .filter(signing_key::Column::Algorithm.eq(rotation_algorithm.clone()))
+                .filter(signing_key::Column::Status.eq(SigningKeyStatus::Next))
+                .one(txn)
+                .await?;
+
+            signing_key::Entity::update_many()
+                .col_expr(signing_key::Column::Status, SigningKeyStatus::Retired.as_enum())
+                .col_expr(
+                    signing_key::Column::ExpiresAt,
+                    Expr::value(Utc::now() + chrono::Duration::seconds(grace_period as i64)),
+                )
+                .col_expr(signing_key::Column::UpdatedAt, Expr::value(Utc::now()))
+                .filter(signing_key::Column::RealmId.eq(realm_id))
+                .filter(signing_key::Column::Algorithm.eq(rotation_algorithm.clone()))
+                .filter(signing_key::Column::Status.eq(SigningKeyStatus::Active))
+                .exec(txn)
+                .await?;
+
+            match next_key {
+                Some(next_key) => {
+                    let key_model = signing_key::ActiveModel {
+                        id: Set(next_key.id),
+                        status: Set(SigningKeyStatus::Active),
+                        updated_at: Set(Utc::now().into()),
+                        ..Default::default()
+                    };
+                    key_model.update(txn).await?;
+                }
+                None => {
+                    let key_model = signing_key::ActiveModel {
+                        id: Set(Uuid::now_v7()),
+                        realm_id: Set(realm_id),
+                        algorithm: Set(rotation_algorithm.clone()),
+                        private_key: Set(new_active_private_key),
+                        status: Set(SigningKeyStatus::Active),
+                        ..Default::default()
+                    };
+                    key_model.insert(txn).await?;
+                }
+            }
+
+            let key_model = signing_key::ActiveModel {
+                id: Set(Uuid::now_v7()),
+                realm_id: Set(realm_id),
+                algorithm: Set(rotation_algorithm),
+                private_key: Set(new_next_private_key),
+                status: Set(SigningKeyStatus::Next),
+                ..Default::default()
+            };
+            key_model.insert(txn).await
+        })
+    })
+    .await?;
+
+    reload(db).await?;
+    Ok(signing_key::Entity::find()
+        .filter(signing_key::Column::RealmId.eq(realm_id))
+        .filter(signing_key::Column::Algorithm.eq(algorithm))
+        .order_by_desc(signing_key::Column::Id)
+        .all(db)
+        .await?)
+}
+
+pub async fn delete_signing_key_by_id(db: &DatabaseConnection, realm_id: Uuid, id: Uuid) -> Result<DeleteResult, Error> {
+    let key = signing_key::Entity::find_by_id(id)
+        .filter(signing_key::Column::RealmId.eq(realm_id))
+        .one(db)
+        .await?
+        .ok_or_else(Error::not_found)?;
+    if key.status != SigningKeyStatus::Retired {
+        return Err(Error::cannot_perform_operation("Only retired keys can be deleted"));
+    }
+
+    let result = signing_key::Entity::delete_by_id(id).exec(db).await?;
+    reload(db).await?;
+    Ok(result)
+}
@@ -0,0 +1,370 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use std::future::Future;
+
+use chrono::{Duration, Utc};
+use entity::{client, realm, recovery_code, totp_secret, user};
+use rand::{seq::SliceRandom, thread_rng};
+use sea_orm::{
+    prelude::{DateTimeWithTimeZone, Expr, Uuid},
+    sea_query::OnConflict,
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    mappers::auth::{TwoFactorEnrollmentResponse, TwoFactorRequiredResponse, TwoFactorSetupResponse, VerifyTwoFactorRequest},
+    packages::{
+        action_token::{self, Action, ActionTokenClaims},
+        errors::{AuthenticateError, Error},
+        jwt_token::JwtUser,
+        mailer::{Email, Mailer},
+        totp,
+    },
+    services::webauthn::{finish_authentication, start_second_factor_authentication},
+};
+
+const TWO_FACTOR_TOKEN_LIFETIME: i64 = 5 * 60; // in seconds
+const ENROLLMENT_TOKEN_LIFETIME: i64 = 60 * 60; // in seconds
+const RECOVERY_CODE_COUNT: usize = 10;
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+const LOCKOUT_DURATION: i64 = 15 * 60; // in seconds
+
+// Letters and digits that cannot be mistaken for one another when typed off paper
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Clients with two-factor authentication enabled require it from all their users, other clients only from users who turned it on.
+pub fn requires_two_factor(client: &client::Model, user: &user::Model) -> bool {
+    client.two_factor_enabled_at.is_some() || user.two_factor_enabled_at.is_some()
+}
+
+/// What users get from logging in instead of a session when they have to prove a second factor.
+/// Users with passkeys can prove it with one of them. Users with neither passkeys nor two-factor authentication are mailed
+/// a token to set it up with, as the password alone must not be enough to choose the second factor.
+pub async fn issue_two_factor_challenge(
+    db: &DatabaseConnection,
+    mailer: &dyn Mailer,
+    realm: &realm::Model,
+    client: &client::Model,
+    user: &user::Model,
+) -> Result<TwoFactorRequiredResponse, Error> {
+    if is_locked_out(user) {
+        return Err(Error::Authenticate(AuthenticateError::TwoFactorLocked));
+    }
+    let webauthn = start_second_factor_authentication(db, client, user).await?;
+    let enrollment_email_sent = user.two_factor_enabled_at.is_none() && webauthn.is_none();
+    if enrollment_email_sent {
+        send_enrollment_email(mailer, realm, client, user).await?;
+    }
+    let token = ActionTokenClaims::new(user, client, Action::TwoFactor, TWO_FACTOR_TOKEN_LIFETIME)
+        .create_token(realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    Ok(TwoFactorRequiredResponse {
+        two_factor_required: true,
+        two_factor_token: token,
+        expires_in: TWO_FACTOR_TOKEN_LIFETIME,
+        enrollment_email_sent,
+        webauthn,
+    })
+}
+
+/// Exchanges a mailed enrollment token for the secret to set up two-factor authentication with,
+/// along with a two-factor token to confirm its codes with, as the one of the login may have expired by now.
+pub async fn enroll_two_factor(db: &DatabaseConnection, client: &client::Model, token: &str) -> Result<TwoFactorEnrollmentResponse, Error> {
+    let claims = action_token::decode(db, token, client, Action::EnrollTwoFactor).await?;
+    let user = user::Entity::find_by_id(claims.sub)
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .one(db)
+        .await?
+        .filter(|user| claims.holds_for(user))
+        .ok_or(Error::Authenticate(AuthenticateError::InvalidToken))?;
+    if is_locked_out(&user) {
+        return Err(Error::Authenticate(AuthenticateError::TwoFactorLocked));
+    }
+
+    let realm = realm::Entity::find_by_id(client.realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let setup = begin_setup(db, &realm, &user).await?;
+    let two_factor_token = ActionTokenClaims::new(&user, client, Action::TwoFactor, TWO_FACTOR_TOKEN_LIFETIME)
+        .create_token(&realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    Ok(TwoFactorEnrollmentResponse {
+        setup,
+        two_factor_token,
+        expires_in: TWO_FACTOR_TOKEN_LIFETIME,
+    })
+}
+
+/// Checks the second factor of a user a two-factor token was issued for, completing the setup for users who had none.
+/// Returns the recovery codes when the setup was completed.
+pub async fn complete_two_factor_challenge(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    payload: &VerifyTwoFactorRequest,
+) -> Result<(user::Model, Option<Vec<String>>), Error> {
+    let claims = action_token::decode(db, &payload.two_factor_token, client, Action::TwoFactor).await?;
+    let user = user::Entity::find_by_id(claims.sub)
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .one(db)
+        .await?
+        .filter(|user| claims.holds_for(user))
+        .ok_or(Error::Authenticate(AuthenticateError::InvalidToken))?;
+
+    if let Some(assertion) = &payload.webauthn {
+        if finish_authentication(db, client, assertion).await?.id != user.id {
+            return Err(Error::Authenticate(AuthenticateError::InvalidPasskey));
+        }
+        return Ok((user, None));
+    }
+    if user.two_factor_enabled_at.is_some() {
+        verify_second_factor(db, &user, payload.code.as_deref(), payload.recovery_code.as_deref()).await?;
+        return Ok((user, None));
+    }
+    let code = payload
+        .code
+        .as_deref()
+        .ok_or(Error::Authenticate(AuthenticateError::InvalidTwoFactorCode))?;
+    let (user, recovery_codes) = confirm_setup(db, user, code).await?;
+    Ok((user, Some(recovery_codes)))
+}
+
+/// Starts setting up two-factor authentication for the signed in user, the codes of the secret have to be confirmed to turn it on.
+pub async fn setup_two_factor(db: &DatabaseConnection, jwt_user: &JwtUser) -> Result<TwoFactorSetupResponse, Error> {
+    let user = find_own_user(db, jwt_user).await?;
+    let realm = realm::Entity::find_by_id(user.realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    begin_setup(db, &realm, &user).await
+}
+
+/// Turns two-factor authentication on for the signed in user and returns their recovery codes.
+pub async fn enable_two_factor(db: &DatabaseConnection, jwt_user: &JwtUser, code: &str) -> Result<Vec<String>, Error> {
+    let user = find_own_user(db, jwt_user).await?;
+    let (_, recovery_codes) = confirm_setup(db, user, code).await?;
+    Ok(recovery_codes)
+}
+
+/// Checks a code of the user's authenticator app, or else one of their recovery codes. Either only works once.
+/// Too many invalid codes in a row lock the user out of two-factor authentication for a while.
+pub async fn verify_second_factor(db: &DatabaseConnection, user: &user::Model, code: Option<&str>, recovery_code: Option<&str>) -> Result<(), Error> {
+    limit_attempts(db, user, async {
+        let verified = match (code, recovery_code) {
+            (Some(code), _) => use_code(db, user, code).await?,
+            (None, Some(recovery_code)) => use_recovery_code(db, user, recovery_code).await?,
+            (None, None) => false,
+        };
+        if !verified {
+            return Err(Error::Authenticate(AuthenticateError::InvalidTwoFactorCode));
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn is_locked_out(user: &user::Model) -> bool {
+    user.two_factor_locked_until.is_some_and(|locked_until| locked_until > Utc::now())
+}
+
+// The attempt is counted before the code is checked, and outside of any transaction the check runs in,
+// so neither concurrent guesses nor rolled back ones get past the limit
+async fn limit_attempts<T>(db: &DatabaseConnection, user: &user::Model, check: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    let now = Utc::now();
+    let attempts = user::Entity::update_many()
+        .col_expr(
+            user::Column::TwoFactorFailedAttempts,
+            Expr::col(user::Column::TwoFactorFailedAttempts).add(1),
+        )
+        .filter(user::Column::Id.eq(user.id))
+        .filter(user::Column::TwoFactorFailedAttempts.lt(MAX_FAILED_ATTEMPTS))
+        .filter(
+            Condition::any()
+                .add(user::Column::TwoFactorLockedUntil.is_null())
+                .add(user::Column::TwoFactorLockedUntil.lte(now)),
+        )
+        .exec_with_returning(db)
+        .await?
+        .first()
+        .map(|user| user.two_factor_failed_attempts)
+        .ok_or(Error::Authenticate(AuthenticateError::TwoFactorLocked))?;
+
+    let result = check.await;
+    match &result {
+        Ok(_) => {
+            user::Entity::update_many()
+                .col_expr(user::Column::TwoFactorFailedAttempts, Expr::value(0))
+                .col_expr(user::Column::TwoFactorLockedUntil, Expr::value(Option::<DateTimeWithTimeZone>::None))
+                .filter(user::Column::Id.eq(user.id))
+                .exec(db)
+                .await?;
+        }
+        Err(Error::Authenticate(AuthenticateError::InvalidTwoFactorCode)) if attempts >= MAX_FAILED_ATTEMPTS => {
+            let locked_until: DateTimeWithTimeZone = (now + Duration::seconds(LOCKOUT_DURATION)).into();
+            user::Entity::update_many()
+                .col_expr(user::Column::TwoFactorFailedAttempts, Expr::value(0))
+                .col_expr(user::Column::TwoFactorLockedUntil, Expr::value(locked_until))
+                .filter(user::Column::Id.eq(user.id))
+                .exec(db)
+                .await?;
+        }
+        _ => {}
+    }
+    result
+}
+
+// Admins impersonating the user cannot change how they sign in
+async fn find_own_user(db: &DatabaseConnection, jwt_user: &JwtUser) -> Result<user::Model, Error> {
+    if jwt_user.act.is_some() {
+        return Err(Error::Authenticate(AuthenticateError::ActionForbidden));
+    }
+    user::Entity::find_by_id(jwt_user.sub).one(db).await?.ok_or_else(Error::not_found)
+}
+
+async fn send_enrollment_email(mailer: &dyn Mailer, realm: &realm::Model, client: &client::Model, user: &user::Model) -> Result<(), Error> {
+    let token = ActionTokenClaims::new(user, client, Action::EnrollTwoFactor, ENROLLMENT_TOKEN_LIFETIME)
+        .create_token(realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))?;
+
+    mailer
+        .send(Email {
+            to: user.email.clone(),
+            subject: format!("Set up two-factor authentication for {}", client.name),
+            body: format!(
+                "Hi {},\n\n{} requires two-factor authentication. Use the token below to set it up, it expires in 1 hour.\n\n{}\n\nIf you did not try to sign in, change your password as someone else knows it.",
+                user.first_name, client.name, token
+            ),
+        })
+        .await
+}
+
+// Keeps a secret that was never confirmed, so asking for it again cannot replace the one the user already added to their app
+async fn begin_setup<C: ConnectionTrait>(db: &C, realm: &realm::Model, user: &user::Model) -> Result<TwoFactorSetupResponse, Error> {
+    if user.two_factor_enabled_at.is_some() {
+        return Err(Error::cannot_perform_operation("Two-factor authentication is already enabled"));
+    }
+
+    let totp_secret = totp_secret::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        user_id: Set(user.id),
+        secret: Set(totp::generate_secret()),
+        last_used_step: Set(None),
+        created_at: Set(Utc::now().into()),
+    };
+    totp_secret::Entity::insert(totp_secret)
+        .on_conflict(OnConflict::column(totp_secret::Column::UserId).do_nothing().to_owned())
+        .exec_without_returning(db)
+        .await?;
+    let secret = totp_secret::Entity::find()
+        .filter(totp_secret::Column::UserId.eq(user.id))
+        .one(db)
+        .await?
+        .ok_or_else(Error::not_found)?
+        .secret;
+
+    Ok(TwoFactorSetupResponse {
+        otpauth_uri: totp::provisioning_uri(&secret, &realm.name, &user.email),
+        secret,
+    })
+}
+
+async fn confirm_setup(db: &DatabaseConnection, user: user::Model, code: &str) -> Result<(user::Model, Vec<String>), Error> {
+    if user.two_factor_enabled_at.is_some() {
+        return Err(Error::cannot_perform_operation("Two-factor authentication is already enabled"));
+    }
+    totp_secret::Entity::find()
+        .filter(totp_secret::Column::UserId.eq(user.id))
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::cannot_perform_operation("Two-factor authentication has not been set up"))?;
+
+    let recovery_codes = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect::<Vec<String>>();
+    let code_hashes = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect::<Vec<String>>();
+    let code = code.to_owned();
+    let locked_user = user.clone();
+    let confirm = db.transaction(|txn| {
+        Box::pin(async move {
+            if !use_code(txn, &user, &code).await? {
+                return Err(Error::Authenticate(AuthenticateError::InvalidTwoFactorCode));
+            }
+
+            recovery_code::Entity::delete_many()
+                .filter(recovery_code::Column::UserId.eq(user.id))
+                .exec(txn)
+                .await?;
+            let now = Utc::now();
+            recovery_code::Entity::insert_many(code_hashes.into_iter().map(|code_hash| recovery_code::ActiveModel {
+                id: Set(Uuid::now_v7()),
+                user_id: Set(user.id),
+                code_hash: Set(code_hash),
+                used_at: Set(None),
+                created_at: Set(now.into()),
+            }))
+            .exec(txn)
+            .await?;
+
+            let user = user::ActiveModel {
+                id: Set(user.id),
+                two_factor_enabled_at: Set(Some(now.into())),
+                ..Default::default()
+            };
+            Ok::<_, Error>(user.update(txn).await?)
+        })
+    });
+    let user = limit_attempts(db, &locked_user, async { Ok(confirm.await?) }).await?;
+
+    Ok((user, recovery_codes))
+}
+
+// A code is used up once accepted, along with the codes of earlier periods, so one seen by someone else cannot be replayed
+async fn use_code<C: ConnectionTrait>(db: &C, user: &user::Model, code: &str) -> Result<bool, Error> {
+    let Some(totp_secret) = totp_secret::Entity::find()
+        .filter(totp_secret::Column::UserId.eq(user.id))
+        .one(db)
+        .await?
+    else {
+        return Ok(false);
+    };
+    let last_used_step = totp_secret.last_used_step.map(|step| step as u64);
+    let Some(step) = totp::verify(&totp_secret.secret, code, Utc::now().timestamp() as u64, last_used_step) else {
+        return Ok(false);
+    };
+
+    let step = step as i64;
+    let result = totp_secret::Entity::update_many()
+        .col_expr(totp_secret::Column::LastUsedStep, Expr::value(step))
+        .filter(totp_secret::Column::Id.eq(totp_secret.id))
+        .filter(
+            Condition::any()
+                .add(totp_secret::Column::LastUsedStep.is_null())
+                .add(totp_secret::Column::LastUsedStep.lt(step)),
+        )
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected == 1)
+}
+
+async fn use_recovery_code<C: ConnectionTrait>(db: &C, user: &user::Model, recovery_code: &str) -> Result<bool, Error> {
+    let result = recovery_code::Entity::update_many()
+        .col_expr(recovery_code::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(recovery_code::Column::UserId.eq(user.id))
+        .filter(recovery_code::Column::CodeHash.eq(hash_recovery_code(recovery_code)))
+        .filter(recovery_code::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = thread_rng();
+    let characters = (0..10)
+        .map(|_| *RECOVERY_CODE_ALPHABET.choose(&mut rng).unwrap() as char)
+        .collect::<String>();
+    format!("{}-{}", &characters[..5], &characters[5..])
+}
+
+// Recovery codes are random enough for a plain hash, the way users type them does not matter
+fn hash_recovery_code(recovery_code: &str) -> String {
+    let normalized = recovery_code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    URL_SAFE_NO_PAD.encode(Sha256::digest(normalized))
+}
@@ -1,10 +1,10 @@
-use crate::{mappers::auth::CreateUserRequest, packages::errors::Error, utils::hash::generate_password_hash};
+use crate::{mappers::auth::CreateUserRequest, packages::errors::Error, services::password::hash_new_password};
 use entity::{resource, resource_group, user};
 use futures::future::join_all;
-use sea_orm::{prelude::Uuid, ActiveModelTrait, DatabaseConnection, Set};
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ConnectionTrait, Set};
 
-pub async fn insert_user(db: &DatabaseConnection, realm_id: Uuid, client_id: Uuid, payload: CreateUserRequest) -> Result<user::Model, Error> {
-    let password_hash = generate_password_hash(payload.password).await?;
+pub async fn insert_user<C: ConnectionTrait>(db: &C, realm_id: Uuid, client_id: Uuid, payload: CreateUserRequest) -> Result<user::Model, Error> {
+    let password_hash = hash_new_password(db, realm_id, None, "password", payload.password).await?;
     let user_model = user::ActiveModel {
         id: Set(Uuid::now_v7()),
         realm_id: Set(realm_id),
@@ -14,6 +14,7 @@ pub async fn insert_user(db: &DatabaseConnection, realm_id: Uuid, client_id: Uui
         last_name: Set(payload.last_name),
         phone: Set(payload.phone),
         image: Set(payload.image),
+        is_temp_password: Set(payload.is_temp_password.unwrap_or(false)),
         ..Default::default()
     };
 
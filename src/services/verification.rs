@@ -0,0 +1,68 @@
+use chrono::Utc;
+use entity::{client, realm, resource_group, user};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+
+use crate::packages::{
+    action_token::{self, Action, ActionTokenClaims},
+    errors::{AuthenticateError, Error},
+    jwt_token::issuer,
+    mailer::{Email, Mailer},
+};
+
+const VERIFICATION_TOKEN_LIFETIME: i64 = 24 * 60 * 60; // in seconds
+
+/// Mails the user a link to verify their email, which is valid for a day and only until the email is verified or changed.
+pub async fn send_verification_email(db: &DatabaseConnection, mailer: &dyn Mailer, client: &client::Model, user: &user::Model) -> Result<(), Error> {
+    let realm = realm::Entity::find_by_id(client.realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    let token = ActionTokenClaims::new(user, client, Action::VerifyEmail, VERIFICATION_TOKEN_LIFETIME)
+        .create_token(&realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))?;
+    let link = format!("{}/clients/{}/auth/verify-email?token={}", issuer(realm.id), client.id, token);
+
+    mailer
+        .send(Email {
+            to: user.email.clone(),
+            subject: format!("Verify your email for {}", client.name),
+            body: format!(
+                "Hi {},\n\nPlease verify your email by opening the link below, it expires in 24 hours.\n\n{}\n\nIf you did not sign up for {}, you can ignore this email.",
+                user.first_name, link, client.name
+            ),
+        })
+        .await
+}
+
+/// Consumes a verification token, which stops working once the email it was issued for is verified.
+pub async fn verify_email(db: &DatabaseConnection, client: &client::Model, token: &str) -> Result<user::Model, Error> {
+    let claims = action_token::decode(db, token, client, Action::VerifyEmail).await?;
+    let user = user::Entity::find_by_id(claims.sub)
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .one(db)
+        .await?
+        .filter(|user| claims.holds_for(user))
+        .ok_or(Error::Authenticate(AuthenticateError::InvalidToken))?;
+
+    let user = user::ActiveModel {
+        id: Set(user.id),
+        email_verified_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+    Ok(user.update(db).await?)
+}
+
+/// Sends a new verification email to an unverified user of the client.
+/// Unknown and already verified emails are ignored so the response does not tell which emails are registered.
+pub async fn resend_verification_email(db: &DatabaseConnection, mailer: &dyn Mailer, client: &client::Model, email: &str) -> Result<(), Error> {
+    let user = user::Entity::find()
+        .filter(user::Column::Email.eq(email))
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .filter(user::Column::EmailVerifiedAt.is_null())
+        .inner_join(resource_group::Entity)
+        .filter(resource_group::Column::ClientId.eq(client.id))
+        .one(db)
+        .await?;
+
+    match user {
+        Some(user) => send_verification_email(db, mailer, client, &user).await,
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,200 @@
+use chrono::{TimeZone, Utc};
+use entity::{client, realm, used_webauthn_challenge, user, webauthn_credential};
+use sea_orm::{prelude::Uuid, sea_query::OnConflict, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set};
+use tracing::{debug, warn};
+
+use crate::{
+    mappers::auth::RegisterPasskeyRequest,
+    packages::{
+        errors::{AuthenticateError, Error},
+        jwt_token::JwtUser,
+        webauthn::{self, AssertionCredential, Ceremony, ChallengeClaims, CreationOptions, RequestOptions},
+    },
+};
+
+/// Starts registering a passkey for the signed in user, which can then sign in to the realm's clients without a password.
+pub async fn start_registration(db: &DatabaseConnection, client: &client::Model, jwt_user: &JwtUser) -> Result<CreationOptions, Error> {
+    let user = find_own_user(db, client, jwt_user).await?;
+    let credential_ids = find_credential_ids(db, user.id).await?;
+
+    let challenge = new_challenge(db, Ceremony::Registration, client, Some(user.id)).await?;
+    let display_name = match &user.last_name {
+        Some(last_name) => format!("{} {}", user.first_name, last_name),
+        None => user.first_name.clone(),
+    };
+    Ok(CreationOptions::new(challenge, user.id, &user.email, &display_name, credential_ids))
+}
+
+/// Stores the passkey the authenticator created in response to the registration options.
+pub async fn finish_registration(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    jwt_user: &JwtUser,
+    payload: RegisterPasskeyRequest,
+) -> Result<webauthn_credential::Model, Error> {
+    let user = find_own_user(db, client, jwt_user).await?;
+    let new_credential =
+        webauthn::verify_registration(&payload.credential, &webauthn::allowed_origins(client)).map_err(|e| Error::cannot_perform_operation(&e))?;
+    let challenge = webauthn::decode_challenge(db, &new_credential.challenge, client, Ceremony::Registration)
+        .await
+        .map_err(|e| Error::cannot_perform_operation(&e))?;
+    if challenge.sub != Some(user.id) {
+        return Err(Error::cannot_perform_operation("The ceremony was started for another user"));
+    }
+    if !use_challenge(db, &challenge).await? {
+        return Err(Error::cannot_perform_operation("The challenge was already used"));
+    }
+
+    let registered = webauthn_credential::Entity::find()
+        .filter(webauthn_credential::Column::CredentialId.eq(&new_credential.credential_id))
+        .one(db)
+        .await?;
+    if registered.is_some() {
+        return Err(Error::cannot_perform_operation("Passkey is already registered"));
+    }
+
+    let credential = webauthn_credential::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        user_id: Set(user.id),
+        credential_id: Set(new_credential.credential_id),
+        public_key: Set(new_credential.public_key),
+        algorithm: Set(new_credential.algorithm as i32),
+        sign_count: Set(new_credential.sign_count.into()),
+        name: Set(payload.name),
+        last_used_at: Set(None),
+        created_at: Set(Utc::now().into()),
+    };
+    Ok(credential.insert(db).await?)
+}
+
+/// Starts signing in with a passkey, without knowing who is signing in yet.
+pub async fn start_authentication(db: &DatabaseConnection, client: &client::Model) -> Result<RequestOptions, Error> {
+    let challenge = new_challenge(db, Ceremony::Authentication, client, None).await?;
+    Ok(RequestOptions::new(challenge, vec![]))
+}
+
+/// Starts proving a second factor with one of the user's passkeys, `None` when they have none.
+pub async fn start_second_factor_authentication(
+    db: &DatabaseConnection,
+    client: &client::Model,
+    user: &user::Model,
+) -> Result<Option<RequestOptions>, Error> {
+    let credential_ids = find_credential_ids(db, user.id).await?;
+    if credential_ids.is_empty() {
+        return Ok(None);
+    }
+
+    let challenge = new_challenge(db, Ceremony::Authentication, client, Some(user.id)).await?;
+    Ok(Some(RequestOptions::new(challenge, credential_ids)))
+}
+
+/// Verifies an assertion of one of the realm's passkeys and returns the user it belongs to.
+pub async fn finish_authentication(db: &DatabaseConnection, client: &client::Model, assertion: &AssertionCredential) -> Result<user::Model, Error> {
+    let invalid_passkey = || Error::Authenticate(AuthenticateError::InvalidPasskey);
+    let (credential, user) = webauthn_credential::Entity::find()
+        .filter(webauthn_credential::Column::CredentialId.eq(&assertion.id))
+        .find_also_related(user::Entity)
+        .one(db)
+        .await?
+        .and_then(|(credential, user)| user.map(|user| (credential, user)))
+        .filter(|(_, user)| user.realm_id == client.realm_id)
+        .ok_or_else(invalid_passkey)?;
+    if assertion
+        .response
+        .user_handle
+        .as_ref()
+        .is_some_and(|user_handle| *user_handle != webauthn::user_handle(user.id))
+    {
+        debug!("User handle does not match the passkey");
+        return Err(invalid_passkey());
+    }
+
+    let verified = webauthn::verify_assertion(assertion, &webauthn::allowed_origins(client), &credential.public_key).map_err(|e| {
+        debug!("Invalid passkey assertion: {}", e);
+        invalid_passkey()
+    })?;
+    let challenge = webauthn::decode_challenge(db, &verified.challenge, client, Ceremony::Authentication)
+        .await
+        .map_err(|e| {
+            debug!("Invalid passkey assertion: {}", e);
+            invalid_passkey()
+        })?;
+    // Ceremonies started for a second factor can only be completed by that user's passkeys
+    if challenge.sub.is_some_and(|user_id| user_id != user.id) {
+        debug!("The ceremony was started for another user");
+        return Err(invalid_passkey());
+    }
+    if !use_challenge(db, &challenge).await? {
+        debug!("The challenge was already used");
+        return Err(invalid_passkey());
+    }
+    // Authenticators counting signatures never go back, unless the passkey was cloned
+    let sign_count = i64::from(verified.sign_count);
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        warn!("Signature counter of passkey {} went back, it may have been cloned", credential.id);
+        return Err(invalid_passkey());
+    }
+
+    let credential = webauthn_credential::ActiveModel {
+        id: Set(credential.id),
+        sign_count: Set(sign_count),
+        last_used_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+    credential.update(db).await?;
+    Ok(user)
+}
+
+async fn new_challenge(db: &DatabaseConnection, ceremony: Ceremony, client: &client::Model, user_id: Option<Uuid>) -> Result<String, Error> {
+    let realm = realm::Entity::find_by_id(client.realm_id).one(db).await?.ok_or_else(Error::not_found)?;
+    ChallengeClaims::new(ceremony, client, user_id)
+        .create_challenge(&realm)
+        .map_err(|e| Error::SigningKey(e.to_string()))
+}
+
+// Records the challenge as used until it expires, `false` when it already was. Only verified responses get here,
+// so the records are bounded by the ceremonies users completed rather than the ones anyone started
+async fn use_challenge(db: &DatabaseConnection, challenge: &ChallengeClaims) -> Result<bool, Error> {
+    let now = Utc::now();
+    used_webauthn_challenge::Entity::delete_many()
+        .filter(used_webauthn_challenge::Column::ExpiresAt.lt(now))
+        .exec(db)
+        .await?;
+
+    let expires_at = Utc
+        .timestamp_opt(challenge.exp as i64, 0)
+        .single()
+        .ok_or_else(|| Error::cannot_perform_operation("Invalid challenge"))?;
+    let used_challenge = used_webauthn_challenge::ActiveModel {
+        id: Set(challenge.jti),
+        client_id: Set(challenge.aud),
+        expires_at: Set(expires_at.into()),
+        created_at: Set(now.into()),
+    };
+    let inserted = used_webauthn_challenge::Entity::insert(used_challenge)
+        .on_conflict(OnConflict::column(used_webauthn_challenge::Column::Id).do_nothing().to_owned())
+        .exec_without_returning(db)
+        .await?;
+    Ok(inserted == 1)
+}
+
+// Admins impersonating the user cannot change how they sign in
+async fn find_own_user(db: &DatabaseConnection, client: &client::Model, jwt_user: &JwtUser) -> Result<user::Model, Error> {
+    if jwt_user.act.is_some() {
+        return Err(Error::Authenticate(AuthenticateError::ActionForbidden));
+    }
+    user::Entity::find_by_id(jwt_user.sub)
+        .filter(user::Column::RealmId.eq(client.realm_id))
+        .one(db)
+        .await?
+        .ok_or_else(Error::not_found)
+}
+
+async fn find_credential_ids(db: &DatabaseConnection, user_id: Uuid) -> Result<Vec<String>, Error> {
+    let credentials = webauthn_credential::Entity::find()
+        .filter(webauthn_credential::Column::UserId.eq(user_id))
+        .order_by_asc(webauthn_credential::Column::CreatedAt)
+        .all(db)
+        .await?;
+    Ok(credentials.into_iter().map(|credential| credential.credential_id).collect())
+}
@@ -0,0 +1,21 @@
+use crate::packages::dpop::{self, DpopKey};
+use crate::packages::errors::Error;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DpopKey
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(proof) = dpop::proof(&parts.headers)? else {
+            return Ok(DpopKey(None));
+        };
+
+        let jkt = dpop::verify(proof, parts.method.as_str(), &dpop::request_uri(parts), None)?;
+        Ok(DpopKey(Some(jkt)))
+    }
+}
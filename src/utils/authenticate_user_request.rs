@@ -1,15 +1,22 @@
+use std::sync::Arc;
+
+use crate::packages::db::AppState;
+use crate::packages::dpop;
 use crate::packages::errors::AuthenticateError;
 use crate::packages::errors::Error;
 use crate::packages::jwt_token;
 use crate::packages::jwt_token::JwtUser;
-use crate::packages::settings::SETTINGS;
-
-use axum::{async_trait, extract::FromRequestParts, http::request::Parts, RequestPartsExt};
+use crate::packages::session_cache;
+use crate::packages::signing_key;
+use crate::utils::default_resource_checker::is_default_realm;
+use crate::utils::role_checker::{is_current_realm_admin, is_master_realm_admin};
 
-use axum_extra::{
-    headers::{authorization::Bearer, Authorization},
-    TypedHeader,
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, RawPathParams},
+    http::{header::AUTHORIZATION, request::Parts},
 };
+use sea_orm::prelude::Uuid;
 
 #[async_trait]
 impl<S> FromRequestParts<S> for JwtUser
@@ -18,15 +25,74 @@ where
 {
     type Rejection = Error;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let TypedHeader(Authorization(bearer)) = parts
-            .extract::<TypedHeader<Authorization<Bearer>>>()
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let path_params = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AuthenticateError::InvalidToken)?;
+        let path_param = |name: &str| {
+            path_params
+                .iter()
+                .find(|(key, _)| *key == name)
+                .and_then(|(_, value)| value.parse::<Uuid>().ok())
+        };
+        let route_realm_id = path_param("realm_id");
+        let route_client_id = path_param("client_id");
+
+        let (scheme, token) = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .ok_or(AuthenticateError::InvalidToken)?;
+
+        let state = parts.extensions.get::<Arc<AppState>>().expect("AppState not found");
+        // Tokens are verified with the keys of the route's realm. Only the master realm's own tokens are taken on the routes of
+        // other realms, or on routes of no realm, as its admins manage every realm
+        let realm_id = signing_key::peek_realm_id(token).map_err(|_| AuthenticateError::InvalidToken)?;
+        let is_foreign_realm = route_realm_id != Some(realm_id);
+        if is_foreign_realm && !is_default_realm(realm_id) {
+            return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+        }
+        let token_data = jwt_token::decode(&state.db, token, realm_id, None)
             .await
             .map_err(|_| AuthenticateError::InvalidToken)?;
 
-        let secret = &SETTINGS.read().secrets.signing_key;
-        let token_data = jwt_token::decode(bearer.token(), secret).map_err(|_| AuthenticateError::InvalidToken)?;
+        // Bound tokens are only accepted along with a proof of possession of their key, see RFC 9449 section 7
+        let is_presented_properly = match &token_data.claims.cnf {
+            Some(cnf) => {
+                let proof = dpop::proof(&parts.headers).map_err(|_| AuthenticateError::InvalidToken)?;
+                let htu = dpop::request_uri(parts);
+                scheme.eq_ignore_ascii_case("DPoP") && dpop::proves_possession(cnf, token, proof, Some(parts.method.as_str()), Some(&htu))
+            }
+            None => scheme.eq_ignore_ascii_case("Bearer"),
+        };
+        if !is_presented_properly {
+            return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+        }
+
+        if !session_cache::is_active(&state.db, &token_data.claims).await? {
+            return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+        }
+
+        let audience = token_data.claims.aud.clone();
+        let user = JwtUser::from_claim(token_data.claims);
+        if is_foreign_realm && !is_master_realm_admin(&user) {
+            return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+        }
+        // The routes of a client only take tokens aimed at that client. Admins manage every client of their realm with
+        // the tokens of the client their admin resources belong to
+        if let Some(client_id) = route_client_id {
+            let is_admin =
+                is_master_realm_admin(&user) || route_realm_id.is_some_and(|realm_id| is_current_realm_admin(&user, &realm_id.to_string()));
+            let expected = match &user.resource {
+                Some(resource) if is_admin => resource.client_id,
+                _ => client_id,
+            };
+            if !audience.contains(&expected.to_string()) {
+                return Err(Error::Authenticate(AuthenticateError::InvalidToken));
+            }
+        }
 
-        Ok(JwtUser::from_claim(token_data.claims))
+        Ok(user)
     }
 }
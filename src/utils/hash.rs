@@ -1,13 +1,164 @@
-use crate::packages::errors::Error;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher as _, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use bcrypt::HashParts;
 use tokio::task;
 
+use crate::packages::{
+    errors::Error,
+    settings::{HashAlgorithm, SETTINGS},
+};
+
+const ARGON2_SALT_LENGTH: usize = 16;
+
+/// Algorithm and parameters a password hash was made with, read from the prefix of the hash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordHasher {
+    Argon2id { memory_cost: u32, time_cost: u32, parallelism: u32 },
+    Bcrypt { cost: u32 },
+}
+
+impl PasswordHasher {
+    /// The hasher new passwords are hashed with.
+    pub fn from_settings() -> Self {
+        let settings = &SETTINGS.read().password_hashing;
+        match settings.algorithm {
+            HashAlgorithm::Argon2id => Self::Argon2id {
+                memory_cost: settings.argon2id.memory_cost,
+                time_cost: settings.argon2id.time_cost,
+                parallelism: settings.argon2id.parallelism,
+            },
+            HashAlgorithm::Bcrypt => Self::Bcrypt { cost: settings.bcrypt.cost },
+        }
+    }
+
+    pub fn identify(hash: &str) -> Option<Self> {
+        if hash.starts_with("$argon2id$") {
+            let hash = PasswordHash::new(hash).ok()?;
+            let params = Params::try_from(&hash).ok()?;
+            return Some(Self::Argon2id {
+                memory_cost: params.m_cost(),
+                time_cost: params.t_cost(),
+                parallelism: params.p_cost(),
+            });
+        }
+        hash.parse::<HashParts>().ok().map(|parts| Self::Bcrypt { cost: parts.get_cost() })
+    }
+
+    fn hash(&self, password: &str) -> Result<String, Error> {
+        match *self {
+            Self::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => {
+                let params = Params::new(memory_cost, time_cost, parallelism, None).map_err(|e| Error::HashPassword(e.to_string()))?;
+                let salt: [u8; ARGON2_SALT_LENGTH] = rand::random();
+                let salt = SaltString::encode_b64(&salt).map_err(|e| Error::HashPassword(e.to_string()))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|e| Error::HashPassword(e.to_string()))
+            }
+            Self::Bcrypt { cost } => bcrypt::hash(password, cost).map_err(|e| Error::HashPassword(e.to_string())),
+        }
+    }
+
+    // Only hashes made with a different algorithm, or with less memory or iterations, are upgraded
+    fn is_weaker_than(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Argon2id { memory_cost, time_cost, .. },
+                Self::Argon2id {
+                    memory_cost: other_memory_cost,
+                    time_cost: other_time_cost,
+                    ..
+                },
+            ) => memory_cost < other_memory_cost || time_cost < other_time_cost,
+            (Self::Bcrypt { cost }, Self::Bcrypt { cost: other }) => cost < other,
+            _ => true,
+        }
+    }
+}
+
+/// Hashes a new password with the algorithm and parameters of the settings.
 pub async fn generate_password_hash<P>(password: P) -> Result<String, Error>
 where
     P: AsRef<str> + Send + 'static,
 {
-    let cost = 4;
-    task::spawn_blocking(move || bcrypt::hash(password.as_ref(), cost))
+    let hasher = PasswordHasher::from_settings();
+    task::spawn_blocking(move || hasher.hash(password.as_ref()))
         .await
         .map_err(Error::RunSyncTask)?
-        .map_err(Error::HashPassword)
+}
+
+/// Runs a password check, like `user::Model::verify_password`, off the async runtime as hashes are slow to compute on purpose.
+pub async fn verify_password<F>(check: F) -> Result<bool, Error>
+where
+    F: FnOnce() -> bool + Send + 'static,
+{
+    task::spawn_blocking(check).await.map_err(Error::RunSyncTask)
+}
+
+/// Whether the hash should be replaced with one made by the hasher of the settings, the next time the password is known.
+pub fn needs_rehash(hash: &str) -> bool {
+    PasswordHasher::identify(hash).is_some_and(|hasher| hasher.is_weaker_than(&PasswordHasher::from_settings()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordHasher;
+
+    const CHEAP_ARGON2ID: PasswordHasher = PasswordHasher::Argon2id {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn identifies_the_hasher_of_a_hash() {
+        let hash = CHEAP_ARGON2ID.hash("password").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+        assert_eq!(PasswordHasher::identify(&hash), Some(CHEAP_ARGON2ID));
+
+        let hash = PasswordHasher::Bcrypt { cost: 4 }.hash("password").unwrap();
+        assert_eq!(PasswordHasher::identify(&hash), Some(PasswordHasher::Bcrypt { cost: 4 }));
+
+        assert_eq!(PasswordHasher::identify("password"), None);
+    }
+
+    #[test]
+    fn salts_every_hash() {
+        assert_ne!(CHEAP_ARGON2ID.hash("password").unwrap(), CHEAP_ARGON2ID.hash("password").unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_argon2id_parameters() {
+        let hasher = PasswordHasher::Argon2id {
+            memory_cost: 1,
+            time_cost: 0,
+            parallelism: 1,
+        };
+        assert!(hasher.hash("password").is_err());
+    }
+
+    #[test]
+    fn upgrades_less_memory_or_iterations_and_other_algorithms() {
+        let stronger = |memory_cost, time_cost| PasswordHasher::Argon2id {
+            memory_cost,
+            time_cost,
+            parallelism: 1,
+        };
+        assert!(CHEAP_ARGON2ID.is_weaker_than(&stronger(128, 1)));
+        assert!(CHEAP_ARGON2ID.is_weaker_than(&stronger(64, 2)));
+        assert!(!CHEAP_ARGON2ID.is_weaker_than(&CHEAP_ARGON2ID));
+        assert!(!stronger(128, 2).is_weaker_than(&CHEAP_ARGON2ID));
+
+        let bcrypt = PasswordHasher::Bcrypt { cost: 10 };
+        assert!(bcrypt.is_weaker_than(&PasswordHasher::Bcrypt { cost: 12 }));
+        assert!(!bcrypt.is_weaker_than(&PasswordHasher::Bcrypt { cost: 10 }));
+        assert!(bcrypt.is_weaker_than(&CHEAP_ARGON2ID));
+        assert!(CHEAP_ARGON2ID.is_weaker_than(&bcrypt));
+    }
 }
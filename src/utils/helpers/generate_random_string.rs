@@ -1,18 +1,18 @@
-use base64::{engine::general_purpose::STANDARD, Engine};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use rand::RngCore;
 
 pub enum Length {
-    // U32,
+    U32,
     U64,
 }
 
 pub fn generate_random_string(length: Length) -> String {
     let length = match length {
-        // Length::U32 => 32,
+        Length::U32 => 32,
         Length::U64 => 64,
     };
 
     let mut bytes = vec![0u8; length];
     rand::thread_rng().fill_bytes(&mut bytes);
-    STANDARD.encode(&bytes)
+    URL_SAFE_NO_PAD.encode(&bytes)
 }
@@ -1,4 +1,5 @@
 pub mod authenticate_api_request;
+pub mod authenticate_dpop_request;
 pub mod authenticate_user_request;
 pub mod default_resource_checker;
 pub mod hash;